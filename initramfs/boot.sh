@@ -0,0 +1,2 @@
+# Run by the shell when it starts. Add commands here to run them at every boot, e.g.
+# `source tests.sh`.
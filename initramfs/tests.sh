@@ -0,0 +1,22 @@
+# Smoke test of the shell commands: `source tests.sh`
+result=passed
+for cmd in uptime ptable meminfo slabinfo drivers irqs uartstat dmesg; do
+    if $cmd > tests.out; then
+        echo ok $cmd
+    else
+        echo FAIL $cmd, exit status $?
+        result=failed
+    fi
+done
+rm tests.out
+
+if echo one two three | grep two > tests.out && cat tests.out | grep three > tests2.out; then
+    echo ok pipes
+else
+    echo FAIL pipes
+    result=failed
+fi
+rm tests.out
+rm tests2.out
+
+echo tests $result
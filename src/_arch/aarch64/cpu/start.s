@@ -3,6 +3,9 @@
 .globl _start
 .type _start, function
 _start:
+    // x0 holds the device tree address on the boot core, keep it for the kernel.
+    mov     x19, x0
+
     mrs     x0, s3_1_c15_c2_1
     orr     x0, x0, #0x40
     msr     s3_1_c15_c2_1, x0
@@ -23,50 +26,54 @@ _start:
     cmp     x5, #3
     beq     core3_stack
 
+    // The stacks, and the register values in .rodata below, can lie further from here than the
+    // 1MB `adr` reaches, e.g. once the heap statistics grow .bss. The kernel runs where it is
+    // linked, so literal-pool loads of their absolute addresses work just as well.
 core0_stack:
-    adr     x1, __stack_end_core0__
+    ldr     x1, =__stack_end_core0__
     b       set_stack
 core1_stack:
-    adr     x1, __stack_end_core1__
+    ldr     x1, =__stack_end_core1__
     b       set_stack
 core2_stack:
-    adr     x1, __stack_end_core2__
+    ldr     x1, =__stack_end_core2__
     b       set_stack
 core3_stack:
-    adr     x1, __stack_end_core3__
+    ldr     x1, =__stack_end_core3__
     b       set_stack
 
 set_stack:
     mov     sp, x1
     msr     sp_el1, x1
 
-    adr     x0, SCTLR_INIT_VAL
+    ldr     x0, =SCTLR_INIT_VAL
     ldr     x0, [x0]
     msr     sctlr_el1, x0
     
-    adr     x0, HCR_INIT_VAL
+    ldr     x0, =HCR_INIT_VAL
     ldr     x0, [x0]
     msr     hcr_el2, x0
 
-    adr     x0, CPACR_EL1_INIT_VAL
+    ldr     x0, =CPACR_EL1_INIT_VAL
     ldr     x0, [x0]
     msr     cpacr_el1, x0
 
-    adr     x0, CNTHCTL_EL2_INIT_VAL
+    ldr     x0, =CNTHCTL_EL2_INIT_VAL
     ldr     x0, [x0]
     msr     cnthctl_el2, x0
 
-    adr     x0, SCR_INIT_VAL
+    ldr     x0, =SCR_INIT_VAL
     ldr     x0, [x0]
     msr     scr_el3, x0
 
-    adr     x0, SPSR_EL3_INIT_VAL
+    ldr     x0, =SPSR_EL3_INIT_VAL
     ldr     x0, [x0]
     msr     spsr_el3, x0
 
     adr     x0, _el1_rust_entry
     msr     elr_el3, x0
 
+    mov     x0, x19
     eret
 
 .balign 4
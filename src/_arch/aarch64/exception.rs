@@ -2,7 +2,7 @@ use aarch64_cpu::registers::{ESR_EL1, SPSR_EL1};
 use tock_registers::registers::InMemoryRegister;
 use tock_registers::interfaces::{Readable, Writeable};
 
-use crate::{info, warn};
+use crate::{error, info};
 
 #[repr(transparent)]
 struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
@@ -22,6 +22,11 @@ pub unsafe fn handle_irq() {
     let core = crate::utils::get_core();
     let core_irq_source = crate::bsp::QA7_REGS.get_incoming_irqs(core);
 
+    // Peripheral interrupts are routed to core 0 only.
+    if core_irq_source & (1 << 8) != 0 {
+        crate::bsp::INTERRUPT_CONTROLLER.handle_pending_irqs();
+    }
+
     if core_irq_source & 0b10 != 0 {
         let freq = aarch64_cpu::registers::CNTFRQ_EL0.get();
         aarch64_cpu::registers::CNTP_TVAL_EL0.set(freq / 10000);
@@ -31,6 +36,8 @@ pub unsafe fn handle_irq() {
 
 #[panic_handler]
 pub unsafe fn panic(panic_info: &core::panic::PanicInfo) -> ! {
-    warn!(" ~ UwU we panic now ~\n{:?}", panic_info);
+    crate::console::enter_panic_mode();
+    error!(" ~ UwU we panic now ~\n{:?}", panic_info);
+    crate::console::console().flush();
     loop {}
 }
\ No newline at end of file
@@ -1,11 +1,12 @@
 use aarch64_cpu::registers::{TCR_EL1, MAIR_EL1, TTBR0_EL1, SCTLR_EL1};
 use tock_registers::interfaces::{Writeable, ReadWriteable};
 
-use super::translation_table::TranslationTable;
+use super::{translation_table::TranslationTable, AttributeFields};
 
 pub mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL_WB_NT_RW: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 4;
 }
 
 #[no_mangle]
@@ -15,6 +16,28 @@ pub fn map_translation_table() {
     unsafe { TRANSLATION_TABLE.populate_tables() }
 }
 
+/// Identity maps `[start, start + size)` after boot. Callers must not race each other, the
+/// other cores only ever see the new pages appear.
+pub unsafe fn map_identity(start: usize, size: usize, attributes: &AttributeFields) -> Result<(), &'static str> {
+    let table = &mut *core::ptr::addr_of_mut!(TRANSLATION_TABLE);
+    table.map_identity(start, start + size, attributes)?;
+
+    // The descriptors have to reach memory before any core walks the table again.
+    aarch64_cpu::asm::barrier::dsb(aarch64_cpu::asm::barrier::ISHST);
+    core::arch::asm!("tlbi vmalle1is");
+    aarch64_cpu::asm::barrier::dsb(aarch64_cpu::asm::barrier::ISH);
+    aarch64_cpu::asm::barrier::isb(aarch64_cpu::asm::barrier::SY);
+    Ok(())
+}
+
+/// Panics if any page is mapped both writable and executable.
+pub fn check_wx_policy() {
+    let table = unsafe { &*core::ptr::addr_of!(TRANSLATION_TABLE) };
+    if let Some(addr) = table.find_writable_executable() {
+        panic!("W^X violation: page at 0x{:X} is writable and executable", addr);
+    }
+}
+
 pub fn enable_mmu_and_caching() {
 
     MAIR_EL1.write(
@@ -68,3 +91,18 @@ pub fn enable_mmu_and_caching() {
 
 }
 
+
+/// Cleans and invalidates the data cache lines covering `[start, start + size)` to the point of
+/// coherency, for memory shared with a non-coherent agent such as the VideoCore.
+pub fn clean_invalidate_dcache_range(start: usize, size: usize) {
+    const CACHE_LINE_SIZE: usize = 64;
+
+    let mut addr = start & !(CACHE_LINE_SIZE - 1);
+    while addr < start + size {
+        unsafe {
+            core::arch::asm!("dc civac, {}", in(reg) addr);
+        }
+        addr += CACHE_LINE_SIZE;
+    }
+    aarch64_cpu::asm::barrier::dsb(aarch64_cpu::asm::barrier::SY);
+}
@@ -89,6 +89,11 @@ impl convert::From<AttributeFields>
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx
                         .val(crate::memory::mmu::arch_mmu::mair::NORMAL_WB_NT_RW)
             }
+            crate::memory::mmu::MemoryAttributes::NonCacheableDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx
+                        .val(crate::memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE)
+            }
             crate::memory::mmu::MemoryAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx
@@ -96,6 +101,11 @@ impl convert::From<AttributeFields>
             }
         };
 
+        desc += match value.permissions {
+            crate::memory::mmu::AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
+            crate::memory::mmu::AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+        };
+
         desc += if value.execute_never {
             STAGE1_PAGE_DESCRIPTOR::PXN::True
         } else {
@@ -132,6 +142,20 @@ impl PageDescriptor {
 
         Self { value: val.get() }
     }
+
+    /// True for a valid page that EL1 or EL0 could both write to and execute from.
+    fn is_writable_executable(&self) -> bool {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+
+        let valid = val.is_set(STAGE1_PAGE_DESCRIPTOR::VALID);
+        let writable = matches!(
+            val.read_as_enum(STAGE1_PAGE_DESCRIPTOR::AP),
+            Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1) | Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1_EL0)
+        );
+        let executable = !val.is_set(STAGE1_PAGE_DESCRIPTOR::PXN) || !val.is_set(STAGE1_PAGE_DESCRIPTOR::UXN);
+
+        valid && writable && executable
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -203,6 +227,35 @@ impl<const NUM_TABLES: usize> TranslationTable<NUM_TABLES> {
         }
     }
 
+    /// Maps the pages covering `[start, end)` onto themselves, for memory that only turns up
+    /// after boot.
+    pub fn map_identity(&mut self, start: usize, end: usize, attribute_fields: &AttributeFields) -> Result<(), &'static str> {
+        if end > NUM_TABLES << Granule512MiB::SHIFT {
+            return Err("range outside the translation table");
+        }
+
+        let mut addr = start & !(Granule64KiB::SIZE - 1);
+        while addr < end {
+            let level2_num = addr >> Granule512MiB::SHIFT;
+            let level3_num = (addr & (Granule512MiB::SIZE - 1)) >> Granule64KiB::SHIFT;
+            self.lower_level3[level2_num][level3_num] = PageDescriptor::from_output_addr(addr, attribute_fields);
+            addr += Granule64KiB::SIZE;
+        }
+        Ok(())
+    }
+
+    /// Returns the virtual address of the first page mapped both writable and executable.
+    pub fn find_writable_executable(&self) -> Option<usize> {
+        for (level2_num, level3_table) in self.lower_level3.iter().enumerate() {
+            for (level3_num, level3_entry) in level3_table.iter().enumerate() {
+                if level3_entry.is_writable_executable() {
+                    return Some((level2_num << Granule512MiB::SHIFT) + (level3_num << Granule64KiB::SHIFT));
+                }
+            }
+        }
+        None
+    }
+
     pub fn phys_base_address(&self) -> u64 {
         &self.lower_level2 as *const [TableDescriptor; NUM_TABLES] as u64
     }
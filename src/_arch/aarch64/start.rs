@@ -39,7 +39,7 @@ pub fn _hang() -> ! {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn _el1_rust_entry() -> ! {
+pub unsafe extern "C" fn _el1_rust_entry(dtb_addr: usize) -> ! {
     irq_init_vectors();
 
     if get_core() != 0 {
@@ -50,5 +50,5 @@ pub unsafe extern "C" fn _el1_rust_entry() -> ! {
     let bss_length = bss_end.get() as usize - bss_start;
     memzero(bss_start, bss_length);
     
-    crate::kernel_main()
+    crate::kernel_main(dtb_addr)
 }
\ No newline at end of file
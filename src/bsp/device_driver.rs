@@ -3,6 +3,8 @@
 mod bcm;
 mod common;
 
+pub use common::{LineSettings, SpiBus, SpiMode, SpiSettings};
+
 
 #[cfg(feature = "bsp_rpi3")]
 pub use bcm::*;
\ No newline at end of file
@@ -1,10 +1,26 @@
+mod bcm2837_aux;
+mod bcm2xxx_dma;
+mod bcm2xxx_framebuffer;
 mod bcm2xxx_gpio;
+mod bcm2xxx_i2c;
+mod bcm2xxx_interrupt_controller;
+mod bcm2xxx_mailbox;
 mod bcm2837_mini_uart;
+mod bcm2xxx_pl011_uart;
 mod bcm2xxx_qa7;
+mod bcm2xxx_spi0;
 mod bcm2xxx_systimer;
 mod bcm2837_spi;
 
+pub use bcm2xxx_dma::*;
+pub use bcm2xxx_framebuffer::*;
 pub use bcm2xxx_gpio::*;
+pub use bcm2xxx_i2c::*;
+pub use bcm2xxx_interrupt_controller::*;
+pub use bcm2xxx_mailbox::*;
 pub use bcm2837_mini_uart::*;
+pub use bcm2xxx_pl011_uart::*;
 pub use bcm2xxx_qa7::*;
+pub use bcm2xxx_spi0::*;
 pub use bcm2xxx_systimer::*;
+pub use bcm2837_spi::*;
@@ -0,0 +1,55 @@
+//! Registers shared by the auxiliary peripherals: the mini UART and the SPI1/SPI2 masters.
+
+use tock_registers::{
+    fields::FieldValue,
+    interfaces::ReadWriteable,
+    register_bitfields,
+    registers::ReadWrite,
+};
+
+use crate::{exception, synchronization::{interface::Mutex, SpinLock}};
+
+register_bitfields! {
+    u32,
+    pub AUXIRQ [
+        MiniUART OFFSET(0) NUMBITS(1) [
+            NoInterruptPending = 0,
+            InterruptPending = 1,
+        ],
+        SPI1 OFFSET(1) NUMBITS(1) [
+            NoInterruptPending = 0,
+            InterruptPending = 1,
+        ],
+        SPI2 OFFSET(2) NUMBITS(1) [
+            NoInterruptPending = 0,
+            InterruptPending = 1,
+        ],
+    ],
+
+    pub AUXENB [
+        MiniUART OFFSET(0) NUMBITS(1) [
+            Enabled = 1,
+            Disabled = 0,
+        ],
+        SPI1 OFFSET(1) NUMBITS(1) [
+            Enabled = 1,
+            Disabled = 0,
+        ],
+        SPI2 OFFSET(2) NUMBITS(1) [
+            Enabled = 1,
+            Disabled = 0,
+        ]
+    ],
+}
+
+/// Serializes the drivers' read-modify-write of the shared enable register.
+static AUXENB_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// Changes one peripheral's enable bit without touching the others'.
+pub fn modify_enables(auxenb: &ReadWrite<u32, AUXENB::Register>, value: FieldValue<u32, AUXENB::Register>) {
+    let daif = exception::irq_save();
+    let _guard = AUXENB_LOCK.lock().unwrap();
+    auxenb.modify(value);
+    drop(_guard);
+    exception::irq_restore(daif);
+}
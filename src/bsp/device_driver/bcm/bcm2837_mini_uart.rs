@@ -1,57 +1,45 @@
-use core::fmt::{self, Write};
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
     registers::{ReadOnly, ReadWrite},
 };
 
-use crate::{console, synchronization::FakeLock};
-use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::driver;
+use super::bcm2837_aux::{self, AUXENB, AUXIRQ};
+use crate::bsp::device_driver::common::{
+    BufferedUart, LineSettings, MMIODerefWrapper, Parity, UartRegisters, UartStats,
+};
 
 register_bitfields! {
     u32,
-    AUXIRQ [
-        MiniUART OFFSET(0) NUMBITS(1) [
-            NoInterruptPending = 0,
-            InterruptPending = 1,
-        ],
-        SPI1 OFFSET(1) NUMBITS(1) [
-            NoInterruptPending = 0,
-            InterruptPending = 1,
-        ],
-        SPI2 OFFSET(2) NUMBITS(1) [
-            NoInterruptPending = 0,
-            InterruptPending = 1,
-        ],
-    ],
-
-    AUXENB [
-        MiniUART OFFSET(0) NUMBITS(1) [
-            Enabled = 1,
-            Disabled = 0,
-        ],
-        SPI1 OFFSET(1) NUMBITS(1) [
-            Enabled = 1,
-            Disabled = 0,
-        ],
-        SPI2 OFFSET(2) NUMBITS(1) [
-            Enabled = 1,
-            Disabled = 0,
-        ]
-    ],
-
     MU_IO [
         BYTE OFFSET(0) NUMBITS(8),
     ],
 
+    // The datasheet has the two enable bits swapped, see the BCM2835 errata. Bits 3:2 are
+    // documented as unused but the interrupt line is never asserted without them.
     MU_IER [
-        ENABLET OFFSET(0) NUMBITS(1),
-        ENABLER OFFSET(1) NUMBITS(1),
+        ENABLER OFFSET(0) NUMBITS(1) [],
+        ENABLET OFFSET(1) NUMBITS(1) [],
+        LINE OFFSET(2) NUMBITS(2) [
+            Enabled = 0b11,
+        ],
     ],
 
     MU_IIR [
-        INTPENDING OFFSET(0) NUMBITS(1),
-        INTID_FIFOCLR OFFSET(1) NUMBITS(2),
+        // Clear while an interrupt is pending.
+        INTPENDING OFFSET(0) NUMBITS(1) [],
+        INTID OFFSET(1) NUMBITS(2) [
+            None = 0b00,
+            TxEmpty = 0b01,
+            RxReady = 0b10,
+        ],
+        // Same bits as INTID, on write.
+        FIFOCLR OFFSET(1) NUMBITS(2) [
+            Rx = 0b01,
+            Tx = 0b10,
+            Both = 0b11,
+        ],
     ],
     MU_LCR [
         DATASIZE OFFSET(0) NUMBITS(2) [
@@ -119,8 +107,8 @@ register_structs! {
         (0x04 => AUXENB: ReadWrite<u32, AUXENB::Register>),
         (0x08 => _reserved1),
         (0x40 => MU_IO: ReadWrite<u32, MU_IO::Register>),
-        (0x44 => MU_IER: ReadWrite<u32>),
-        (0x48 => MU_IIR: ReadWrite<u32>),
+        (0x44 => MU_IER: ReadWrite<u32, MU_IER::Register>),
+        (0x48 => MU_IIR: ReadWrite<u32, MU_IIR::Register>),
         (0x4c => MU_LCR: ReadWrite<u32, MU_LCR::Register>),
         (0x50 => MU_MCR: ReadWrite<u32>),
         (0x54 => MU_LSR: ReadWrite<u32, MU_LSR::Register>),
@@ -135,126 +123,142 @@ register_structs! {
 
 type Registers = MMIODerefWrapper<AuxRegisters>;
 
-pub struct MiniUart {
-    inner: FakeLock<MiniUartInner>,
-}
+pub type MiniUart = BufferedUart<Registers>;
 
 impl MiniUart {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-aux-uart";
+
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
-        Self {
-            inner: FakeLock::new(MiniUartInner::new(mmio_start_addr)),
-        }
+        Self::with_registers(Registers::new(mmio_start_addr))
     }
 
-    pub fn init(&self) {
-        let mut data = self.inner.lock().unwrap();
-        data.init();
-    }
-}
+    /// Reprograms baud rate, frame format and flow control. The baud rate generator runs off the VPU core
+    /// clock, `core_clock` in Hz. The hardware only does 7 or 8 data bits without parity and
+    /// with a single stop bit.
+    pub fn configure(&self, settings: &LineSettings, core_clock: u32) -> Result<(), &'static str> {
+        if settings.data_bits != 7 && settings.data_bits != 8 {
+            return Err("mini UART only supports 7 or 8 data bits");
+        }
+        if settings.parity != Parity::None || settings.stop_bits != 1 {
+            return Err("mini UART only supports no parity and 1 stop bit");
+        }
+        let divisor = baud_divisor(settings.baud, core_clock)?;
 
-use crate::synchronization::interface::Mutex;
+        self.with_inner(|inner| {
+            // Let everything queued go out at the old rate first.
+            inner.flush();
 
-impl console::interface::Write for MiniUart {
+            let registers = &inner.registers;
+            registers.MU_CNTL.set(0);
+            registers.MU_LCR.write(match settings.data_bits {
+                7 => MU_LCR::DATASIZE::SevenBit,
+                _ => MU_LCR::DATASIZE::EightBit,
+            });
+            registers.MU_BAUD.set(divisor);
+            registers.MU_CNTL.write(control(settings));
 
-    fn write_char(&self, c: char) {
-        let mut busy = true;
-        while busy {
-            let mut data = self.inner.lock().unwrap();
-            busy = data.write_char(c).is_err();
-        }
+            inner.settings = *settings;
+        });
+        Ok(())
     }
+}
 
-    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
-        let mut data = self.inner.lock().unwrap();
-        fmt::Write::write_fmt(&mut *data, args)
+impl driver::interface::DeviceDriver for MiniUart {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
     }
 
-    fn flush(&self) {
-        //todo!()
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.with_inner(|inner| {
+            let registers = &inner.registers;
+            bcm2837_aux::modify_enables(&registers.AUXENB, AUXENB::MiniUART::Enabled);
+            registers.MU_CNTL.set(0);
+            registers.MU_IER.set(0);
+            registers.MU_LCR.write(MU_LCR::DATASIZE::EightBit);
+            registers.MU_MCR.set(0);
+            registers.MU_BAUD.set(270);
+            registers.MU_IIR.write(MU_IIR::FIFOCLR::Both);
+            registers.MU_CNTL.write(control(&inner.settings));
+        });
+        Ok(())
     }
-    
 }
 
-impl console::interface::Read for MiniUart {
-    fn read_char(&self) -> char {
-        let mut c = None;
-        while c.is_none() {
-            let data = self.inner.lock().unwrap();
-            c = data.read_char(BlockingMode::Blocking);
+impl UartRegisters for Registers {
+    const NAME: &'static str = "Mini UART";
+    const LINE_ERRORS: bool = false;
+
+    fn receive_byte(&self, stats: &mut UartStats) -> Option<u8> {
+        // Reading LSR clears the overrun flag.
+        let lsr = self.MU_LSR.extract();
+        if lsr.is_set(MU_LSR::RXOVERRUN) {
+            stats.hw_overruns += 1;
+        }
+        if !lsr.is_set(MU_LSR::DATAREADY) {
+            return None;
         }
-        c.unwrap_or('x')
+        Some(self.MU_IO.read(MU_IO::BYTE) as u8)
     }
 
-    fn clear_rx(&self) {
-        todo!()
+    fn tx_ready(&self) -> bool {
+        self.MU_LSR.is_set(MU_LSR::TXEMPTY)
     }
-}
-
-impl console::interface::ReadWrite for MiniUart {}
-
-#[derive(PartialEq)]
-enum BlockingMode {
-    Blocking,
-    NonBlocking
-}
 
-struct MiniUartInner {
-    registers: Registers
-}
+    fn put_byte(&self, b: u8) {
+        self.MU_IO.set(b as u32);
+    }
 
-impl MiniUartInner {
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
-        Self {
-            registers: Registers::new(mmio_start_addr),
-        }
+    fn tx_idle(&self) -> bool {
+        self.MU_LSR.is_set(MU_LSR::TXIDLE)
     }
 
-    pub fn init(&mut self) {
-        self.registers.AUXENB.write(AUXENB::MiniUART::Enabled);
-        self.registers.MU_CNTL.set(0);
-        self.registers.MU_IER.set(0);
-        self.registers.MU_LCR.write(MU_LCR::DATASIZE::EightBit);
-        self.registers.MU_MCR.set(0);
-        self.registers.MU_BAUD.set(270);
-        self.registers.MU_CNTL.write(MU_CNTL::RXEN::SET + MU_CNTL::TXEN::SET + MU_CNTL::RXAUTOEN::SET + MU_CNTL::RXAUTOEN::SET);
+    fn clear_rx_fifo(&self) {
+        self.MU_IIR.write(MU_IIR::FIFOCLR::Rx);
+    }
 
-        self.read_char(BlockingMode::NonBlocking);
+    fn enable_interrupts(&self) {
+        self.set_tx_interrupt(false);
     }
 
-    
+    fn set_tx_interrupt(&self, enabled: bool) {
+        self.MU_IER.write(
+            MU_IER::ENABLER::SET + MU_IER::ENABLET.val(enabled as u32) + MU_IER::LINE::Enabled,
+        );
+    }
 
-    pub fn put_char(&self, c: char) {
-        if c == '\n' {
-            self.put_char('\r');
-        }
+    fn ack_interrupt(&self) -> bool {
+        // The AUX interrupt is shared with the SPI masters, and the mini UART's own flags clear
+        // themselves as the FIFOs are serviced.
+        self.AUXIRQ.is_set(AUXIRQ::MiniUART)
+    }
+}
 
-        while !self.registers.MU_LSR.matches_all(MU_LSR::TXEMPTY::SET) {
-            aarch64_cpu::asm::nop();
-        }
-        self.registers.MU_IO.set(c as u32);
+/// Value for `MU_BAUD`: baud = core_clock / (8 * (divisor + 1)).
+fn baud_divisor(baud: u32, core_clock: u32) -> Result<u32, &'static str> {
+    if baud == 0 {
+        return Err("baud rate must not be zero");
     }
+    let (baud, core_clock) = (baud as u64, core_clock as u64);
 
-    
-    
-    pub fn read_char(&self, blocking_mode: BlockingMode) -> Option<char> {
-        if self.registers.MU_LSR.matches_all(MU_LSR::DATAREADY::CLEAR) {
-            if blocking_mode == BlockingMode::NonBlocking {
-                return None;
-            }
+    let divisor = (core_clock + 4 * baud) / (8 * baud);
+    if divisor == 0 || divisor - 1 > 0xFFFF {
+        return Err("baud rate out of range for the core clock");
+    }
 
-            while self.registers.MU_LSR.matches_all(MU_LSR::DATAREADY::CLEAR) {
-                aarch64_cpu::asm::nop();
-            }
-        }
-        Some(char::from_u32(self.registers.MU_IO.read(MU_IO::BYTE)).unwrap())
+    // Beyond a few percent the receiver at the other end won't keep up.
+    let actual = core_clock / (8 * divisor);
+    if actual.abs_diff(baud) * 100 > baud * 3 {
+        return Err("baud rate not reachable from the core clock");
     }
+    Ok(divisor as u32 - 1)
 }
 
-impl fmt::Write for MiniUartInner {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.put_char(c);
-        }
-        Ok(())
-    }
-}
\ No newline at end of file
+/// Receiver and transmitter on, with automatic RTS/CTS handling if flow control is set.
+fn control(settings: &LineSettings) -> tock_registers::fields::FieldValue<u32, MU_CNTL::Register> {
+    let flow_control = settings.flow_control as u32;
+    MU_CNTL::RXEN::SET
+        + MU_CNTL::TXEN::SET
+        + MU_CNTL::RXAUTOEN.val(flow_control)
+        + MU_CNTL::TXAUTOEN.val(flow_control)
+        + MU_CNTL::RTSAUTOLVL::ThreeEmpty
+}
@@ -1,9 +1,20 @@
+//! Auxiliary SPI masters, SPI1 and SPI2.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://elinux.org/BCM2835_datasheet_errata>
+
+use alloc::vec::Vec;
 use tock_registers::{
+    interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
-    registers::ReadWrite,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
-use crate::bsp::device_driver::common::MMIODerefWrapper;
+use super::bcm2837_aux::{self, AUXENB};
+use crate::bsp::device_driver::common::{MMIODerefWrapper, SpiBus, SpiSettings};
+use crate::{driver, exception, synchronization::{interface::Mutex, SpinLock}};
 
 register_bitfields! {
     u32,
@@ -58,27 +69,318 @@ register_bitfields! {
         CSHighTime OFFSET(8) NUMBITS(3) [],
     ],
 
-    /*AUXSPI_STAT [
+    // Bit positions as corrected by the errata, the datasheet's table is off.
+    AUXSPI_STAT [
         BitCount OFFSET(0) NUMBITS(6) [],
-        Busy OFFSET(6) NUMBITS() [],
-        RxEmpty OFFSET() NUMBITS(2) [],
-        TxEmpty OFFSET() NUMBITS() [],
-        TxFull OFFSET() NUMBITS() [],
-        RxFIFOLevel OFFSET() NUMBITS() [],
-        TxFIFOLevel OFFSET() NUMBITS() [],
-    ],*/
-    // AUXSPI_PEEK
-    // AUXSPI_IO
-    // AUXSPI_TXHOLD
+        Busy OFFSET(6) NUMBITS(1) [],
+        RxEmpty OFFSET(7) NUMBITS(1) [],
+        RxFull OFFSET(8) NUMBITS(1) [],
+        TxEmpty OFFSET(9) NUMBITS(1) [],
+        TxFull OFFSET(10) NUMBITS(1) [],
+        RxFIFOLevel OFFSET(20) NUMBITS(4) [],
+        TxFIFOLevel OFFSET(24) NUMBITS(8) [],
+    ],
 
+    // With variable width, bits 28:24 of a FIFO entry hold its length and the data starts at
+    // bit 23. Received data ends up right-aligned.
+    AUXSPI_DATA [
+        Width OFFSET(24) NUMBITS(5) [],
+        Data OFFSET(0) NUMBITS(24) [],
+    ],
 }
 
+// IO and TXHOLD are each aliased four times. Writing TXHOLD keeps the chip select asserted
+// after the entry has been shifted out, writing IO releases it.
 register_structs! {
     #[allow(non_snake_case)]
     pub SpiRegisters {
         (0x00 => AUXSPI_CNTL0: ReadWrite<u32, AUXSPI_CNTL0::Register>),
-        (0x04 => @END),
+        (0x04 => AUXSPI_CNTL1: ReadWrite<u32, AUXSPI_CNTL1::Register>),
+        (0x08 => AUXSPI_STAT: ReadOnly<u32, AUXSPI_STAT::Register>),
+        (0x0C => AUXSPI_PEEK: ReadOnly<u32, AUXSPI_DATA::Register>),
+        (0x10 => _reserved1),
+        (0x20 => AUXSPI_IO: ReadWrite<u32, AUXSPI_DATA::Register>),
+        (0x24 => _reserved2),
+        (0x30 => AUXSPI_TXHOLD: WriteOnly<u32, AUXSPI_DATA::Register>),
+        (0x34 => _reserved3),
+        (0x40 => @END),
     }
 }
 
-type Registers = MMIODerefWrapper<SpiRegisters>;
\ No newline at end of file
+// Only AUXENB is used here, the rest of the block belongs to the mini UART driver.
+register_structs! {
+    #[allow(non_snake_case)]
+    AuxEnableRegisters {
+        (0x00 => _reserved1),
+        (0x04 => AUXENB: ReadWrite<u32, AUXENB::Register>),
+        (0x08 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<SpiRegisters>;
+
+const FIFO_DEPTH: usize = 4;
+
+/// Widest entry the variable width mode can shift.
+pub const AUX_SPI_MAX_WORD_BITS: u8 = 24;
+
+/// What the firmware runs the VPU core clock at unless told otherwise.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// Polls before giving up on a FIFO entry.
+const TIMEOUT_SPINS: usize = 1_000_000;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum AuxSpiIndex {
+    Spi1,
+    Spi2,
+}
+
+pub struct AuxSpi {
+    mmio_start_addr: usize,
+    inner: SpinLock<AuxSpiInner>,
+}
+
+impl AuxSpi {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-aux-spi";
+
+    /// `aux_start_addr` is the base of the auxiliary block, for the shared enable register.
+    pub const unsafe fn new(aux_start_addr: usize, mmio_start_addr: usize, index: AuxSpiIndex) -> Self {
+        Self {
+            mmio_start_addr,
+            inner: SpinLock::new(AuxSpiInner::new(aux_start_addr, mmio_start_addr, index)),
+        }
+    }
+
+    /// Reprograms speed, mode and chip select. The clock divider runs off the VPU core clock,
+    /// `core_clock` in Hz.
+    pub fn configure(&self, settings: &SpiSettings, core_clock: u32) -> Result<(), &'static str> {
+        if settings.chip_select > 2 {
+            return Err("auxiliary SPI only has chip selects 0 to 2");
+        }
+        let divider = clock_divider(settings.speed_hz, core_clock)?;
+
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().configure(settings, divider);
+        exception::irq_restore(daif);
+        Ok(())
+    }
+
+    pub fn settings(&self) -> SpiSettings {
+        let daif = exception::irq_save();
+        let settings = self.inner.lock().unwrap().settings;
+        exception::irq_restore(daif);
+        settings
+    }
+
+    /// Powers the master up or down through `AUXENB`.
+    pub fn set_enabled(&self, enabled: bool) {
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().set_enabled(enabled);
+        exception::irq_restore(daif);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        let daif = exception::irq_save();
+        let enabled = self.inner.lock().unwrap().enabled;
+        exception::irq_restore(daif);
+        enabled
+    }
+
+    /// Full-duplex transfer of `bits` wide words, 1 to 24 bits each. Every word in `words` is
+    /// replaced by the one received while it was sent.
+    pub fn transfer_words(&self, words: &mut [u32], bits: u8) -> Result<(), &'static str> {
+        if bits == 0 || bits > AUX_SPI_MAX_WORD_BITS {
+            return Err("word width must be 1 to 24 bits");
+        }
+
+        let mask = (1 << bits) - 1;
+        let tx: Vec<u32> = words.iter().map(|word| word & mask).collect();
+
+        let daif = exception::irq_save();
+        let mut data = self.inner.lock().unwrap();
+        let result = data.exchange(tx.len(), |i| (tx[i], bits), |i, received| words[i] = received & mask);
+        drop(data);
+        exception::irq_restore(daif);
+        result
+    }
+}
+
+impl SpiBus for AuxSpi {
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        if !rx.is_empty() && rx.len() != tx.len() {
+            return Err("rx must be empty or as long as tx");
+        }
+
+        // Up to three bytes fit into one FIFO entry.
+        let chunks = tx.len().div_ceil(3);
+        let chunk = |i: usize| i * 3..tx.len().min(i * 3 + 3);
+
+        let daif = exception::irq_save();
+        let mut data = self.inner.lock().unwrap();
+        let result = data.exchange(
+            chunks,
+            |i| {
+                let bytes = &tx[chunk(i)];
+                let word = bytes.iter().fold(0, |word, &b| word << 8 | b as u32);
+                (word, bytes.len() as u8 * 8)
+            },
+            |i, received| {
+                if let Some(out) = rx.get_mut(chunk(i)) {
+                    let len = out.len();
+                    for (j, b) in out.iter_mut().enumerate() {
+                        *b = (received >> (8 * (len - 1 - j))) as u8;
+                    }
+                }
+            },
+        );
+        drop(data);
+        exception::irq_restore(daif);
+        result
+    }
+}
+
+impl driver::interface::DeviceDriver for AuxSpi {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    fn mmio_start_addr(&self) -> Option<usize> {
+        Some(self.mmio_start_addr)
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let divider = clock_divider(SpiSettings::DEFAULT.speed_hz, DEFAULT_CORE_CLOCK)?;
+        let mut data = self.inner.lock().unwrap();
+        data.configure(&SpiSettings::DEFAULT, divider);
+        data.set_enabled(true);
+        Ok(())
+    }
+}
+
+/// SCLK = core_clock / (2 * (divider + 1)), rounded towards the slower clock.
+fn clock_divider(speed_hz: u32, core_clock: u32) -> Result<u32, &'static str> {
+    if speed_hz == 0 {
+        return Err("speed must not be zero");
+    }
+
+    let divider = core_clock.div_ceil(2 * speed_hz).saturating_sub(1);
+    if divider > 0xFFF {
+        return Err("speed too low for the core clock");
+    }
+    Ok(divider)
+}
+
+struct AuxSpiInner {
+    registers: Registers,
+    aux: MMIODerefWrapper<AuxEnableRegisters>,
+    index: AuxSpiIndex,
+    settings: SpiSettings,
+    divider: u32,
+    enabled: bool,
+}
+
+impl AuxSpiInner {
+    const unsafe fn new(aux_start_addr: usize, mmio_start_addr: usize, index: AuxSpiIndex) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            aux: MMIODerefWrapper::new(aux_start_addr),
+            index,
+            settings: SpiSettings::DEFAULT,
+            divider: 0,
+            enabled: false,
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        let value = match (self.index, enabled) {
+            (AuxSpiIndex::Spi1, true) => AUXENB::SPI1::Enabled,
+            (AuxSpiIndex::Spi1, false) => AUXENB::SPI1::Disabled,
+            (AuxSpiIndex::Spi2, true) => AUXENB::SPI2::Enabled,
+            (AuxSpiIndex::Spi2, false) => AUXENB::SPI2::Disabled,
+        };
+        bcm2837_aux::modify_enables(&self.aux.AUXENB, value);
+        self.enabled = enabled;
+
+        // The registers don't keep their contents while the block is disabled.
+        if enabled {
+            self.apply();
+        }
+    }
+
+    fn configure(&mut self, settings: &SpiSettings, divider: u32) {
+        self.settings = *settings;
+        self.divider = divider;
+        self.apply();
+    }
+
+    fn apply(&mut self) {
+        // Data goes out on one edge and is sampled on the other, which edge is which follows
+        // from CPOL and CPHA.
+        let out_rising = self.settings.mode.cpol() != self.settings.mode.cpha();
+        let chip_selects = 0b111 & !(1 << self.settings.chip_select);
+
+        self.registers.AUXSPI_CNTL0.write(AUXSPI_CNTL0::ClearFIFOs::SET);
+        self.registers.AUXSPI_CNTL1.write(
+            AUXSPI_CNTL1::ShiftMSFirst::MS + AUXSPI_CNTL1::KeepInput::ClearedBeforeTxn + AUXSPI_CNTL1::CSHighTime.val(0),
+        );
+        self.registers.AUXSPI_CNTL0.write(
+            AUXSPI_CNTL0::Speed.val(self.divider)
+                + AUXSPI_CNTL0::ChipSelects.val(chip_selects)
+                + AUXSPI_CNTL0::VariableWidth::SLFromFIFO
+                + AUXSPI_CNTL0::VariableCS::CSFromRegs
+                + AUXSPI_CNTL0::DOUTHoldTime::NoExtra
+                + AUXSPI_CNTL0::InvertSPIClk.val(self.settings.mode.cpol() as u32)
+                + AUXSPI_CNTL0::OutRising.val(out_rising as u32)
+                + AUXSPI_CNTL0::InRising.val(!out_rising as u32)
+                + AUXSPI_CNTL0::ShiftMSFirst::MS
+                + AUXSPI_CNTL0::Enable::SET,
+        );
+    }
+
+    /// Shifts `count` entries, `tx(i)` giving the value and width of each, and hands every
+    /// received entry to `rx(i, value)`. The chip select stays asserted until the last one.
+    fn exchange(
+        &mut self,
+        count: usize,
+        tx: impl Fn(usize) -> (u32, u8),
+        mut rx: impl FnMut(usize, u32),
+    ) -> Result<(), &'static str> {
+        if !self.enabled {
+            return Err("SPI master is disabled");
+        }
+
+        let (mut sent, mut received) = (0, 0);
+        while received < count {
+            while sent < count && sent - received < FIFO_DEPTH && !self.registers.AUXSPI_STAT.is_set(AUXSPI_STAT::TxFull) {
+                let (word, bits) = tx(sent);
+                let entry = AUXSPI_DATA::Width.val(bits as u32) + AUXSPI_DATA::Data.val(word << (AUX_SPI_MAX_WORD_BITS - bits));
+                if sent + 1 == count {
+                    self.registers.AUXSPI_IO.write(entry);
+                } else {
+                    self.registers.AUXSPI_TXHOLD.write(entry);
+                }
+                sent += 1;
+            }
+
+            if let Err(x) = self.spin_while(|r| r.AUXSPI_STAT.is_set(AUXSPI_STAT::RxEmpty)) {
+                // Don't leave a half-done transfer's data behind for the next one.
+                self.apply();
+                return Err(x);
+            }
+            rx(received, self.registers.AUXSPI_IO.read(AUXSPI_DATA::Data));
+            received += 1;
+        }
+        Ok(())
+    }
+
+    fn spin_while(&self, condition: impl Fn(&SpiRegisters) -> bool) -> Result<(), &'static str> {
+        for _ in 0..TIMEOUT_SPINS {
+            if !condition(&self.registers) {
+                return Ok(());
+            }
+            aarch64_cpu::asm::nop();
+        }
+        Err("SPI timeout")
+    }
+}
@@ -0,0 +1,441 @@
+//! DMA controller, channels 0-14.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::{
+    driver, dtb, exception,
+    memory::mmu,
+    scheduler::PTABLE,
+    synchronization::{interface::Mutex, SpinLock},
+    time::time_manager,
+};
+
+register_bitfields! {
+    u32,
+
+    /// Control and Status.
+    DMA_CS [
+        /// Resets the channel, self-clearing.
+        RESET OFFSET(31) NUMBITS(1) [],
+        ABORT OFFSET(30) NUMBITS(1) [],
+        /// Don't advance to the next control block before all writes have been acknowledged.
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+        PANIC_PRIORITY OFFSET(20) NUMBITS(4) [],
+        PRIORITY OFFSET(16) NUMBITS(4) [],
+        ERROR OFFSET(8) NUMBITS(1) [],
+        /// Write 1 to clear.
+        INT OFFSET(2) NUMBITS(1) [],
+        /// Set when the last control block has completed, write 1 to clear.
+        END OFFSET(1) NUMBITS(1) [],
+        ACTIVE OFFSET(0) NUMBITS(1) [],
+    ],
+
+    /// Transfer Information, also the first word of a control block.
+    pub DMA_TI [
+        NO_WIDE_BURSTS OFFSET(26) NUMBITS(1) [],
+        /// Peripheral whose DREQ paces the transfer.
+        PERMAP OFFSET(16) NUMBITS(5) [],
+        SRC_DREQ OFFSET(10) NUMBITS(1) [],
+        SRC_INC OFFSET(8) NUMBITS(1) [],
+        DEST_DREQ OFFSET(6) NUMBITS(1) [],
+        DEST_INC OFFSET(4) NUMBITS(1) [],
+        /// Wait for the AXI write response of every write.
+        WAIT_RESP OFFSET(3) NUMBITS(1) [],
+        /// Interrupt when this control block completes.
+        INTEN OFFSET(0) NUMBITS(1) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    ChannelRegisters {
+        (0x00 => CS: ReadWrite<u32, DMA_CS::Register>),
+        (0x04 => CONBLK_AD: ReadWrite<u32>),
+        (0x08 => _reserved1),
+        /// Bytes left in the current control block.
+        (0x14 => TXFR_LEN: ReadWrite<u32>),
+        (0x18 => _reserved2),
+        (0x100 => @END),
+    }
+}
+
+// Channel 15 lives in a different block and isn't used.
+register_structs! {
+    #[allow(non_snake_case)]
+    pub DmaRegisters {
+        (0x000 => CHANNELS: [ChannelRegisters; NUM_CHANNELS]),
+        (0xF00 => _reserved1),
+        (0xFE0 => INT_STATUS: ReadWrite<u32>),
+        (0xFE4 => _reserved2),
+        (0xFF0 => ENABLE: ReadWrite<u32>),
+        (0xFF4 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<DmaRegisters>;
+
+const NUM_CHANNELS: usize = 15;
+
+/// Channels left to the ARM by the firmware if the device tree doesn't say. Channels 11-14 share
+/// a single interrupt line and are left out.
+const DEFAULT_CHANNEL_MASK: u32 = 0x0735;
+
+/// Polls before giving up on a transfer when it can't be waited for.
+const TIMEOUT_SPINS: usize = 10_000_000;
+
+/// Time without progress before giving up on a transfer that is waited for.
+const IRQ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The DMA engine sees ARM memory through the uncached bus alias.
+const BUS_ALIAS: u32 = 0xC000_0000;
+
+/// Bus address of the peripherals, for a control block's source or destination.
+pub const PERIPHERAL_BUS_BASE: u32 = 0x7E00_0000;
+
+/// Peripheral DREQ numbers for `DMA_TI::PERMAP`.
+pub const DREQ_SPI0_TX: u32 = 6;
+pub const DREQ_SPI0_RX: u32 = 7;
+
+/// A DMA control block, read by the engine straight from memory.
+#[repr(C, align(32))]
+pub struct ControlBlock {
+    pub ti: u32,
+    pub source_ad: u32,
+    pub dest_ad: u32,
+    pub txfr_len: u32,
+    pub stride: u32,
+    pub nextconbk: u32,
+    _reserved: [u32; 2],
+}
+
+impl ControlBlock {
+    pub const fn new() -> Self {
+        Self {
+            ti: 0,
+            source_ad: 0,
+            dest_ad: 0,
+            txfr_len: 0,
+            stride: 0,
+            nextconbk: 0,
+            _reserved: [0; 2],
+        }
+    }
+}
+
+#[repr(C, align(64))]
+#[derive(Copy, Clone)]
+struct CacheLine([u32; 16]);
+
+/// Word buffer that owns whole cache lines, so maintenance on it can't disturb neighbouring data.
+pub struct DmaBuffer {
+    lines: Vec<CacheLine>,
+    words: usize,
+}
+
+impl DmaBuffer {
+    pub fn new(words: usize) -> Self {
+        Self {
+            lines: alloc::vec![CacheLine([0; 16]); words.div_ceil(16)],
+            words,
+        }
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.as_flat()[..self.words]
+    }
+
+    pub fn words_mut(&mut self) -> &mut [u32] {
+        let words = self.words;
+        &mut self.as_flat_mut()[..words]
+    }
+
+    pub fn bus_address(&self) -> u32 {
+        bus_address(self.lines.as_ptr() as usize)
+    }
+
+    /// Writes the buffer back before the engine reads it and drops stale lines before the
+    /// CPU reads what the engine wrote.
+    pub fn sync(&self) {
+        mmu::clean_invalidate_dcache_range(self.lines.as_ptr() as usize, self.lines.len() * 64);
+    }
+
+    fn as_flat(&self) -> &[u32] {
+        unsafe { core::slice::from_raw_parts(self.lines.as_ptr() as *const u32, self.lines.len() * 16) }
+    }
+
+    fn as_flat_mut(&mut self) -> &mut [u32] {
+        unsafe { core::slice::from_raw_parts_mut(self.lines.as_mut_ptr() as *mut u32, self.lines.len() * 16) }
+    }
+}
+
+/// Bus address of identity-mapped ARM memory.
+pub fn bus_address(addr: usize) -> u32 {
+    addr as u32 | BUS_ALIAS
+}
+
+/// A channel handed out by `Dma::allocate_channel`.
+#[derive(Copy, Clone, PartialEq)]
+pub struct DmaChannel(usize);
+
+pub struct Dma {
+    inner: SpinLock<DmaInner>,
+}
+
+impl Dma {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-dma";
+
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: SpinLock::new(DmaInner::new(mmio_start_addr)),
+        }
+    }
+
+    /// Channels the ARM may use, one bit per channel.
+    pub fn channel_mask(&self) -> u32 {
+        let daif = exception::irq_save();
+        let mask = self.inner.lock().unwrap().channel_mask;
+        exception::irq_restore(daif);
+        mask
+    }
+
+    pub fn allocate_channel(&self) -> Result<DmaChannel, &'static str> {
+        let daif = exception::irq_save();
+        let result = self.inner.lock().unwrap().allocate_channel();
+        exception::irq_restore(daif);
+        result
+    }
+
+    pub fn free_channel(&self, channel: DmaChannel) {
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().free_channel(channel);
+        exception::irq_restore(daif);
+    }
+
+    /// Starts `channel` on the chain beginning at `cb`, which must stay put until the
+    /// transfer has completed.
+    pub fn start(&self, channel: DmaChannel, cb: &ControlBlock) {
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().start(channel, cb);
+        exception::irq_restore(daif);
+    }
+
+    /// Waits for `channel` to finish the transfer begun by `start`. Lets other processes run
+    /// in between checks if interrupts are routed here, otherwise spins. The channel is stopped
+    /// if the transfer stalls or the wait is interrupted.
+    pub fn wait(&self, channel: DmaChannel) -> Result<(), &'static str> {
+        let (mut position, mut last_progress) = (None, time_manager().uptime());
+        loop {
+            let daif = exception::irq_save();
+            let mut data = self.inner.lock().unwrap();
+            if let Some(result) = data.take_completion(channel) {
+                drop(data);
+                exception::irq_restore(daif);
+                return result;
+            }
+
+            if !data.irq_mode || !exception::irqs_enabled(daif) {
+                let result = data.spin_until_done(channel);
+                drop(data);
+                exception::irq_restore(daif);
+                return result;
+            }
+
+            let now = data.position(channel);
+            let result = if position != Some(now) {
+                (position, last_progress) = (Some(now), time_manager().uptime());
+                Ok(())
+            } else if time_manager().uptime() - last_progress > IRQ_TIMEOUT {
+                Err("DMA timeout")
+            } else if PTABLE.interrupted() {
+                Err("interrupted")
+            } else {
+                Ok(())
+            };
+            if result.is_err() {
+                data.reset(channel);
+                drop(data);
+                exception::irq_restore(daif);
+                return result;
+            }
+
+            // As for SPI0's interrupt-driven transfers, sleeping would leave nobody to notice the
+            // timeout should the interrupt never come.
+            drop(data);
+            exception::irq_restore(daif);
+            PTABLE.yield_now();
+        }
+    }
+
+    /// Stops `channel` and drops whatever it was doing.
+    pub fn abort(&self, channel: DmaChannel) {
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().reset(channel);
+        exception::irq_restore(daif);
+    }
+
+    /// Switches completion waits from spinning to yielding. The channels' interrupts must already
+    /// be routed to `Dma`'s `IrqHandler`.
+    pub fn enable_interrupts(&self) {
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().irq_mode = true;
+        exception::irq_restore(daif);
+    }
+}
+
+impl exception::interface::IrqHandler for Dma {
+    fn handle(&self) {
+        self.inner.lock().unwrap().handle_interrupt();
+    }
+}
+
+impl driver::interface::DeviceDriver for Dma {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mask = dtb::device_tree()
+            .and_then(|dt| dt.find_compatible(Self::COMPATIBLE).next())
+            .and_then(|node| node.property("brcm,dma-channel-mask"))
+            .and_then(|p| p.as_u32())
+            .unwrap_or(DEFAULT_CHANNEL_MASK);
+
+        let mut data = self.inner.lock().unwrap();
+        data.channel_mask = mask & DEFAULT_CHANNEL_MASK;
+        data.init();
+        Ok(())
+    }
+}
+
+struct DmaInner {
+    registers: Registers,
+    channel_mask: u32,
+    allocated: u32,
+    /// Channels whose transfer finished since `start`, and those that finished with an error.
+    completed: u32,
+    failed: u32,
+    irq_mode: bool,
+}
+
+impl DmaInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            channel_mask: 0,
+            allocated: 0,
+            completed: 0,
+            failed: 0,
+            irq_mode: false,
+        }
+    }
+
+    fn init(&mut self) {
+        self.registers.ENABLE.set(self.registers.ENABLE.get() | self.channel_mask);
+        let mask = self.channel_mask;
+        for channel in (0..NUM_CHANNELS).filter(|c| mask & (1 << c) != 0) {
+            self.reset(DmaChannel(channel));
+        }
+    }
+
+    fn allocate_channel(&mut self) -> Result<DmaChannel, &'static str> {
+        let free = self.channel_mask & !self.allocated;
+        if free == 0 {
+            return Err("no free DMA channel");
+        }
+
+        let channel = free.trailing_zeros() as usize;
+        self.allocated |= 1 << channel;
+        Ok(DmaChannel(channel))
+    }
+
+    fn free_channel(&mut self, channel: DmaChannel) {
+        self.reset(channel);
+        self.allocated &= !(1 << channel.0);
+    }
+
+    fn reset(&mut self, channel: DmaChannel) {
+        self.registers.CHANNELS[channel.0].CS.write(DMA_CS::RESET::SET);
+        self.registers.CHANNELS[channel.0].CS.write(DMA_CS::INT::SET + DMA_CS::END::SET);
+    }
+
+    fn start(&mut self, channel: DmaChannel, cb: &ControlBlock) {
+        let addr = cb as *const ControlBlock as usize;
+        mmu::clean_invalidate_dcache_range(addr, core::mem::size_of::<ControlBlock>());
+
+        self.completed &= !(1 << channel.0);
+        self.failed &= !(1 << channel.0);
+
+        let registers = &self.registers.CHANNELS[channel.0];
+        registers.CS.write(DMA_CS::INT::SET + DMA_CS::END::SET);
+        registers.CONBLK_AD.set(bus_address(addr));
+        registers.CS.write(
+            DMA_CS::ACTIVE::SET
+                + DMA_CS::WAIT_FOR_OUTSTANDING_WRITES::SET
+                + DMA_CS::PRIORITY.val(8)
+                + DMA_CS::PANIC_PRIORITY.val(15),
+        );
+    }
+
+    /// Acknowledges finished channels and records them.
+    fn handle_interrupt(&mut self) {
+        let pending = self.registers.INT_STATUS.get() & self.allocated;
+        for channel in (0..NUM_CHANNELS).filter(|c| pending & (1 << c) != 0) {
+            self.collect(channel);
+        }
+    }
+
+    fn collect(&mut self, channel: usize) {
+        let cs = &self.registers.CHANNELS[channel].CS;
+        if cs.is_set(DMA_CS::ERROR) {
+            self.failed |= 1 << channel;
+        }
+        cs.write(DMA_CS::INT::SET + DMA_CS::END::SET);
+        self.completed |= 1 << channel;
+    }
+
+    /// The control block `channel` is on and the bytes it has left, which change as the
+    /// transfer makes progress.
+    fn position(&self, channel: DmaChannel) -> (u32, u32) {
+        let registers = &self.registers.CHANNELS[channel.0];
+        (registers.CONBLK_AD.get(), registers.TXFR_LEN.get())
+    }
+
+    fn take_completion(&mut self, channel: DmaChannel) -> Option<Result<(), &'static str>> {
+        let bit = 1 << channel.0;
+        if self.completed & bit == 0 {
+            return None;
+        }
+
+        self.completed &= !bit;
+        if self.failed & bit != 0 {
+            Some(Err("DMA transfer error"))
+        } else {
+            Some(Ok(()))
+        }
+    }
+
+    fn spin_until_done(&mut self, channel: DmaChannel) -> Result<(), &'static str> {
+        for _ in 0..TIMEOUT_SPINS {
+            let cs = &self.registers.CHANNELS[channel.0].CS;
+            if cs.is_set(DMA_CS::END) || cs.is_set(DMA_CS::ERROR) {
+                self.collect(channel.0);
+                return self.take_completion(channel).unwrap();
+            }
+            aarch64_cpu::asm::nop();
+        }
+
+        self.reset(channel);
+        Err("DMA timeout")
+    }
+}
@@ -0,0 +1,304 @@
+//! Framebuffer allocated from the VideoCore firmware through the mailbox property interface.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface>
+
+use core::ptr;
+
+use super::Mailbox;
+use crate::memory::mmu::{self, AccessPermissions, AttributeFields, MemoryAttributes};
+use crate::{driver, exception, synchronization::{interface::Mutex, SpinLock}};
+
+const TAG_ALLOCATE_BUFFER: u32 = 0x0004_0001;
+const TAG_GET_PITCH: u32 = 0x0004_0008;
+const TAG_SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+const TAG_SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+const TAG_SET_DEPTH: u32 = 0x0004_8005;
+const TAG_SET_PIXEL_ORDER: u32 = 0x0004_8006;
+const TAG_SET_VIRTUAL_OFFSET: u32 = 0x0004_8009;
+
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+const BITS_PER_PIXEL: u32 = 32;
+const PIXEL_ORDER_RGB: u32 = 1;
+
+/// The firmware hands out bus addresses, the ARM sees the same memory with the alias bits clear.
+const BUS_ADDRESS_MASK: u32 = 0x3FFF_FFFF;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Self = Self::new(0, 0, 0);
+    pub const WHITE: Self = Self::new(255, 255, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+pub struct Framebuffer {
+    inner: SpinLock<FramebufferInner>,
+    mailbox: &'static Mailbox,
+}
+
+impl Framebuffer {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2708-fb";
+
+    pub const fn new(mailbox: &'static Mailbox) -> Self {
+        Self {
+            inner: SpinLock::new(FramebufferInner::new()),
+            mailbox,
+        }
+    }
+
+    fn with_inner<R>(&self, f: impl FnOnce(&mut FramebufferInner) -> R) -> R {
+        let daif = exception::irq_save();
+        let result = f(&mut self.inner.lock().unwrap());
+        exception::irq_restore(daif);
+        result
+    }
+
+    /// Width and height in pixels, zero until the firmware has handed out a buffer.
+    pub fn size(&self) -> (usize, usize) {
+        self.with_inner(|inner| (inner.width, inner.height))
+    }
+
+    pub fn set_pixel(&self, x: usize, y: usize, color: Color) {
+        self.fill_rect(x, y, 1, 1, color);
+    }
+
+    /// Fills a rectangle, clipped to the screen like everything else drawn.
+    pub fn fill_rect(&self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        self.with_inner(|inner| {
+            let value = inner.pixel_value(color);
+            let (width, height) = inner.clip(x, y, width, height);
+            for row in y..y + height {
+                for col in x..x + width {
+                    inner.write(col, row, value);
+                }
+            }
+        });
+    }
+
+    /// Copies `pixels`, `width` per row, to the rectangle at `x`, `y`.
+    pub fn blit(&self, x: usize, y: usize, width: usize, pixels: &[Color]) {
+        if width == 0 {
+            return;
+        }
+
+        self.with_inner(|inner| {
+            let (visible_width, visible_height) = inner.clip(x, y, width, pixels.len() / width);
+            for (row, line) in pixels.chunks(width).take(visible_height).enumerate() {
+                for (col, &color) in line[..visible_width].iter().enumerate() {
+                    inner.write(x + col, y + row, inner.pixel_value(color));
+                }
+            }
+        });
+    }
+
+    /// Moves the rectangle at `src_x`, `src_y` to `dst_x`, `dst_y`. The two may overlap.
+    pub fn copy_rect(&self, src_x: usize, src_y: usize, width: usize, height: usize, dst_x: usize, dst_y: usize) {
+        self.with_inner(|inner| {
+            let (width, height) = inner.clip(src_x, src_y, width, height);
+            let (width, height) = inner.clip(dst_x, dst_y, width, height);
+
+            let copy_row = |row: usize| unsafe {
+                ptr::copy(
+                    inner.pixel_ptr(src_x, src_y + row),
+                    inner.pixel_ptr(dst_x, dst_y + row),
+                    width,
+                );
+            };
+            // Rows are copied in the order that doesn't overwrite ones still to be read.
+            if dst_y <= src_y {
+                (0..height).for_each(copy_row);
+            } else {
+                (0..height).rev().for_each(copy_row);
+            }
+        });
+    }
+
+    /// Moves the whole screen up by `height` rows of pixels and fills the rows uncovered at the
+    /// bottom with `color`. With room left in the virtual buffer below the screen this only
+    /// pans the display, so the pixels are copied about once per screenful rather than on
+    /// every scroll.
+    pub fn scroll_up(&self, height: usize, color: Color) {
+        self.with_inner(|inner| {
+            let height = height.min(inner.height);
+            let mut offset = inner.y_offset + height;
+            if offset + inner.height > inner.virtual_height {
+                // Out of room, start over at the top with what stays on screen.
+                inner.copy_rows(inner.y_offset + height, 0, inner.height - height);
+                offset = 0;
+            }
+
+            // Fill before panning, so the stale rows are never shown.
+            let value = inner.pixel_value(color);
+            for row in offset + inner.height - height..offset + inner.height {
+                for col in 0..inner.width {
+                    unsafe { ptr::write_volatile(inner.row_ptr(row).add(col), value) };
+                }
+            }
+
+            if offset != inner.y_offset {
+                let panned = self.mailbox.property(TAG_SET_VIRTUAL_OFFSET, &[0, offset as u32], &mut [0; 2]);
+                if panned.is_err() {
+                    // Put the new screen where it is shown and copy from now on.
+                    inner.copy_rows(offset, inner.y_offset, inner.height);
+                    inner.virtual_height = inner.height;
+                    offset = inner.y_offset;
+                }
+                inner.y_offset = offset;
+            }
+        });
+    }
+
+    /// Inverts every pixel of a rectangle, drawing it twice restores the original.
+    pub fn invert_rect(&self, x: usize, y: usize, width: usize, height: usize) {
+        self.with_inner(|inner| {
+            let (width, height) = inner.clip(x, y, width, height);
+            for row in y..y + height {
+                for col in x..x + width {
+                    let value = inner.read(col, row);
+                    inner.write(col, row, value ^ 0x00FF_FFFF);
+                }
+            }
+        });
+    }
+
+    /// Asks the firmware for a `width` x `height` 32 bit framebuffer and maps it. The buffer is
+    /// `virtual_height` rows tall, `scroll_up` pans over the part off screen.
+    fn allocate(&self, width: u32, height: u32, virtual_height: u32) -> Result<(), &'static str> {
+        let mut physical_size = [width, height];
+        let mut virtual_size = [width, virtual_height];
+        let mut virtual_offset = [0, 0];
+        let mut depth = [BITS_PER_PIXEL];
+        let mut pixel_order = [PIXEL_ORDER_RGB];
+        let mut buffer = [4096, 0];
+        let mut pitch = [0];
+        self.mailbox.properties(&mut [
+            (TAG_SET_PHYSICAL_SIZE, &mut physical_size),
+            (TAG_SET_VIRTUAL_SIZE, &mut virtual_size),
+            (TAG_SET_VIRTUAL_OFFSET, &mut virtual_offset),
+            (TAG_SET_DEPTH, &mut depth),
+            (TAG_SET_PIXEL_ORDER, &mut pixel_order),
+            (TAG_ALLOCATE_BUFFER, &mut buffer),
+            (TAG_GET_PITCH, &mut pitch),
+        ])?;
+
+        if buffer[0] == 0 || depth[0] != BITS_PER_PIXEL {
+            return Err("firmware refused the framebuffer");
+        }
+
+        let base = (buffer[0] & BUS_ADDRESS_MASK) as usize;
+        let size = buffer[1] as usize;
+        // Written by the ARM, scanned out by the VideoCore. Bypassing the caches keeps the two
+        // in sync without cleaning after every glyph.
+        let attributes = AttributeFields {
+            execute_never: true,
+            permissions: AccessPermissions::ReadWrite,
+            memory_attributes: MemoryAttributes::NonCacheableDRAM,
+        };
+        unsafe { mmu::map_identity(base, size, &attributes)? };
+
+        self.with_inner(|inner| {
+            inner.base = base;
+            inner.width = physical_size[0] as usize;
+            inner.height = physical_size[1] as usize;
+            inner.pitch = pitch[0] as usize;
+            // Only what both the firmware granted and the mapping covers.
+            inner.virtual_height = (virtual_size[1] as usize).min(size / inner.pitch.max(1)).max(inner.height);
+            inner.y_offset = 0;
+            inner.rgb = pixel_order[0] == PIXEL_ORDER_RGB;
+        });
+        Ok(())
+    }
+}
+
+impl driver::interface::DeviceDriver for Framebuffer {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Twice the screen height leaves room to scroll by panning, settle for one if the
+        // firmware is short on memory.
+        self.allocate(DEFAULT_WIDTH, DEFAULT_HEIGHT, 2 * DEFAULT_HEIGHT)
+            .or_else(|_| self.allocate(DEFAULT_WIDTH, DEFAULT_HEIGHT, DEFAULT_HEIGHT))?;
+        let (width, height) = self.size();
+        self.fill_rect(0, 0, width, height, Color::BLACK);
+        Ok(())
+    }
+}
+
+struct FramebufferInner {
+    base: usize,
+    width: usize,
+    height: usize,
+    /// Rows in the whole buffer, at least `height`.
+    virtual_height: usize,
+    /// First row of the buffer on screen, everything drawn is relative to it.
+    y_offset: usize,
+    /// Bytes from one row to the next.
+    pitch: usize,
+    /// Whether red is in the low byte of a pixel, the firmware may not honour the request.
+    rgb: bool,
+}
+
+impl FramebufferInner {
+    const fn new() -> Self {
+        Self {
+            base: 0,
+            width: 0,
+            height: 0,
+            virtual_height: 0,
+            y_offset: 0,
+            pitch: 0,
+            rgb: true,
+        }
+    }
+
+    /// The part of a `width` x `height` rectangle at `x`, `y` that is on screen.
+    fn clip(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        (
+            width.min(self.width.saturating_sub(x)),
+            height.min(self.height.saturating_sub(y)),
+        )
+    }
+
+    fn pixel_value(&self, color: Color) -> u32 {
+        let (low, high) = if self.rgb { (color.r, color.b) } else { (color.b, color.r) };
+        low as u32 | (color.g as u32) << 8 | (high as u32) << 16
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        unsafe { self.row_ptr(self.y_offset + y).add(x) }
+    }
+
+    /// Copies `count` rows of the whole buffer from `src` up to `dst`, which must not be below
+    /// it.
+    fn copy_rows(&self, src: usize, dst: usize, count: usize) {
+        for row in 0..count {
+            unsafe { ptr::copy(self.row_ptr(src + row), self.row_ptr(dst + row), self.width) };
+        }
+    }
+
+    /// Start of row `y` of the whole buffer, on screen or not.
+    fn row_ptr(&self, y: usize) -> *mut u32 {
+        (self.base + y * self.pitch) as *mut u32
+    }
+
+    fn write(&self, x: usize, y: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.pixel_ptr(x, y), value) }
+    }
+
+    fn read(&self, x: usize, y: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.pixel_ptr(x, y)) }
+    }
+}
@@ -4,13 +4,20 @@
 
 //! GPIO Driver.
 
+use alloc::vec::Vec;
+use core::fmt;
 use tock_registers::{
     interfaces::{Writeable, Readable},
     register_bitfields, register_structs,
-    registers::ReadWrite,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::{
+    driver, dtb, exception,
+    scheduler::{WaitQueue, PTABLE},
+    synchronization::{interface::Mutex, SpinLock},
+};
 use crate::utils::spin_for_cycles;
 
 //--------------------------------------------------------------------------------------------------
@@ -25,29 +32,6 @@ use crate::utils::spin_for_cycles;
 register_bitfields! {
     u32,
 
-    /// GPIO Function Select 1
-    GPFSEL1 [
-        /// Pin 15
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100,  // PL011 UART RX
-            AltFunc1 = 0b101,
-            AltFunc2 = 0b110,
-            AltFunc5 = 0b010,
-        ],
-
-        /// Pin 14
-        FSEL14 OFFSET(12) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100,  // PL011 UART TX
-            AltFunc1 = 0b101,
-            AltFunc2 = 0b110,
-            AltFunc5 = 0b010,
-        ]
-    ],
-
     /// GPIO Pull-up/down Register
     ///
     /// BCM2837 only.
@@ -58,96 +42,436 @@ register_bitfields! {
             PullDown = 0b01,
             PullUp = 0b10
         ]
-    ],
-
-    /// GPIO Pull-up/down Clock Register 0
-    ///
-    /// BCM2837 only.
-    GPPUDCLK0 [
-        /// Pin 15
-        PUDCLK15 OFFSET(15) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1
-        ],
-
-        /// Pin 14
-        PUDCLK14 OFFSET(14) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1
-        ]
-    ],
-
-    /// GPIO Pull-up / Pull-down Register 0
-    ///
-    /// BCM2711 only.
-    GPIO_PUP_PDN_CNTRL_REG0 [
-        /// Pin 15
-        GPIO_PUP_PDN_CNTRL15 OFFSET(30) NUMBITS(2) [
-            NoResistor = 0b00,
-            PullUp = 0b01
-        ],
-
-        /// Pin 14
-        GPIO_PUP_PDN_CNTRL14 OFFSET(28) NUMBITS(2) [
-            NoResistor = 0b00,
-            PullUp = 0b01
-        ]
     ]
 }
 
+// Banked registers hold one bit per pin, pins 0-31 in the first word and 32-53 in the second.
+// GPFSEL packs ten pins of three bits each per word, GPIO_PUP_PDN_CNTRL sixteen pins of two bits.
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => _reserved1),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => _reserved2),
+        (0x00 => GPFSEL: [ReadWrite<u32>; 6]),
+        (0x18 => _reserved1),
+        (0x1C => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved2),
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved3),
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
+        (0x3C => _reserved4),
+        (0x40 => GPEDS: [ReadWrite<u32>; 2]),
+        (0x48 => _reserved5),
+        (0x4C => GPREN: [ReadWrite<u32>; 2]),
+        (0x54 => _reserved6),
+        (0x58 => GPFEN: [ReadWrite<u32>; 2]),
+        (0x60 => _reserved7),
+        (0x64 => GPHEN: [ReadWrite<u32>; 2]),
+        (0x6C => _reserved8),
+        (0x70 => GPLEN: [ReadWrite<u32>; 2]),
+        (0x78 => _reserved9),
+        (0x7C => GPAREN: [ReadWrite<u32>; 2]),
+        (0x84 => _reserved10),
+        (0x88 => GPAFEN: [ReadWrite<u32>; 2]),
+        (0x90 => _reserved11),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
-        (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9C => _reserved3),
-        (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
-        (0xE8 => @END),
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xA0 => _reserved12),
+        (0xE4 => GPIO_PUP_PDN_CNTRL: [ReadWrite<u32>; 4]),
+        (0xF4 => @END),
     }
 }
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const NUM_PINS: usize = 54;
+
+/// Pin function, with its GPFSEL encoding.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Function {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010,
+}
+
+impl Function {
+    const ALL: [Function; 8] = [
+        Function::Input,
+        Function::Output,
+        Function::Alt0,
+        Function::Alt1,
+        Function::Alt2,
+        Function::Alt3,
+        Function::Alt4,
+        Function::Alt5,
+    ];
+
+    fn from_bits(bits: u32) -> Self {
+        Self::ALL.into_iter().find(|f| *f as u32 == bits).unwrap()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Input => "in",
+            Function::Output => "out",
+            Function::Alt0 => "alt0",
+            Function::Alt1 => "alt1",
+            Function::Alt2 => "alt2",
+            Function::Alt3 => "alt3",
+            Function::Alt4 => "alt4",
+            Function::Alt5 => "alt5",
+        }
+    }
+
+    /// Inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Pull {
+    Off,
+    Down,
+    Up,
+}
+
+/// Event detection. The edge triggers are sampled against the system clock, the async ones
+/// aren't and also catch pulses shorter than a clock cycle. Level triggers keep firing for as
+/// long as the level persists, so the interrupt handler disarms them once they have fired.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Trigger {
+    Rising,
+    Falling,
+    High,
+    Low,
+    AsyncRising,
+    AsyncFalling,
+}
+
+impl Trigger {
+    const ALL: [Trigger; 6] = [
+        Trigger::Rising,
+        Trigger::Falling,
+        Trigger::High,
+        Trigger::Low,
+        Trigger::AsyncRising,
+        Trigger::AsyncFalling,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trigger::Rising => "rising",
+            Trigger::Falling => "falling",
+            Trigger::High => "high",
+            Trigger::Low => "low",
+            Trigger::AsyncRising => "arising",
+            Trigger::AsyncFalling => "afalling",
+        }
+    }
+
+    /// Inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+/// Called in IRQ context with the pin that saw an event.
+pub type GpioCallback = fn(pin: usize);
+
 #[allow(clippy::upper_case_acronyms)]
 pub struct GPIO {
-    registers: Registers,
+    inner: SpinLock<GPIOInner>,
+    event_waiters: WaitQueue,
 }
 
 impl GPIO {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-gpio";
+
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
-            registers: Registers::new(mmio_start_addr)
+            inner: SpinLock::new(GPIOInner::new(mmio_start_addr)),
+            event_waiters: WaitQueue::new(),
+        }
+    }
+
+    fn with_inner<R>(&self, pin: usize, f: impl FnOnce(&mut GPIOInner) -> R) -> Result<R, &'static str> {
+        if pin >= NUM_PINS {
+            return Err("invalid GPIO pin");
+        }
+
+        let daif = exception::irq_save();
+        let result = f(&mut self.inner.lock().unwrap());
+        exception::irq_restore(daif);
+        Ok(result)
+    }
+
+    pub fn set_function(&self, pin: usize, function: Function) -> Result<(), &'static str> {
+        self.with_inner(pin, |inner| inner.set_function(pin, function))
+    }
+
+    pub fn function(&self, pin: usize) -> Result<Function, &'static str> {
+        self.with_inner(pin, |inner| inner.function(pin))
+    }
+
+    /// Drives `pin` high or low. Only has a visible effect while the pin is an output.
+    pub fn set_level(&self, pin: usize, high: bool) -> Result<(), &'static str> {
+        self.with_inner(pin, |inner| inner.set_level(pin, high))
+    }
+
+    pub fn level(&self, pin: usize) -> Result<bool, &'static str> {
+        self.with_inner(pin, |inner| inner.level(pin))
+    }
+
+    pub fn set_pull(&self, pin: usize, pull: Pull) -> Result<(), &'static str> {
+        self.with_inner(pin, |inner| inner.set_pull(pin, pull))
+    }
+
+    /// Arms `trigger` on `pin` in addition to any triggers already set. The pin's bank interrupt
+    /// must be routed to `GPIO`'s `IrqHandler` for callbacks and waiters to see events.
+    pub fn enable_trigger(&self, pin: usize, trigger: Trigger) -> Result<(), &'static str> {
+        self.with_inner(pin, |inner| inner.set_trigger(pin, trigger, true))
+    }
+
+    /// Disarms every trigger on `pin`.
+    pub fn disable_triggers(&self, pin: usize) -> Result<(), &'static str> {
+        self.with_inner(pin, |inner| {
+            for trigger in Trigger::ALL {
+                inner.set_trigger(pin, trigger, false);
+            }
+        })
+    }
+
+    /// Triggers currently armed on `pin`.
+    pub fn triggers(&self, pin: usize) -> Result<Vec<Trigger>, &'static str> {
+        self.with_inner(pin, |inner| {
+            Trigger::ALL.into_iter().filter(|&t| inner.trigger_enabled(pin, t)).collect()
+        })
+    }
+
+    /// Runs `callback` for every event on `pin`, replacing any earlier callback.
+    pub fn set_callback(&self, pin: usize, callback: Option<GpioCallback>) -> Result<(), &'static str> {
+        self.with_inner(pin, |inner| inner.callbacks[pin] = callback)
+    }
+
+    /// Blocks the calling task until the next event on `pin`, or until it is interrupted.
+    pub fn wait_for_event(&self, pin: usize) -> Result<(), &'static str> {
+        let start = self.with_inner(pin, |inner| inner.event_counts[pin])?;
+
+        loop {
+            let daif = exception::irq_save();
+            let data = self.inner.lock().unwrap();
+            if data.event_counts[pin] != start {
+                drop(data);
+                exception::irq_restore(daif);
+                return Ok(());
+            }
+            if PTABLE.interrupted() {
+                drop(data);
+                exception::irq_restore(daif);
+                return Err("interrupted");
+            }
+
+            // Holding the GPIO lock while queueing means the handler can't slip in between.
+            let slept = self.event_waiters.sleep(daif, data);
+            exception::irq_restore(daif);
+            if !slept {
+                return Err("can't wait for GPIO events with IRQs masked");
+            }
+        }
+    }
+
+    /// Hands each `(pin, function)` pair to its alternate function with the pull-up/down
+    /// disabled, e.g. a UART's TXD/RXD/CTS/RTS.
+    pub fn init_alt_pins(&self, pins: &[(usize, Function)]) -> Result<(), &'static str> {
+        for &(pin, function) in pins {
+            self.set_function(pin, function)?;
+            self.set_pull(pin, Pull::Off)?;
+        }
+        Ok(())
+    }
+}
+
+impl exception::interface::IrqHandler for GPIO {
+    fn handle(&self) {
+        let (pending, callbacks) = {
+            let mut data = self.inner.lock().unwrap();
+            (data.take_events(), data.callbacks)
+        };
+        if pending == 0 {
+            return;
+        }
+
+        for pin in (0..NUM_PINS).filter(|pin| pending & (1 << pin) != 0) {
+            if let Some(callback) = callbacks[pin] {
+                callback(pin);
+            }
+        }
+        self.event_waiters.wake_all();
+    }
+}
+
+impl driver::interface::DeviceDriver for GPIO {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // The BCM2711 replaced the GPPUD/GPPUDCLK sequence with directly writable pull registers.
+        let bcm2711 = dtb::device_tree()
+            .and_then(|dt| dt.nodes().next())
+            .is_some_and(|root| root.is_compatible("brcm,bcm2711"));
+        self.inner.lock().unwrap().bcm2711 = bcm2711;
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+struct GPIOInner {
+    registers: Registers,
+    bcm2711: bool,
+    callbacks: [Option<GpioCallback>; NUM_PINS],
+    event_counts: [usize; NUM_PINS],
+}
+
+impl GPIOInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            bcm2711: false,
+            callbacks: [None; NUM_PINS],
+            event_counts: [0; NUM_PINS],
         }
     }
 
-    fn set_func(&self, pin: u32, func: u32) {
-        let bit_start = (pin * 3) % 30;
+    fn trigger_register(&self, trigger: Trigger, bank: usize) -> &ReadWrite<u32> {
+        match trigger {
+            Trigger::Rising => &self.registers.GPREN[bank],
+            Trigger::Falling => &self.registers.GPFEN[bank],
+            Trigger::High => &self.registers.GPHEN[bank],
+            Trigger::Low => &self.registers.GPLEN[bank],
+            Trigger::AsyncRising => &self.registers.GPAREN[bank],
+            Trigger::AsyncFalling => &self.registers.GPAFEN[bank],
+        }
+    }
 
-        let mut selector = self.registers.GPFSEL1.get();
-        selector &= !(7 << bit_start);
-        selector |= func << bit_start;
+    fn set_trigger(&mut self, pin: usize, trigger: Trigger, enabled: bool) {
+        let register = self.trigger_register(trigger, pin / 32);
+        let bit = 1 << (pin % 32);
+        if enabled {
+            register.set(register.get() | bit);
+        } else {
+            register.set(register.get() & !bit);
+        }
+    }
+
+    fn trigger_enabled(&self, pin: usize, trigger: Trigger) -> bool {
+        self.trigger_register(trigger, pin / 32).get() & (1 << (pin % 32)) != 0
+    }
 
+    /// Acknowledges all detected events and returns them as a pin mask.
+    fn take_events(&mut self) -> u64 {
+        let mut pending = 0;
+        for bank in 0..2 {
+            let events = self.registers.GPEDS[bank].get();
+            if events == 0 {
+                continue;
+            }
 
-        self.registers.GPFSEL1.set(selector);
+            // A level that is still present would set the status bit again right away.
+            for level in [&self.registers.GPHEN[bank], &self.registers.GPLEN[bank]] {
+                level.set(level.get() & !events);
+            }
+            self.registers.GPEDS[bank].set(events);
+            pending |= (events as u64) << (bank * 32);
+        }
+
+        for pin in (0..NUM_PINS).filter(|pin| pending & (1 << pin) != 0) {
+            self.event_counts[pin] = self.event_counts[pin].wrapping_add(1);
+        }
+        pending
     }
 
-    pub fn enable_pin(&self, pin: usize) {
-        self.registers.GPPUD.set(0);
+    fn set_function(&mut self, pin: usize, function: Function) {
+        let register = &self.registers.GPFSEL[pin / 10];
+        let shift = (pin % 10) * 3;
+
+        let mut selector = register.get();
+        selector &= !(0b111 << shift);
+        selector |= (function as u32) << shift;
+        register.set(selector);
+    }
+
+    fn function(&self, pin: usize) -> Function {
+        let shift = (pin % 10) * 3;
+        Function::from_bits((self.registers.GPFSEL[pin / 10].get() >> shift) & 0b111)
+    }
+
+    fn set_level(&mut self, pin: usize, high: bool) {
+        let (bank, bit) = (pin / 32, 1 << (pin % 32));
+        if high {
+            self.registers.GPSET[bank].set(bit);
+        } else {
+            self.registers.GPCLR[bank].set(bit);
+        }
+    }
+
+    fn level(&self, pin: usize) -> bool {
+        self.registers.GPLEV[pin / 32].get() & (1 << (pin % 32)) != 0
+    }
+
+    fn set_pull(&mut self, pin: usize, pull: Pull) {
+        if self.bcm2711 {
+            self.set_pull_bcm2711(pin, pull);
+        } else {
+            self.set_pull_bcm2837(pin, pull);
+        }
+    }
+
+    /// Set the control signal, clock it into the pin, then remove both again.
+    fn set_pull_bcm2837(&mut self, pin: usize, pull: Pull) {
+        let clock = &self.registers.GPPUDCLK[pin / 32];
+
+        self.registers.GPPUD.write(match pull {
+            Pull::Off => GPPUD::PUD::Off,
+            Pull::Down => GPPUD::PUD::PullDown,
+            Pull::Up => GPPUD::PUD::PullUp,
+        });
         spin_for_cycles(2000);
-        self.registers.GPPUDCLK0.set(1 << (pin % 32));
+        clock.set(1 << (pin % 32));
         spin_for_cycles(2000);
-        self.registers.GPPUD.set(0);
-        self.registers.GPPUDCLK0.set(0);
+        self.registers.GPPUD.write(GPPUD::PUD::Off);
+        clock.set(0);
     }
 
-    pub fn init_mini_uart_pins(&self) {
-        self.set_func(14, 2);
-        self.set_func(15, 2);
+    fn set_pull_bcm2711(&mut self, pin: usize, pull: Pull) {
+        let register = &self.registers.GPIO_PUP_PDN_CNTRL[pin / 16];
+        let shift = (pin % 16) * 2;
+        let value = match pull {
+            Pull::Off => 0b00,
+            Pull::Up => 0b01,
+            Pull::Down => 0b10,
+        };
 
-        self.enable_pin(14);
-        self.enable_pin(15);
+        let mut control = register.get();
+        control &= !(0b11 << shift);
+        control |= value << shift;
+        register.set(control);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,421 @@
+//! Broadcom Serial Controller (BSC) I2C masters, BSC0 and BSC1.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://elinux.org/BCM2835_datasheet_errata>
+
+use core::{fmt, time::Duration};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+use crate::bsp::device_driver::common::{
+    clock_divider, with_irqs_masked, AcquireError, BusLock, MMIODerefWrapper,
+};
+use crate::{driver, exception, synchronization::SpinLock, time};
+
+register_bitfields! {
+    u32,
+    C [
+        I2CEN OFFSET(15) NUMBITS(1) [],
+        INTR OFFSET(10) NUMBITS(1) [],
+        INTT OFFSET(9) NUMBITS(1) [],
+        INTD OFFSET(8) NUMBITS(1) [],
+        ST OFFSET(7) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Keep = 0b00,
+            Clear = 0b01,
+        ],
+        READ OFFSET(0) NUMBITS(1) [
+            Write = 0,
+            Read = 1,
+        ],
+    ],
+
+    // CLKT, ERR and DONE are cleared by writing 1.
+    S [
+        CLKT OFFSET(9) NUMBITS(1) [],
+        ERR OFFSET(8) NUMBITS(1) [],
+        RXF OFFSET(7) NUMBITS(1) [],
+        TXE OFFSET(6) NUMBITS(1) [],
+        RXD OFFSET(5) NUMBITS(1) [],
+        TXD OFFSET(4) NUMBITS(1) [],
+        RXR OFFSET(3) NUMBITS(1) [],
+        TXW OFFSET(2) NUMBITS(1) [],
+        DONE OFFSET(1) NUMBITS(1) [],
+        TA OFFSET(0) NUMBITS(1) [],
+    ],
+
+    DLEN [
+        DLEN OFFSET(0) NUMBITS(16) [],
+    ],
+
+    A [
+        ADDR OFFSET(0) NUMBITS(7) [],
+    ],
+
+    FIFO [
+        DATA OFFSET(0) NUMBITS(8) [],
+    ],
+
+    DIV [
+        CDIV OFFSET(0) NUMBITS(16) [],
+    ],
+
+    DEL [
+        FEDL OFFSET(16) NUMBITS(16) [],
+        REDL OFFSET(0) NUMBITS(16) [],
+    ],
+
+    CLKT [
+        TOUT OFFSET(0) NUMBITS(16) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub I2cRegisters {
+        (0x00 => C: ReadWrite<u32, C::Register>),
+        (0x04 => S: ReadWrite<u32, S::Register>),
+        (0x08 => DLEN: ReadWrite<u32, DLEN::Register>),
+        (0x0C => A: ReadWrite<u32, A::Register>),
+        (0x10 => FIFO: ReadWrite<u32, FIFO::Register>),
+        (0x14 => DIV: ReadWrite<u32, DIV::Register>),
+        (0x18 => DEL: ReadWrite<u32, DEL::Register>),
+        (0x1C => CLKT: ReadWrite<u32, CLKT::Register>),
+        (0x20 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<I2cRegisters>;
+
+/// DLEN is 16 bits wide.
+const MAX_TRANSFER: usize = 0xFFFF;
+
+/// What the firmware runs the VPU core clock at unless told otherwise.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// Longest the bus may go without moving a byte before the transfer is abandoned.
+const BYTE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Device address, the 10-bit form is sent as 0b11110xx plus a second address byte.
+#[derive(Copy, Clone, PartialEq)]
+pub enum I2cAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl I2cAddress {
+    /// Address byte for the A register and, for 10-bit addresses, the low byte that has to
+    /// lead the written data.
+    fn encode(&self) -> Result<(u8, Option<u8>), I2cError> {
+        match *self {
+            I2cAddress::SevenBit(addr) if addr <= 0x7F => Ok((addr, None)),
+            I2cAddress::TenBit(addr) if addr <= 0x3FF => Ok((0x78 | (addr >> 8) as u8, Some(addr as u8))),
+            _ => Err(I2cError::InvalidAddress),
+        }
+    }
+}
+
+impl fmt::Display for I2cAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cAddress::SevenBit(addr) => write!(f, "0x{:02x}", addr),
+            I2cAddress::TenBit(addr) => write!(f, "0x{:03x} (10-bit)", addr),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum I2cError {
+    /// The device didn't acknowledge its address or a byte written to it.
+    Nack,
+    /// A device held SCL low for longer than the clock-stretch timeout.
+    ClockStretchTimeout,
+    /// The transfer stopped making progress.
+    Timeout,
+    InvalidAddress,
+    /// More than DLEN can count in one direction.
+    TooLong,
+    /// Waiting for the bus was interrupted.
+    Interrupted,
+    /// The bus is taken and can't be waited for.
+    Busy,
+}
+
+impl I2cError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            I2cError::Nack => "no acknowledge",
+            I2cError::ClockStretchTimeout => "clock stretch timeout",
+            I2cError::Timeout => "transfer timed out",
+            I2cError::InvalidAddress => "invalid address",
+            I2cError::TooLong => "transfer too long",
+            I2cError::Interrupted => "interrupted",
+            I2cError::Busy => "bus busy",
+        }
+    }
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<I2cError> for &'static str {
+    fn from(error: I2cError) -> Self {
+        error.as_str()
+    }
+}
+
+impl From<AcquireError> for I2cError {
+    fn from(error: AcquireError) -> Self {
+        match error {
+            AcquireError::Interrupted => I2cError::Interrupted,
+            AcquireError::Busy => I2cError::Busy,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct I2cSettings {
+    pub speed_hz: u32,
+    /// How long a device may stretch the clock, 0 waits forever.
+    pub stretch_timeout_ms: u32,
+}
+
+impl I2cSettings {
+    /// Standard mode, with the stretch timeout Linux uses.
+    pub const DEFAULT: Self = Self {
+        speed_hz: 100_000,
+        stretch_timeout_ms: 35,
+    };
+}
+
+impl fmt::Display for I2cSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz, clock stretch timeout ", self.speed_hz)?;
+        match self.stretch_timeout_ms {
+            0 => write!(f, "off"),
+            ms => write!(f, "{} ms", ms),
+        }
+    }
+}
+
+pub struct I2c {
+    mmio_start_addr: usize,
+    registers: Registers,
+    settings: SpinLock<I2cSettings>,
+    /// Held for a whole transaction, which runs with IRQs unmasked.
+    bus: BusLock,
+}
+
+impl I2c {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-i2c";
+
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            mmio_start_addr,
+            registers: Registers::new(mmio_start_addr),
+            settings: SpinLock::new(I2cSettings::DEFAULT),
+            bus: BusLock::new(),
+        }
+    }
+
+    /// Reprograms the bus clock and the clock-stretch timeout. The divider runs off the VPU core
+    /// clock, `core_clock` in Hz.
+    pub fn configure(&self, settings: &I2cSettings, core_clock: u32) -> Result<(), &'static str> {
+        let divider = clock_divider(settings.speed_hz, core_clock)?;
+        self.bus.acquire()?;
+        self.program(settings, divider);
+        self.bus.release();
+        Ok(())
+    }
+
+    pub fn settings(&self) -> I2cSettings {
+        with_irqs_masked(&self.settings, |settings| *settings)
+    }
+
+    pub fn write(&self, addr: I2cAddress, tx: &[u8]) -> Result<(), I2cError> {
+        self.write_read(addr, tx, &mut [])
+    }
+
+    pub fn read(&self, addr: I2cAddress, rx: &mut [u8]) -> Result<(), I2cError> {
+        self.write_read(addr, &[], rx)
+    }
+
+    /// Writes `tx`, then reads `rx` after a repeated start, without releasing the bus in
+    /// between. Either may be empty, both empty probes for the device with a bare address.
+    pub fn write_read(&self, addr: I2cAddress, tx: &[u8], rx: &mut [u8]) -> Result<(), I2cError> {
+        self.bus.acquire()?;
+        let result = self.transaction(addr, tx, rx);
+        self.bus.release();
+        result
+    }
+
+    fn program(&self, settings: &I2cSettings, divider: u32) {
+        with_irqs_masked(&self.settings, |current| *current = *settings);
+
+        // Data changes a sixteenth of a clock period after each edge, as Linux sets it up.
+        let delay = (divider / 16).max(1);
+        let stretch = settings.speed_hz as u64 * settings.stretch_timeout_ms as u64 / 1000;
+
+        self.registers.C.write(C::I2CEN::CLEAR);
+        self.registers.DIV.write(DIV::CDIV.val(divider));
+        self.registers.DEL.write(DEL::FEDL.val(delay) + DEL::REDL.val(delay));
+        self.registers.CLKT.write(CLKT::TOUT.val(stretch.min(0xFFFF) as u32));
+    }
+
+    fn transaction(&self, addr: I2cAddress, tx: &[u8], rx: &mut [u8]) -> Result<(), I2cError> {
+        let (addr_byte, prefix) = addr.encode()?;
+        let write_len = prefix.is_some() as usize + tx.len();
+        if write_len > MAX_TRANSFER || rx.len() > MAX_TRANSFER {
+            return Err(I2cError::TooLong);
+        }
+
+        self.registers.S.write(S::CLKT::SET + S::ERR::SET + S::DONE::SET);
+        self.registers.C.write(C::I2CEN::SET + C::CLEAR::Clear);
+        self.registers.A.write(A::ADDR.val(addr_byte as u32));
+
+        let mut masked = None;
+        let bytes = prefix.into_iter().chain(tx.iter().copied());
+        let result = self.write_phase(bytes, write_len, !rx.is_empty(), &mut masked);
+        let result = result.and_then(|_| self.read_phase(rx, &mut masked));
+        if let Some(daif) = masked {
+            exception::irq_restore(daif);
+        }
+        if result.is_err() {
+            // The controller has already sent a stop, just drop whatever is left in the FIFO.
+            self.registers.C.write(C::I2CEN::SET + C::CLEAR::Clear);
+            self.registers.S.write(S::CLKT::SET + S::ERR::SET + S::DONE::SET);
+        }
+        result
+    }
+
+    /// Sends `len` bytes. With `read_follows` it returns as soon as everything is queued, the
+    /// read has to be started while the write is still going for a repeated start. Being
+    /// scheduled away in that window would end the write with a stop instead, so IRQs are
+    /// masked from the last byte queued on, with the state to restore left in `masked`.
+    fn write_phase(
+        &self,
+        mut bytes: impl Iterator<Item = u8>,
+        len: usize,
+        read_follows: bool,
+        masked: &mut Option<usize>,
+    ) -> Result<(), I2cError> {
+        if len == 0 && read_follows {
+            return Ok(());
+        }
+
+        self.registers.DLEN.write(DLEN::DLEN.val(len as u32));
+        let mut pending = len;
+        // Preloading the FIFO saves the first byte from racing the address.
+        while pending > 0 && self.registers.S.is_set(S::TXD) {
+            self.registers.FIFO.write(FIFO::DATA.val(bytes.next().unwrap_or(0) as u32));
+            pending -= 1;
+        }
+        if pending == 0 && read_follows {
+            *masked = Some(exception::irq_save());
+        }
+        self.registers.C.write(C::I2CEN::SET + C::ST::SET + C::READ::Write);
+
+        let mut deadline = time::time_manager().uptime() + BYTE_TIMEOUT;
+        while pending > 0 {
+            self.check_errors()?;
+            if self.registers.S.is_set(S::TXD) {
+                if pending == 1 && read_follows {
+                    *masked = Some(exception::irq_save());
+                }
+                self.registers.FIFO.write(FIFO::DATA.val(bytes.next().unwrap_or(0) as u32));
+                pending -= 1;
+                deadline = time::time_manager().uptime() + BYTE_TIMEOUT;
+            } else if time::time_manager().uptime() > deadline {
+                return Err(I2cError::Timeout);
+            }
+        }
+
+        if read_follows {
+            // ST is only taken as a repeated start once the write has begun.
+            self.spin_until(|s| s.is_set(S::TA) || s.is_set(S::DONE))
+        } else {
+            self.wait_done()
+        }
+    }
+
+    /// Reads `rx`, unmasking IRQs again once the read has started if the write phase masked
+    /// them.
+    fn read_phase(&self, rx: &mut [u8], masked: &mut Option<usize>) -> Result<(), I2cError> {
+        if rx.is_empty() {
+            return Ok(());
+        }
+
+        self.registers.DLEN.write(DLEN::DLEN.val(rx.len() as u32));
+        self.registers.C.write(C::I2CEN::SET + C::ST::SET + C::READ::Read);
+        if let Some(daif) = masked.take() {
+            exception::irq_restore(daif);
+        }
+
+        let mut received = 0;
+        let mut deadline = time::time_manager().uptime() + BYTE_TIMEOUT;
+        while received < rx.len() {
+            self.check_errors()?;
+            if self.registers.S.is_set(S::RXD) {
+                rx[received] = self.registers.FIFO.read(FIFO::DATA) as u8;
+                received += 1;
+                deadline = time::time_manager().uptime() + BYTE_TIMEOUT;
+            } else if time::time_manager().uptime() > deadline {
+                return Err(I2cError::Timeout);
+            }
+        }
+        self.wait_done()
+    }
+
+    fn wait_done(&self) -> Result<(), I2cError> {
+        self.spin_until(|s| s.is_set(S::DONE))?;
+        self.check_errors()?;
+        self.registers.S.write(S::DONE::SET);
+        Ok(())
+    }
+
+    fn spin_until(&self, condition: impl Fn(&ReadWrite<u32, S::Register>) -> bool) -> Result<(), I2cError> {
+        let deadline = time::time_manager().uptime() + BYTE_TIMEOUT;
+        while !condition(&self.registers.S) {
+            self.check_errors()?;
+            if time::time_manager().uptime() > deadline {
+                return Err(I2cError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_errors(&self) -> Result<(), I2cError> {
+        let status = self.registers.S.extract();
+        if status.is_set(S::ERR) {
+            Err(I2cError::Nack)
+        } else if status.is_set(S::CLKT) {
+            Err(I2cError::ClockStretchTimeout)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl driver::interface::DeviceDriver for I2c {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    fn mmio_start_addr(&self) -> Option<usize> {
+        Some(self.mmio_start_addr)
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let divider = clock_divider(I2cSettings::DEFAULT.speed_hz, DEFAULT_CORE_CLOCK)?;
+        self.program(&I2cSettings::DEFAULT, divider);
+        Ok(())
+    }
+}
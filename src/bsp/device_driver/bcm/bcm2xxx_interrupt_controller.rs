@@ -0,0 +1,142 @@
+use alloc::vec::Vec;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::{driver, exception, synchronization::{interface::Mutex, SpinLock}};
+
+// Peripheral (GPU) interrupts 0-63. The "basic" ARM interrupts aren't used.
+register_structs! {
+    #[allow(non_snake_case)]
+    pub InterruptControllerRegisters {
+        (0x00 => IRQ_BASIC_PENDING: ReadOnly<u32>),
+        (0x04 => IRQ_PENDING_1: ReadOnly<u32>),
+        (0x08 => IRQ_PENDING_2: ReadOnly<u32>),
+        (0x0C => FIQ_CONTROL: ReadWrite<u32>),
+        (0x10 => ENABLE_IRQS_1: WriteOnly<u32>),
+        (0x14 => ENABLE_IRQS_2: WriteOnly<u32>),
+        (0x18 => ENABLE_BASIC_IRQS: WriteOnly<u32>),
+        (0x1C => DISABLE_IRQS_1: WriteOnly<u32>),
+        (0x20 => DISABLE_IRQS_2: WriteOnly<u32>),
+        (0x24 => DISABLE_BASIC_IRQS: WriteOnly<u32>),
+        (0x28 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<InterruptControllerRegisters>;
+
+pub const NUM_IRQS: usize = 64;
+
+#[derive(Copy, Clone)]
+struct IrqHandlerDescriptor {
+    irq: usize,
+    name: &'static str,
+    handler: &'static (dyn exception::interface::IrqHandler + Sync),
+}
+
+pub struct InterruptController {
+    inner: SpinLock<InterruptControllerInner>,
+}
+
+impl InterruptController {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2836-armctrl-ic";
+
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: SpinLock::new(InterruptControllerInner::new(mmio_start_addr)),
+        }
+    }
+
+    /// Adds `handler` for peripheral interrupt `irq` and unmasks it. Several handlers may share
+    /// one line, each is expected to check whether its device raised it.
+    pub fn register_handler(
+        &self,
+        irq: usize,
+        name: &'static str,
+        handler: &'static (dyn exception::interface::IrqHandler + Sync),
+    ) -> Result<(), &'static str> {
+        let daif = exception::irq_save();
+        let result = self.inner.lock().unwrap().register_handler(IrqHandlerDescriptor { irq, name, handler });
+        exception::irq_restore(daif);
+        result
+    }
+
+    /// Runs the handlers of every pending peripheral interrupt. Called in IRQ context.
+    pub fn handle_pending_irqs(&self) {
+        let inner = self.inner.lock().unwrap();
+        inner.handle_pending_irqs();
+    }
+
+    pub fn print_handlers(&self) {
+        let daif = exception::irq_save();
+        let handlers = self.inner.lock().unwrap().handlers.clone();
+        exception::irq_restore(daif);
+
+        for descriptor in handlers.iter() {
+            crate::println!("  {:>3}: {}", descriptor.irq, descriptor.name);
+        }
+    }
+}
+
+impl driver::interface::DeviceDriver for InterruptController {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.init();
+        Ok(())
+    }
+}
+
+struct InterruptControllerInner {
+    registers: Registers,
+    handlers: Vec<IrqHandlerDescriptor>,
+    initialized: bool,
+}
+
+impl InterruptControllerInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            handlers: Vec::new(),
+            initialized: false,
+        }
+    }
+
+    fn init(&mut self) {
+        self.registers.DISABLE_IRQS_1.set(u32::MAX);
+        self.registers.DISABLE_IRQS_2.set(u32::MAX);
+        self.registers.DISABLE_BASIC_IRQS.set(u32::MAX);
+        self.initialized = true;
+    }
+
+    fn register_handler(&mut self, descriptor: IrqHandlerDescriptor) -> Result<(), &'static str> {
+        if !self.initialized {
+            return Err("interrupt controller not initialized");
+        }
+        if descriptor.irq >= NUM_IRQS {
+            return Err("invalid IRQ number");
+        }
+
+        self.handlers.push(descriptor);
+        match descriptor.irq {
+            irq @ 0..=31 => self.registers.ENABLE_IRQS_1.set(1 << irq),
+            irq => self.registers.ENABLE_IRQS_2.set(1 << (irq - 32)),
+        }
+        Ok(())
+    }
+
+    fn handle_pending_irqs(&self) {
+        let pending =
+            self.registers.IRQ_PENDING_1.get() as u64 | (self.registers.IRQ_PENDING_2.get() as u64) << 32;
+
+        for descriptor in self.handlers.iter().filter(|d| pending & (1 << d.irq) != 0) {
+            descriptor.handler.handle();
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! VideoCore mailbox, property channel only, with typed helpers for the common tags.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface>
+
+use core::fmt;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, WriteOnly},
+};
+
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::{driver, exception, memory::mmu, synchronization::{interface::Mutex, SpinLock}};
+
+register_bitfields! {
+    u32,
+
+    STATUS [
+        EMPTY OFFSET(30) NUMBITS(1) [],
+        FULL OFFSET(31) NUMBITS(1) [],
+    ],
+}
+
+// Mailbox 0 carries messages from the VideoCore, mailbox 1 messages to it.
+register_structs! {
+    #[allow(non_snake_case)]
+    pub MailboxRegisters {
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => STATUS0: ReadOnly<u32, STATUS::Register>),
+        (0x1C => _reserved2),
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => _reserved3),
+        (0x38 => STATUS1: ReadOnly<u32, STATUS::Register>),
+        (0x3C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<MailboxRegisters>;
+
+const PROPERTY_CHANNEL: u32 = 8;
+
+const REQUEST_CODE: u32 = 0;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const TAG_RESPONSE: u32 = 1 << 31;
+const END_TAG: u32 = 0;
+
+const TAG_GET_FIRMWARE_REVISION: u32 = 0x0000_0001;
+const TAG_GET_BOARD_MODEL: u32 = 0x0001_0001;
+const TAG_GET_BOARD_REVISION: u32 = 0x0001_0002;
+const TAG_GET_MAC_ADDRESS: u32 = 0x0001_0003;
+const TAG_GET_BOARD_SERIAL: u32 = 0x0001_0004;
+const TAG_GET_ARM_MEMORY: u32 = 0x0001_0005;
+const TAG_GET_VC_MEMORY: u32 = 0x0001_0006;
+const TAG_GET_POWER_STATE: u32 = 0x0002_0001;
+const TAG_SET_POWER_STATE: u32 = 0x0002_8001;
+const TAG_GET_CLOCK_RATE: u32 = 0x0003_0002;
+const TAG_GET_MAX_CLOCK_RATE: u32 = 0x0003_0004;
+const TAG_GET_TEMPERATURE: u32 = 0x0003_0006;
+const TAG_GET_MAX_TEMPERATURE: u32 = 0x0003_000A;
+
+const POWER_ON: u32 = 1 << 0;
+const POWER_WAIT: u32 = 1 << 1;
+const POWER_NO_DEVICE: u32 = 1 << 1;
+
+/// Message header and end tag words around the tags.
+const MESSAGE_OVERHEAD_WORDS: usize = 3;
+/// Tag id, value buffer size and request/response code ahead of each tag's values.
+const TAG_OVERHEAD_WORDS: usize = 3;
+const BUFFER_WORDS: usize = 64;
+
+/// Polls before giving up on the VideoCore.
+const TIMEOUT_SPINS: usize = 10_000_000;
+
+/// The VideoCore sees ARM memory through the uncached bus alias.
+const BUS_ALIAS: u32 = 0xC000_0000;
+
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum ClockId {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+}
+
+impl ClockId {
+    pub const ALL: [ClockId; 10] = [
+        ClockId::Emmc,
+        ClockId::Uart,
+        ClockId::Arm,
+        ClockId::Core,
+        ClockId::V3d,
+        ClockId::H264,
+        ClockId::Isp,
+        ClockId::Sdram,
+        ClockId::Pixel,
+        ClockId::Pwm,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClockId::Emmc => "emmc",
+            ClockId::Uart => "uart",
+            ClockId::Arm => "arm",
+            ClockId::Core => "core",
+            ClockId::V3d => "v3d",
+            ClockId::H264 => "h264",
+            ClockId::Isp => "isp",
+            ClockId::Sdram => "sdram",
+            ClockId::Pixel => "pixel",
+            ClockId::Pwm => "pwm",
+        }
+    }
+}
+
+/// Devices the firmware can power up and down.
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum PowerDevice {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+impl PowerDevice {
+    pub const ALL: [PowerDevice; 9] = [
+        PowerDevice::SdCard,
+        PowerDevice::Uart0,
+        PowerDevice::Uart1,
+        PowerDevice::UsbHcd,
+        PowerDevice::I2c0,
+        PowerDevice::I2c1,
+        PowerDevice::I2c2,
+        PowerDevice::Spi,
+        PowerDevice::Ccp2tx,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PowerDevice::SdCard => "sdcard",
+            PowerDevice::Uart0 => "uart0",
+            PowerDevice::Uart1 => "uart1",
+            PowerDevice::UsbHcd => "usb",
+            PowerDevice::I2c0 => "i2c0",
+            PowerDevice::I2c1 => "i2c1",
+            PowerDevice::I2c2 => "i2c2",
+            PowerDevice::Spi => "spi",
+            PowerDevice::Ccp2tx => "ccp2tx",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|device| device.name() == name)
+    }
+}
+
+/// The board revision code, decoded as described at
+/// <https://www.raspberrypi.com/documentation/computers/raspberry-pi.html#raspberry-pi-revision-codes>.
+#[derive(Copy, Clone)]
+pub struct BoardRevision(pub u32);
+
+impl BoardRevision {
+    /// Old-style codes are a plain number that only identifies the original Pi 1 boards.
+    fn new_style(&self) -> bool {
+        self.0 & (1 << 23) != 0
+    }
+
+    pub fn model(&self) -> &'static str {
+        if !self.new_style() {
+            return "1";
+        }
+        match (self.0 >> 4) & 0xFF {
+            0x00 => "A",
+            0x01 => "B",
+            0x02 => "A+",
+            0x03 => "B+",
+            0x04 => "2B",
+            0x06 => "CM1",
+            0x08 => "3B",
+            0x09 => "Zero",
+            0x0A => "CM3",
+            0x0C => "Zero W",
+            0x0D => "3B+",
+            0x0E => "3A+",
+            0x10 => "CM3+",
+            0x11 => "4B",
+            0x12 => "Zero 2 W",
+            0x13 => "400",
+            0x14 => "CM4",
+            _ => "unknown",
+        }
+    }
+
+    pub fn processor(&self) -> &'static str {
+        if !self.new_style() {
+            return "BCM2835";
+        }
+        match (self.0 >> 12) & 0xF {
+            0 => "BCM2835",
+            1 => "BCM2836",
+            2 => "BCM2837",
+            3 => "BCM2711",
+            _ => "unknown",
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.0 & 0xF
+    }
+
+    pub fn memory_mb(&self) -> Option<u32> {
+        self.new_style().then(|| 256 << ((self.0 >> 20) & 0x7))
+    }
+}
+
+impl fmt::Display for BoardRevision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Raspberry Pi {} rev 1.{}, {}", self.model(), self.revision(), self.processor())?;
+        if let Some(mb) = self.memory_mb() {
+            write!(f, ", {} MB", mb)?;
+        }
+        write!(f, " ({:06x})", self.0)
+    }
+}
+
+/// A region of memory as the firmware reports it.
+#[derive(Copy, Clone)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+
+#[repr(C, align(16))]
+struct PropertyBuffer([u32; BUFFER_WORDS]);
+
+pub struct Mailbox {
+    inner: SpinLock<MailboxInner>,
+}
+
+impl Mailbox {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-mbox";
+
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: SpinLock::new(MailboxInner::new(mmio_start_addr)),
+        }
+    }
+
+    /// Sends a single property tag and copies its response values into `response`.
+    pub fn property(&self, tag: u32, request: &[u32], response: &mut [u32]) -> Result<(), &'static str> {
+        let daif = exception::irq_save();
+        let result = self.inner.lock().unwrap().property(tag, request, response);
+        exception::irq_restore(daif);
+        result
+    }
+
+    /// Sends several tags in one message, for requests the firmware only honours together.
+    /// Each tag's values go out as its request and are replaced by its response.
+    pub fn properties(&self, tags: &mut [(u32, &mut [u32])]) -> Result<(), &'static str> {
+        let daif = exception::irq_save();
+        let result = self.inner.lock().unwrap().properties(tags);
+        exception::irq_restore(daif);
+        result
+    }
+
+    fn get<const N: usize>(&self, tag: u32, request: &[u32]) -> Result<[u32; N], &'static str> {
+        let mut response = [0; N];
+        self.property(tag, request, &mut response)?;
+        Ok(response)
+    }
+
+    pub fn firmware_revision(&self) -> Result<u32, &'static str> {
+        self.get::<1>(TAG_GET_FIRMWARE_REVISION, &[]).map(|r| r[0])
+    }
+
+    pub fn board_model(&self) -> Result<u32, &'static str> {
+        self.get::<1>(TAG_GET_BOARD_MODEL, &[]).map(|r| r[0])
+    }
+
+    pub fn board_revision(&self) -> Result<BoardRevision, &'static str> {
+        self.get::<1>(TAG_GET_BOARD_REVISION, &[]).map(|r| BoardRevision(r[0]))
+    }
+
+    pub fn board_serial(&self) -> Result<u64, &'static str> {
+        self.get::<2>(TAG_GET_BOARD_SERIAL, &[]).map(|r| (r[1] as u64) << 32 | r[0] as u64)
+    }
+
+    pub fn mac_address(&self) -> Result<[u8; 6], &'static str> {
+        let words = self.get::<2>(TAG_GET_MAC_ADDRESS, &[])?;
+        let mut mac = [0; 6];
+        for (i, b) in mac.iter_mut().enumerate() {
+            *b = (words[i / 4] >> (8 * (i % 4))) as u8;
+        }
+        Ok(mac)
+    }
+
+    /// Memory left to the ARM cores, the VideoCore owns everything above it.
+    pub fn arm_memory(&self) -> Result<MemoryRegion, &'static str> {
+        self.get::<2>(TAG_GET_ARM_MEMORY, &[]).map(|r| MemoryRegion { base: r[0], size: r[1] })
+    }
+
+    pub fn vc_memory(&self) -> Result<MemoryRegion, &'static str> {
+        self.get::<2>(TAG_GET_VC_MEMORY, &[]).map(|r| MemoryRegion { base: r[0], size: r[1] })
+    }
+
+    /// Current rate of `clock` in Hz.
+    pub fn clock_rate(&self, clock: ClockId) -> Result<u32, &'static str> {
+        match self.get::<2>(TAG_GET_CLOCK_RATE, &[clock as u32])?[1] {
+            0 => Err("clock not found"),
+            rate => Ok(rate),
+        }
+    }
+
+    /// Highest rate the firmware lets `clock` run at, in Hz.
+    pub fn max_clock_rate(&self, clock: ClockId) -> Result<u32, &'static str> {
+        match self.get::<2>(TAG_GET_MAX_CLOCK_RATE, &[clock as u32])?[1] {
+            0 => Err("clock not found"),
+            rate => Ok(rate),
+        }
+    }
+
+    /// SoC temperature in thousandths of a degree Celsius.
+    pub fn temperature(&self) -> Result<u32, &'static str> {
+        self.get::<2>(TAG_GET_TEMPERATURE, &[0]).map(|r| r[1])
+    }
+
+    /// Temperature at which the firmware starts throttling, in thousandths of a degree Celsius.
+    pub fn max_temperature(&self) -> Result<u32, &'static str> {
+        self.get::<2>(TAG_GET_MAX_TEMPERATURE, &[0]).map(|r| r[1])
+    }
+
+    pub fn power_state(&self, device: PowerDevice) -> Result<bool, &'static str> {
+        let state = self.get::<2>(TAG_GET_POWER_STATE, &[device as u32])?[1];
+        if state & POWER_NO_DEVICE != 0 {
+            return Err("no such device");
+        }
+        Ok(state & POWER_ON != 0)
+    }
+
+    /// Powers `device` up or down, waiting for it to settle. Returns the new state.
+    pub fn set_power_state(&self, device: PowerDevice, on: bool) -> Result<bool, &'static str> {
+        let state = if on { POWER_ON | POWER_WAIT } else { POWER_WAIT };
+        let state = self.get::<2>(TAG_SET_POWER_STATE, &[device as u32, state])?[1];
+        if state & POWER_NO_DEVICE != 0 {
+            return Err("no such device");
+        }
+        Ok(state & POWER_ON != 0)
+    }
+}
+
+impl driver::interface::DeviceDriver for Mailbox {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+}
+
+struct MailboxInner {
+    registers: Registers,
+    buffer: PropertyBuffer,
+}
+
+impl MailboxInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            buffer: PropertyBuffer([0; BUFFER_WORDS]),
+        }
+    }
+
+    fn property(&mut self, tag: u32, request: &[u32], response: &mut [u32]) -> Result<(), &'static str> {
+        let mut values = [0; BUFFER_WORDS];
+        let value_words = request.len().max(response.len());
+        if value_words > BUFFER_WORDS {
+            return Err("property message too large");
+        }
+
+        values[..request.len()].copy_from_slice(request);
+        self.properties(&mut [(tag, &mut values[..value_words])])?;
+        response.copy_from_slice(&values[..response.len()]);
+        Ok(())
+    }
+
+    fn properties(&mut self, tags: &mut [(u32, &mut [u32])]) -> Result<(), &'static str> {
+        let message_words = tags.iter().map(|(_, values)| values.len() + TAG_OVERHEAD_WORDS).sum::<usize>()
+            + MESSAGE_OVERHEAD_WORDS;
+        if message_words > BUFFER_WORDS {
+            return Err("property message too large");
+        }
+
+        let buf = &mut self.buffer.0;
+        buf[..message_words].fill(0);
+        buf[0] = (message_words * 4) as u32;
+        buf[1] = REQUEST_CODE;
+        let mut idx = 2;
+        for (tag, values) in tags.iter() {
+            buf[idx] = *tag;
+            buf[idx + 1] = (values.len() * 4) as u32;
+            buf[idx + 2] = REQUEST_CODE;
+            buf[idx + 3..idx + 3 + values.len()].copy_from_slice(values);
+            idx += TAG_OVERHEAD_WORDS + values.len();
+        }
+        buf[idx] = END_TAG;
+
+        self.call()?;
+
+        let buf = &self.buffer.0;
+        if buf[1] != RESPONSE_SUCCESS {
+            return Err("property request failed");
+        }
+        let mut idx = 2;
+        for (_, values) in tags.iter_mut() {
+            let status = buf[idx + 2];
+            if status & TAG_RESPONSE == 0 {
+                return Err("property request failed");
+            }
+            // The firmware reports how much it would have written, which may exceed the buffer.
+            if ((status & !TAG_RESPONSE) as usize).div_ceil(4) < values.len() {
+                return Err("property response too short");
+            }
+            values.copy_from_slice(&buf[idx + 3..idx + 3 + values.len()]);
+            idx += TAG_OVERHEAD_WORDS + values.len();
+        }
+        Ok(())
+    }
+
+    /// Hands the buffer to the VideoCore and waits for it to come back.
+    fn call(&mut self) -> Result<(), &'static str> {
+        let addr = self.buffer.0.as_ptr() as usize;
+        let message = (addr as u32 | BUS_ALIAS) | PROPERTY_CHANNEL;
+
+        mmu::clean_invalidate_dcache_range(addr, BUFFER_WORDS * 4);
+
+        self.spin_while(|r| r.STATUS1.is_set(STATUS::FULL))?;
+        self.registers.WRITE.set(message);
+
+        loop {
+            self.spin_while(|r| r.STATUS0.is_set(STATUS::EMPTY))?;
+            // Replies for other channels aren't ours to handle.
+            if self.registers.READ.get() == message {
+                break;
+            }
+        }
+
+        mmu::clean_invalidate_dcache_range(addr, BUFFER_WORDS * 4);
+        Ok(())
+    }
+
+    fn spin_while(&self, condition: impl Fn(&MailboxRegisters) -> bool) -> Result<(), &'static str> {
+        for _ in 0..TIMEOUT_SPINS {
+            if !condition(&self.registers) {
+                return Ok(());
+            }
+            aarch64_cpu::asm::nop();
+        }
+        Err("mailbox timeout")
+    }
+}
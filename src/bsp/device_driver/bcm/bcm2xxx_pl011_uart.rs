@@ -9,14 +9,16 @@
 //! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
 //! - <https://developer.arm.com/documentation/ddi0183/latest>
 
-
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
     registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
-use super::common::MMIODerefWrapper;
+use crate::bsp::device_driver::common::{
+    BufferedUart, LineSettings, MMIODerefWrapper, Parity, UartRegisters, UartStats,
+};
+use crate::driver;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -28,6 +30,25 @@ use super::common::MMIODerefWrapper;
 register_bitfields! {
     u32,
 
+    /// Data Register.
+    DR [
+        /// Overrun error. Set if data is received and the receive FIFO is already full.
+        OE OFFSET(11) NUMBITS(1) [],
+
+        /// Break error. Set if a break condition was detected.
+        BE OFFSET(10) NUMBITS(1) [],
+
+        /// Parity error. Set if the parity of the received character does not match the parity
+        /// selected by EPS and SPS in LCR_H.
+        PE OFFSET(9) NUMBITS(1) [],
+
+        /// Framing error. Set if the received character did not have a valid stop bit.
+        FE OFFSET(8) NUMBITS(1) [],
+
+        /// Data character.
+        DATA OFFSET(0) NUMBITS(8) []
+    ],
+
     /// Flag Register.
     FR [
         /// Transmit FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
@@ -58,7 +79,11 @@ register_bitfields! {
         ///
         /// This bit is set as soon as the transmit FIFO becomes non-empty, regardless of whether
         /// the UART is enabled or not.
-        BUSY OFFSET(3) NUMBITS(1) []
+        BUSY OFFSET(3) NUMBITS(1) [],
+
+        /// Clear to send. This bit is the complement of the UART clear to send, nUARTCTS, modem
+        /// status input.
+        CTS OFFSET(0) NUMBITS(1) []
     ],
 
     /// Integer Baud Rate Divisor.
@@ -94,11 +119,41 @@ register_bitfields! {
         FEN  OFFSET(4) NUMBITS(1) [
             FifosDisabled = 0,
             FifosEnabled = 1
+        ],
+
+        /// Two stop bits select. If this bit is set to 1, two stop bits are transmitted at the end
+        /// of the frame.
+        STP2 OFFSET(3) NUMBITS(1) [],
+
+        /// Even parity select. 0 = odd parity, 1 = even parity. No effect unless PEN is set.
+        EPS OFFSET(2) NUMBITS(1) [
+            Odd = 0,
+            Even = 1
+        ],
+
+        /// Parity enable.
+        PEN OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
         ]
     ],
 
     /// Control Register.
     CR [
+        /// CTS hardware flow control enable. If this bit is set to 1, data is only transmitted
+        /// when the nUARTCTS signal is asserted.
+        CTSEN OFFSET(15) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// RTS hardware flow control enable. If this bit is set to 1, data is only requested when
+        /// there is space in the receive FIFO for it to be received.
+        RTSEN OFFSET(14) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
         /// Receive enable. If this bit is set to 1, the receive section of the UART is enabled.
         /// Data reception occurs for either UART signals or SIR signals depending on the setting of
         /// the SIREN bit. When the UART is disabled in the middle of reception, it completes the
@@ -132,9 +187,53 @@ register_bitfields! {
         ]
     ],
 
-    /// Interrupt Clear Register.
-    ICR [
-        /// Meta field for all pending interrupts.
+    /// Interrupt FIFO Level Select Register.
+    IFLS [
+        /// Receive interrupt FIFO level select.
+        RXIFLSEL OFFSET(3) NUMBITS(3) [
+            OneEighth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEighths = 0b100
+        ],
+
+        /// Transmit interrupt FIFO level select.
+        TXIFLSEL OFFSET(0) NUMBITS(3) [
+            OneEighth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEighths = 0b100
+        ]
+    ],
+
+    /// Interrupt Mask Set/Clear, Raw Interrupt Status, Masked Interrupt Status and Interrupt
+    /// Clear Register all share this layout.
+    INT [
+        /// Overrun error.
+        OE OFFSET(10) NUMBITS(1) [],
+
+        /// Break error.
+        BE OFFSET(9) NUMBITS(1) [],
+
+        /// Parity error.
+        PE OFFSET(8) NUMBITS(1) [],
+
+        /// Framing error.
+        FE OFFSET(7) NUMBITS(1) [],
+
+        /// Receive timeout. Asserted when the receive FIFO is not empty and no more data is
+        /// received during a 32-bit period.
+        RT OFFSET(6) NUMBITS(1) [],
+
+        /// Transmit. Asserted when the transmit FIFO level drops to the TXIFLSEL trigger level.
+        TX OFFSET(5) NUMBITS(1) [],
+
+        /// Receive. Asserted when the receive FIFO level reaches the RXIFLSEL trigger level.
+        RX OFFSET(4) NUMBITS(1) [],
+
+        /// Meta field for all interrupts.
         ALL OFFSET(0) NUMBITS(11) []
     ]
 }
@@ -142,76 +241,196 @@ register_bitfields! {
 register_structs! {
     #[allow(non_snake_case)]
     pub RegisterBlock {
-        (0x00 => DR: ReadWrite<u32>),
+        (0x00 => DR: ReadWrite<u32, DR::Register>),
         (0x04 => _reserved1),
         (0x18 => FR: ReadOnly<u32, FR::Register>),
         (0x1c => _reserved2),
         (0x24 => IBRD: WriteOnly<u32, IBRD::Register>),
         (0x28 => FBRD: WriteOnly<u32, FBRD::Register>),
         (0x2c => LCR_H: WriteOnly<u32, LCR_H::Register>),
-        (0x30 => CR: WriteOnly<u32, CR::Register>),
-        (0x34 => _reserved3),
-        (0x44 => ICR: WriteOnly<u32, ICR::Register>),
+        (0x30 => CR: ReadWrite<u32, CR::Register>),
+        (0x34 => IFLS: ReadWrite<u32, IFLS::Register>),
+        (0x38 => IMSC: ReadWrite<u32, INT::Register>),
+        (0x3C => RIS: ReadOnly<u32, INT::Register>),
+        (0x40 => MIS: ReadOnly<u32, INT::Register>),
+        (0x44 => ICR: WriteOnly<u32, INT::Register>),
         (0x48 => @END),
     }
 }
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-pub struct PL011Uart {
-    registers: Registers,
-}
+/// Reference clock the firmware sets up by default (`init_uart_clock`).
+const DEFAULT_UART_CLOCK: u32 = 48_000_000;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub type PL011Uart = BufferedUart<Registers>;
 
 impl PL011Uart {
+    pub const COMPATIBLE: &'static str = "arm,pl011";
+
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
-        Self {
-            registers: Registers::new(mmio_start_addr),
+        Self::with_registers(Registers::new(mmio_start_addr))
+    }
+
+    /// Reprograms baud rate, frame format and flow control. `uart_clock` is the rate of the UART
+    /// reference clock in Hz.
+    pub fn configure(&self, settings: &LineSettings, uart_clock: u32) -> Result<(), &'static str> {
+        if !(5..=8).contains(&settings.data_bits) || !(1..=2).contains(&settings.stop_bits) {
+            return Err("PL011 supports 5 to 8 data bits and 1 or 2 stop bits");
         }
+        let (ibrd, fbrd) = baud_divisors(settings.baud, uart_clock)?;
+
+        self.with_inner(|inner| {
+            // Let everything queued go out at the old rate first, the divisors are only latched
+            // by the LCR_H write.
+            inner.flush();
+            program(&inner.registers, settings, ibrd, fbrd);
+            inner.settings = *settings;
+        });
+        Ok(())
+    }
+}
+
+impl driver::interface::DeviceDriver for PL011Uart {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
     }
-    
-    pub fn init(&self) {
-        self.registers.CR.set(0);
-        
-        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(3));
-        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(16));
-        self.registers
-            .LCR_H
-            .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
-
-        
-        self.registers
-            .CR
-            .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let (ibrd, fbrd) = baud_divisors(LineSettings::DEFAULT.baud, DEFAULT_UART_CLOCK)?;
+        self.with_inner(|inner| {
+            inner.flush();
+            program(&inner.registers, &LineSettings::DEFAULT, ibrd, fbrd);
+            inner.settings = LineSettings::DEFAULT;
+        });
+        Ok(())
     }
-    
-    pub fn write_char(&self, c: char) {
-        if c == '\r' {
-            self.write_char('\n');
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl UartRegisters for Registers {
+    const NAME: &'static str = "PL011 UART";
+    const LINE_ERRORS: bool = true;
+
+    fn receive_byte(&self, stats: &mut UartStats) -> Option<u8> {
+        while !self.FR.is_set(FR::RXFE) {
+            let dr = self.DR.extract();
+            if dr.is_set(DR::OE) {
+                stats.hw_overruns += 1;
+            }
+            if dr.is_set(DR::BE) {
+                // A break comes with a zero character that isn't real data.
+                stats.breaks += 1;
+                continue;
+            }
+            if dr.is_set(DR::FE) {
+                stats.framing_errors += 1;
+            }
+            if dr.is_set(DR::PE) {
+                stats.parity_errors += 1;
+            }
+            return Some(dr.read(DR::DATA) as u8);
         }
+        None
+    }
+
+    fn tx_ready(&self) -> bool {
+        !self.FR.is_set(FR::TXFF)
+    }
+
+    fn put_byte(&self, b: u8) {
+        self.DR.set(b as u32);
+    }
 
-        while self.registers.FR.matches_all(FR::TXFF::SET) {
-            cortex_a::asm::nop();
+    fn tx_idle(&self) -> bool {
+        !self.FR.is_set(FR::BUSY)
+    }
+
+    fn clear_rx_fifo(&self) {
+        while !self.FR.is_set(FR::RXFE) {
+            self.DR.get();
         }
+    }
 
-        // Write the character to the buffer.
-        self.registers.DR.set(c as u32);
+    fn enable_interrupts(&self) {
+        self.IFLS
+            .write(IFLS::RXIFLSEL::OneHalf + IFLS::TXIFLSEL::OneQuarter);
+        self.ICR.write(INT::ALL::SET);
+        self.set_tx_interrupt(false);
+    }
 
+    fn set_tx_interrupt(&self, enabled: bool) {
+        self.IMSC.write(
+            INT::RX::SET
+                + INT::RT::SET
+                + INT::OE::SET
+                + INT::BE::SET
+                + INT::PE::SET
+                + INT::FE::SET
+                + INT::TX.val(enabled as u32),
+        );
     }
-    
-    pub fn read_char(&self) -> char {
-        while self.registers.FR.matches_all(FR::RXFE::SET) {
-            cortex_a::asm::nop();
+
+    fn ack_interrupt(&self) -> bool {
+        let pending = self.MIS.get();
+        if pending == 0 {
+            return false;
         }
-        
-        char::from_u32(self.registers.DR.get()).unwrap()
+        // RX and RT clear themselves once the FIFO is drained, the rest need an explicit clear.
+        self.ICR.set(pending);
+        true
     }
-    
-    pub fn write_str(&self, string: &str) {
-         for c in string.chars() {
-             self.write_char(c);
-         }
-     }
-
-    
 }
 
+/// Disables the UART, sets the divisors and frame format and enables it again.
+fn program(registers: &Registers, settings: &LineSettings, ibrd: u32, fbrd: u32) {
+    registers.CR.set(0);
+    registers.ICR.write(INT::ALL::SET);
+    registers.IBRD.write(IBRD::BAUD_DIVINT.val(ibrd));
+    registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(fbrd));
+    registers.LCR_H.write(line_control(settings));
+    registers.CR.write(
+        CR::UARTEN::Enabled
+            + CR::TXE::Enabled
+            + CR::RXE::Enabled
+            + CR::RTSEN.val(settings.flow_control as u32)
+            + CR::CTSEN.val(settings.flow_control as u32),
+    );
+}
+
+/// Integer and 6-bit fractional divisor: uart_clock / (16 * baud) = IBRD + FBRD / 64.
+fn baud_divisors(baud: u32, uart_clock: u32) -> Result<(u32, u32), &'static str> {
+    if baud == 0 {
+        return Err("baud rate must not be zero");
+    }
+
+    // 64 * uart_clock / (16 * baud), rounded to nearest.
+    let divisor = (4 * uart_clock as u64 + baud as u64 / 2) / baud as u64;
+    let (ibrd, fbrd) = (divisor >> 6, divisor & 0x3F);
+    if ibrd == 0 || ibrd > 0xFFFF {
+        return Err("baud rate out of range for the UART clock");
+    }
+    Ok((ibrd as u32, fbrd as u32))
+}
+
+fn line_control(settings: &LineSettings) -> tock_registers::fields::FieldValue<u32, LCR_H::Register> {
+    let wlen = match settings.data_bits {
+        5 => LCR_H::WLEN::FiveBit,
+        6 => LCR_H::WLEN::SixBit,
+        7 => LCR_H::WLEN::SevenBit,
+        _ => LCR_H::WLEN::EightBit,
+    };
+    let parity = match settings.parity {
+        Parity::None => LCR_H::PEN::Disabled,
+        Parity::Even => LCR_H::PEN::Enabled + LCR_H::EPS::Even,
+        Parity::Odd => LCR_H::PEN::Enabled + LCR_H::EPS::Odd,
+    };
+    wlen + parity + LCR_H::STP2.val(settings.stop_bits as u32 - 1) + LCR_H::FEN::FifosEnabled
+}
@@ -5,6 +5,7 @@ use tock_registers::{
 };
 
 use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::driver;
 use crate::synchronization::{interface::Mutex, FakeLock};
 
 register_bitfields! {
@@ -277,6 +278,8 @@ pub struct QA7Registers {
 }
 
 impl QA7Registers {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2836-l1-intc";
+
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             inner: FakeLock::new(QA7RegistersInner::new(mmio_start_addr))
@@ -299,6 +302,12 @@ impl QA7Registers {
     }
 }
 
+impl driver::interface::DeviceDriver for QA7Registers {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+}
+
 struct QA7RegistersInner {
     registers: Registers
 }
@@ -0,0 +1,520 @@
+//! Main SPI controller, SPI0.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+use super::bcm2xxx_dma::{self, ControlBlock, Dma, DmaBuffer, DmaChannel, DMA_TI};
+use crate::bsp::device_driver::common::{
+    clock_divider, with_irqs_masked, BusLock, MMIODerefWrapper, SpiBus, SpiSettings,
+};
+use crate::{
+    driver, exception,
+    scheduler::PTABLE,
+    synchronization::{interface::Mutex, SpinLock},
+    time::time_manager,
+};
+
+register_bitfields! {
+    u32,
+
+    /// Master Control and Status.
+    CS [
+        /// Polarity of chip select 2, 1 and 0 (1 = active high).
+        CSPOL2 OFFSET(23) NUMBITS(1) [],
+        CSPOL1 OFFSET(22) NUMBITS(1) [],
+        CSPOL0 OFFSET(21) NUMBITS(1) [],
+        /// RX FIFO full.
+        RXF OFFSET(20) NUMBITS(1) [],
+        /// RX FIFO needs reading, it is at least 3/4 full.
+        RXR OFFSET(19) NUMBITS(1) [],
+        /// TX FIFO can accept data.
+        TXD OFFSET(18) NUMBITS(1) [],
+        /// RX FIFO contains data.
+        RXD OFFSET(17) NUMBITS(1) [],
+        /// Transfer done, the TX FIFO is empty and the last byte has been shifted.
+        DONE OFFSET(16) NUMBITS(1) [],
+        /// Interrupt on RXR.
+        INTR OFFSET(10) NUMBITS(1) [],
+        /// Interrupt on DONE.
+        INTD OFFSET(9) NUMBITS(1) [],
+        /// Let DMA requests drive the FIFO. The first word written then holds the transfer
+        /// length in its top half and CS[7:0] in its bottom byte.
+        DMAEN OFFSET(8) NUMBITS(1) [],
+        /// Transfer active, asserts the chip select.
+        TA OFFSET(7) NUMBITS(1) [],
+        /// Polarity of the chip select lines while idle, see CSPOLn.
+        CSPOL OFFSET(6) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Tx = 0b01,
+            Rx = 0b10,
+            Both = 0b11,
+        ],
+        CPOL OFFSET(3) NUMBITS(1) [],
+        CPHA OFFSET(2) NUMBITS(1) [],
+        /// Chip select.
+        CS OFFSET(0) NUMBITS(2) [],
+    ],
+
+    /// Clock Divider. SCLK = core clock / CDIV, a CDIV of 0 divides by 65536.
+    CLK [
+        CDIV OFFSET(0) NUMBITS(16) [],
+    ],
+
+    /// DMA DREQ Controls.
+    DC [
+        RPANIC OFFSET(24) NUMBITS(8) [],
+        RDREQ OFFSET(16) NUMBITS(8) [],
+        TPANIC OFFSET(8) NUMBITS(8) [],
+        TDREQ OFFSET(0) NUMBITS(8) [],
+    ],
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub Spi0Registers {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => FIFO: ReadWrite<u32>),
+        (0x08 => CLK: ReadWrite<u32, CLK::Register>),
+        (0x0C => DLEN: ReadWrite<u32>),
+        (0x10 => LTOH: ReadWrite<u32>),
+        (0x14 => DC: ReadWrite<u32, DC::Register>),
+        (0x18 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<Spi0Registers>;
+
+/// Bus address of the FIFO register, for DMA.
+const FIFO_BUS_ADDR: u32 = bcm2xxx_dma::PERIPHERAL_BUS_BASE + 0x0020_4004;
+
+/// What the firmware runs the VPU core clock at unless told otherwise.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// Polls without progress before giving up on a polled transfer.
+const TIMEOUT_SPINS: usize = 1_000_000;
+
+/// Time without progress before giving up on an interrupt-driven transfer.
+const IRQ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// DLEN is 16 bits wide.
+const MAX_DMA_TRANSFER: usize = 0xFFFF;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Spi0TransferMode {
+    Polled,
+    Interrupt,
+    Dma,
+}
+
+impl Spi0TransferMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Spi0TransferMode::Polled => "polled",
+            Spi0TransferMode::Interrupt => "irq",
+            Spi0TransferMode::Dma => "dma",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Spi0TransferMode::Polled, Spi0TransferMode::Interrupt, Spi0TransferMode::Dma]
+            .into_iter()
+            .find(|mode| mode.name() == name)
+    }
+}
+
+/// Buffers of an interrupt-driven transfer, shared with the handler.
+struct IrqTransfer {
+    tx: Vec<u8>,
+    rx: Vec<u8>,
+    sent: usize,
+    done: bool,
+}
+
+pub struct Spi0 {
+    inner: SpinLock<Spi0Inner>,
+    dma: &'static Dma,
+    bus: BusLock,
+}
+
+impl Spi0 {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-spi";
+
+    pub const unsafe fn new(mmio_start_addr: usize, dma: &'static Dma) -> Self {
+        Self {
+            inner: SpinLock::new(Spi0Inner::new(mmio_start_addr)),
+            dma,
+            bus: BusLock::new(),
+        }
+    }
+
+    fn with_inner<R>(&self, f: impl FnOnce(&mut Spi0Inner) -> R) -> R {
+        with_irqs_masked(&self.inner, f)
+    }
+
+    /// Reprograms speed, mode and chip select. The clock divider runs off the VPU core clock,
+    /// `core_clock` in Hz.
+    pub fn configure(&self, settings: &SpiSettings, core_clock: u32) -> Result<(), &'static str> {
+        if settings.chip_select > 2 {
+            return Err("SPI0 only has chip selects 0 to 2");
+        }
+        let divider = clock_divider(settings.speed_hz, core_clock)?;
+
+        self.with_inner(|inner| {
+            inner.settings = *settings;
+            inner.registers.CLK.write(CLK::CDIV.val(divider));
+        });
+        Ok(())
+    }
+
+    pub fn settings(&self) -> SpiSettings {
+        self.with_inner(|inner| inner.settings)
+    }
+
+    /// Makes `chip_select` active high instead of active low.
+    pub fn set_cs_active_high(&self, chip_select: u8, active_high: bool) -> Result<(), &'static str> {
+        if chip_select > 2 {
+            return Err("SPI0 only has chip selects 0 to 2");
+        }
+
+        self.with_inner(|inner| {
+            if active_high {
+                inner.cs_active_high |= 1 << chip_select;
+            } else {
+                inner.cs_active_high &= !(1 << chip_select);
+            }
+            // Takes effect on the idle lines right away.
+            let idle = inner.control();
+            inner.registers.CS.write(idle);
+        });
+        Ok(())
+    }
+
+    pub fn cs_active_high(&self, chip_select: u8) -> bool {
+        self.with_inner(|inner| inner.cs_active_high & (1 << chip_select) != 0)
+    }
+
+    pub fn transfer_mode(&self) -> Spi0TransferMode {
+        self.with_inner(|inner| inner.mode)
+    }
+
+    /// Selects how transfers move data. The DMA mode takes two channels from the DMA controller
+    /// and gives them back when another mode is selected.
+    pub fn set_transfer_mode(&self, mode: Spi0TransferMode) -> Result<(), &'static str> {
+        if mode == Spi0TransferMode::Interrupt && !self.with_inner(|inner| inner.irq_registered) {
+            return Err("SPI0 interrupt not available");
+        }
+
+        let channels = self.with_inner(|inner| inner.dma_channels);
+        match (mode, channels) {
+            (Spi0TransferMode::Dma, None) => {
+                let tx = self.dma.allocate_channel()?;
+                let rx = match self.dma.allocate_channel() {
+                    Ok(rx) => rx,
+                    Err(x) => {
+                        self.dma.free_channel(tx);
+                        return Err(x);
+                    }
+                };
+                self.with_inner(|inner| inner.dma_channels = Some((tx, rx)));
+            }
+            (Spi0TransferMode::Polled | Spi0TransferMode::Interrupt, Some((tx, rx))) => {
+                self.with_inner(|inner| inner.dma_channels = None);
+                self.dma.free_channel(tx);
+                self.dma.free_channel(rx);
+            }
+            _ => {}
+        }
+
+        self.with_inner(|inner| inner.mode = mode);
+        Ok(())
+    }
+
+    /// Marks the SPI0 interrupt as routed to `Spi0`'s `IrqHandler`, allowing the interrupt mode.
+    pub fn enable_interrupts(&self) {
+        self.with_inner(|inner| inner.irq_registered = true);
+    }
+
+    fn transfer_polled(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        self.with_inner(|inner| inner.transfer_polled(tx, rx))
+    }
+
+    fn transfer_irq(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        let daif = exception::irq_save();
+        if !exception::irqs_enabled(daif) {
+            // Nothing would ever run the handler.
+            exception::irq_restore(daif);
+            return self.transfer_polled(tx, rx);
+        }
+
+        let mut data = self.inner.lock().unwrap();
+        data.irq_transfer = Some(IrqTransfer {
+            tx: tx.to_vec(),
+            rx: Vec::with_capacity(tx.len()),
+            sent: 0,
+            done: false,
+        });
+        // DONE is already set with an empty FIFO, so the handler fills it right away.
+        let control = data.control();
+        data.registers.CS.write(control + CS::CLEAR::Both + CS::INTR::SET + CS::INTD::SET + CS::TA::SET);
+
+        let (mut received, mut last_progress) = (0, time_manager().uptime());
+        let result = loop {
+            let transfer = data.irq_transfer.as_ref().unwrap();
+            if transfer.done {
+                break Ok(());
+            }
+            if transfer.rx.len() != received {
+                (received, last_progress) = (transfer.rx.len(), time_manager().uptime());
+            } else if time_manager().uptime() - last_progress > IRQ_TIMEOUT {
+                break Err("SPI timeout");
+            }
+            if PTABLE.interrupted() {
+                break Err("interrupted");
+            }
+
+            // Sleeping would leave nobody to notice the timeout should the interrupt never come,
+            // so only let the handler and other processes run in between checks.
+            drop(data);
+            exception::irq_restore(daif);
+            PTABLE.yield_now();
+            exception::irq_save();
+            data = self.inner.lock().unwrap();
+        };
+
+        let transfer = data.irq_transfer.take().unwrap();
+        if result.is_err() {
+            data.registers.CS.write(control + CS::CLEAR::Both);
+        }
+        drop(data);
+        exception::irq_restore(daif);
+
+        result?;
+        if !rx.is_empty() {
+            rx.copy_from_slice(&transfer.rx);
+        }
+        Ok(())
+    }
+
+    fn transfer_dma(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        if tx.len() > MAX_DMA_TRANSFER {
+            return Err("DMA transfers are limited to 65535 bytes");
+        }
+        let Some((tx_channel, rx_channel)) = self.with_inner(|inner| inner.dma_channels) else {
+            return Err("no DMA channels");
+        };
+
+        // In DMA mode the FIFO takes whole words, the first one sets up the transfer.
+        let words = tx.len().div_ceil(4);
+        let mut tx_buffer = DmaBuffer::new(1 + words);
+        let rx_buffer = DmaBuffer::new(words);
+        let control = self.with_inner(|inner| inner.control() + CS::TA::SET);
+        tx_buffer.words_mut()[0] = (tx.len() as u32) << 16 | (control.value & 0xFF);
+        for (word, chunk) in tx_buffer.words_mut()[1..].iter_mut().zip(tx.chunks(4)) {
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u32::from_le_bytes(bytes);
+        }
+        tx_buffer.sync();
+        rx_buffer.sync();
+
+        let (tx_cb, rx_cb) = self.with_inner(|inner| {
+            inner.dma_cbs[0] = ControlBlock::new();
+            inner.dma_cbs[0].ti = (DMA_TI::PERMAP.val(bcm2xxx_dma::DREQ_SPI0_TX)
+                + DMA_TI::DEST_DREQ::SET
+                + DMA_TI::SRC_INC::SET
+                + DMA_TI::WAIT_RESP::SET)
+                .value;
+            inner.dma_cbs[0].source_ad = tx_buffer.bus_address();
+            inner.dma_cbs[0].dest_ad = FIFO_BUS_ADDR;
+            inner.dma_cbs[0].txfr_len = (4 + words * 4) as u32;
+
+            inner.dma_cbs[1] = ControlBlock::new();
+            inner.dma_cbs[1].ti = (DMA_TI::PERMAP.val(bcm2xxx_dma::DREQ_SPI0_RX)
+                + DMA_TI::SRC_DREQ::SET
+                + DMA_TI::DEST_INC::SET
+                + DMA_TI::WAIT_RESP::SET
+                + DMA_TI::INTEN::SET)
+                .value;
+            inner.dma_cbs[1].source_ad = FIFO_BUS_ADDR;
+            inner.dma_cbs[1].dest_ad = rx_buffer.bus_address();
+            inner.dma_cbs[1].txfr_len = (words * 4) as u32;
+
+            let control = inner.control();
+            inner.registers.CS.write(control + CS::CLEAR::Both + CS::DMAEN::SET);
+            (&inner.dma_cbs[0] as *const ControlBlock, &inner.dma_cbs[1] as *const ControlBlock)
+        });
+
+        // The control blocks live in the driver's static state and aren't touched again until
+        // the next transfer, which can't start before this one has released the bus.
+        unsafe {
+            self.dma.start(rx_channel, &*rx_cb);
+            self.dma.start(tx_channel, &*tx_cb);
+        }
+        let result = self.dma.wait(rx_channel);
+        if result.is_err() {
+            self.dma.abort(tx_channel);
+            self.dma.abort(rx_channel);
+        }
+
+        self.with_inner(|inner| {
+            let control = inner.control();
+            inner.registers.CS.write(control + CS::CLEAR::Both);
+        });
+        result?;
+
+        rx_buffer.sync();
+        for (chunk, word) in rx.chunks_mut(4).zip(rx_buffer.words()) {
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+impl SpiBus for Spi0 {
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        if !rx.is_empty() && rx.len() != tx.len() {
+            return Err("rx must be empty or as long as tx");
+        }
+        if tx.is_empty() {
+            return Ok(());
+        }
+
+        self.bus.acquire()?;
+        let result = match self.transfer_mode() {
+            Spi0TransferMode::Polled => self.transfer_polled(tx, rx),
+            Spi0TransferMode::Interrupt => self.transfer_irq(tx, rx),
+            Spi0TransferMode::Dma => self.transfer_dma(tx, rx),
+        };
+        self.bus.release();
+        result
+    }
+}
+
+impl exception::interface::IrqHandler for Spi0 {
+    fn handle(&self) {
+        self.inner.lock().unwrap().handle_interrupt();
+    }
+}
+
+impl driver::interface::DeviceDriver for Spi0 {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let divider = clock_divider(SpiSettings::DEFAULT.speed_hz, DEFAULT_CORE_CLOCK)?;
+        let data = self.inner.lock().unwrap();
+        data.registers.CLK.write(CLK::CDIV.val(divider));
+        let control = data.control();
+        data.registers.CS.write(control + CS::CLEAR::Both);
+        Ok(())
+    }
+}
+
+struct Spi0Inner {
+    registers: Registers,
+    settings: SpiSettings,
+    /// One bit per chip select.
+    cs_active_high: u32,
+    mode: Spi0TransferMode,
+    irq_registered: bool,
+    irq_transfer: Option<IrqTransfer>,
+    dma_channels: Option<(DmaChannel, DmaChannel)>,
+    dma_cbs: [ControlBlock; 2],
+}
+
+impl Spi0Inner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            settings: SpiSettings::DEFAULT,
+            cs_active_high: 0,
+            mode: Spi0TransferMode::Polled,
+            irq_registered: false,
+            irq_transfer: None,
+            dma_channels: None,
+            dma_cbs: [ControlBlock::new(), ControlBlock::new()],
+        }
+    }
+
+    /// CS register contents for the current settings, with no transfer active.
+    fn control(&self) -> tock_registers::fields::FieldValue<u32, CS::Register> {
+        let selected_high = self.cs_active_high & (1 << self.settings.chip_select) != 0;
+        CS::CS.val(self.settings.chip_select as u32)
+            + CS::CPOL.val(self.settings.mode.cpol() as u32)
+            + CS::CPHA.val(self.settings.mode.cpha() as u32)
+            + CS::CSPOL.val(selected_high as u32)
+            + CS::CSPOL0.val(self.cs_active_high & 1)
+            + CS::CSPOL1.val((self.cs_active_high >> 1) & 1)
+            + CS::CSPOL2.val((self.cs_active_high >> 2) & 1)
+    }
+
+    fn transfer_polled(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str> {
+        let control = self.control();
+        self.registers.CS.write(control + CS::CLEAR::Both + CS::TA::SET);
+
+        let (mut sent, mut received, mut idle) = (0, 0, 0);
+        let result = loop {
+            let mut progress = false;
+            while sent < tx.len() && self.registers.CS.is_set(CS::TXD) {
+                self.registers.FIFO.set(tx[sent] as u32);
+                sent += 1;
+                progress = true;
+            }
+            while received < tx.len() && self.registers.CS.is_set(CS::RXD) {
+                let b = self.registers.FIFO.get() as u8;
+                if let Some(out) = rx.get_mut(received) {
+                    *out = b;
+                }
+                received += 1;
+                progress = true;
+            }
+
+            if received == tx.len() && self.registers.CS.is_set(CS::DONE) {
+                break Ok(());
+            }
+            idle = if progress { 0 } else { idle + 1 };
+            if idle > TIMEOUT_SPINS {
+                break Err("SPI timeout");
+            }
+        };
+
+        self.registers.CS.write(control + CS::CLEAR::Both);
+        result
+    }
+
+    /// Moves data between the FIFOs and the transfer buffers, and marks the transfer done once
+    /// the last byte is in.
+    fn handle_interrupt(&mut self) {
+        let Some(transfer) = self.irq_transfer.as_mut() else {
+            return;
+        };
+        if transfer.done {
+            return;
+        }
+
+        while transfer.rx.len() < transfer.tx.len() && self.registers.CS.is_set(CS::RXD) {
+            transfer.rx.push(self.registers.FIFO.get() as u8);
+        }
+        while transfer.sent < transfer.tx.len() && self.registers.CS.is_set(CS::TXD) {
+            self.registers.FIFO.set(transfer.tx[transfer.sent] as u32);
+            transfer.sent += 1;
+        }
+
+        if transfer.rx.len() < transfer.tx.len() || !self.registers.CS.is_set(CS::DONE) {
+            return;
+        }
+        transfer.done = true;
+        let control = self.control();
+        self.registers.CS.write(control + CS::CLEAR::Both);
+    }
+}
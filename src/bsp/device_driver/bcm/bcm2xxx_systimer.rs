@@ -5,6 +5,7 @@ use tock_registers::{
 };
 
 use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::driver;
 
 register_structs! {
     #[allow(non_snake_case)]
@@ -27,6 +28,8 @@ pub struct SystemTimer {
 }
 
 impl SystemTimer {
+    pub const COMPATIBLE: &'static str = "brcm,bcm2835-system-timer";
+
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
@@ -43,4 +46,10 @@ impl SystemTimer {
         let start = self.get_ticks();
         while self.get_ticks() < start + ms*1000 {}        
     }
-}
\ No newline at end of file
+}
+
+impl driver::interface::DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+}
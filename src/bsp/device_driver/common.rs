@@ -1,4 +1,11 @@
-use core::{marker::PhantomData, ops};
+use core::{fmt, marker::PhantomData, ops};
+
+use crate::{
+    console, exception,
+    scheduler::{WaitQueue, PTABLE},
+    synchronization::{interface::Mutex, SpinLock},
+    utils::RingBuffer,
+};
 
 pub struct MMIODerefWrapper<T> {
     start_addr: usize,
@@ -20,4 +27,526 @@ impl<T> ops::Deref for MMIODerefWrapper<T> {
     fn deref(&self) -> &Self::Target {
         unsafe { &*(self.start_addr as *const _) }
     }
-}
\ No newline at end of file
+}
+
+/// Runs `f` on the data behind `lock` with IRQs masked, so an IRQ handler taking the same lock
+/// can't deadlock against it.
+pub fn with_irqs_masked<T, R>(lock: &SpinLock<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    let daif = exception::irq_save();
+    let result = f(&mut lock.lock().unwrap());
+    exception::irq_restore(daif);
+    result
+}
+
+/// Why `BusLock::acquire` gave up.
+#[derive(Copy, Clone, PartialEq)]
+pub enum AcquireError {
+    /// The waiting process was asked to finish early.
+    Interrupted,
+    /// The bus is taken and there is no process to put to sleep, or IRQs are masked, so the
+    /// holder might never get to release it.
+    Busy,
+}
+
+impl From<AcquireError> for &'static str {
+    fn from(error: AcquireError) -> Self {
+        match error {
+            AcquireError::Interrupted => "interrupted",
+            AcquireError::Busy => "bus busy",
+        }
+    }
+}
+
+/// Claims a bus for one transfer at a time. Unlike a spin lock it is held with IRQs unmasked,
+/// and whoever waits for it sleeps.
+pub struct BusLock {
+    busy: SpinLock<bool>,
+    waiters: WaitQueue,
+}
+
+impl BusLock {
+    pub const fn new() -> Self {
+        Self {
+            busy: SpinLock::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Result<(), AcquireError> {
+        loop {
+            let daif = exception::irq_save();
+            let mut busy = self.busy.lock().unwrap();
+            if !*busy {
+                *busy = true;
+                drop(busy);
+                exception::irq_restore(daif);
+                return Ok(());
+            }
+            if PTABLE.interrupted() {
+                drop(busy);
+                exception::irq_restore(daif);
+                return Err(AcquireError::Interrupted);
+            }
+
+            let slept = self.waiters.sleep(daif, busy);
+            exception::irq_restore(daif);
+            if !slept {
+                return Err(AcquireError::Busy);
+            }
+        }
+    }
+
+    pub fn release(&self) {
+        with_irqs_masked(&self.busy, |busy| *busy = false);
+        self.waiters.wake_all();
+    }
+}
+
+/// Smallest even clock divider, as the SPI and I2C masters take it, that doesn't exceed
+/// `speed_hz`.
+pub fn clock_divider(speed_hz: u32, core_clock: u32) -> Result<u32, &'static str> {
+    if speed_hz == 0 {
+        return Err("speed must not be zero");
+    }
+
+    let divider = core_clock.div_ceil(speed_hz).max(2).next_multiple_of(2);
+    if divider > 0xFFFE {
+        return Err("speed too low for the core clock");
+    }
+    Ok(divider)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Serial line settings shared by the UART drivers.
+#[derive(Copy, Clone)]
+pub struct LineSettings {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+    /// RTS/CTS hardware flow control.
+    pub flow_control: bool,
+}
+
+impl LineSettings {
+    /// 115200 baud, 8N1.
+    pub const DEFAULT: Self = Self {
+        baud: 115200,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+        flow_control: false,
+    };
+
+    /// Parses the usual shorthand for the frame format, e.g. "8N1" or "7E2".
+    pub fn parse_format(&mut self, format: &str) -> Result<(), &'static str> {
+        let &[data_bits, parity, stop_bits] = format.as_bytes() else {
+            return Err("format must look like 8N1");
+        };
+
+        self.data_bits = match data_bits {
+            b'5'..=b'8' => data_bits - b'0',
+            _ => return Err("data bits must be 5 to 8"),
+        };
+        self.parity = match parity.to_ascii_uppercase() {
+            b'N' => Parity::None,
+            b'E' => Parity::Even,
+            b'O' => Parity::Odd,
+            _ => return Err("parity must be N, E or O"),
+        };
+        self.stop_bits = match stop_bits {
+            b'1' | b'2' => stop_bits - b'0',
+            _ => return Err("stop bits must be 1 or 2"),
+        };
+        Ok(())
+    }
+}
+
+impl fmt::Display for LineSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        write!(f, "{} baud {}{}{}", self.baud, self.data_bits, parity, self.stop_bits)?;
+        if self.flow_control {
+            write!(f, " rtscts")?;
+        }
+        Ok(())
+    }
+}
+
+const UART_RX_BUFFER_SIZE: usize = 1024;
+const UART_TX_BUFFER_SIZE: usize = 4096;
+
+/// What `BufferedUart` needs from the registers of a particular UART.
+pub trait UartRegisters {
+    /// Heads the `print_stats` output.
+    const NAME: &'static str;
+    /// Whether the hardware reports framing and parity errors and breaks.
+    const LINE_ERRORS: bool;
+
+    /// Takes the next data byte out of the receive FIFO, `None` once it is empty. Error
+    /// conditions reported along with it are counted in `stats`.
+    fn receive_byte(&self, stats: &mut UartStats) -> Option<u8>;
+
+    /// Whether the transmit FIFO has room for another byte.
+    fn tx_ready(&self) -> bool;
+
+    fn put_byte(&self, b: u8);
+
+    /// Whether everything written has left the shift register.
+    fn tx_idle(&self) -> bool;
+
+    fn clear_rx_fifo(&self);
+
+    /// Unmasks the receive interrupts, with the transmit interrupt off.
+    fn enable_interrupts(&self);
+
+    fn set_tx_interrupt(&self, enabled: bool);
+
+    /// Acknowledges the UART's interrupt. Returns false if it wasn't the one asserting it.
+    fn ack_interrupt(&self) -> bool;
+}
+
+#[derive(Copy, Clone)]
+pub struct UartStats {
+    pub irq_mode: bool,
+    pub rx_bytes: usize,
+    pub tx_bytes: usize,
+    /// Characters lost because the receive FIFO overflowed.
+    pub hw_overruns: usize,
+    /// Characters dropped because the receive buffer was full.
+    pub sw_overruns: usize,
+    pub framing_errors: usize,
+    pub parity_errors: usize,
+    pub breaks: usize,
+}
+
+pub struct BufferedUartInner<R> {
+    pub registers: R,
+    pub settings: LineSettings,
+    rx: RingBuffer<UART_RX_BUFFER_SIZE>,
+    tx: RingBuffer<UART_TX_BUFFER_SIZE>,
+    pub stats: UartStats,
+}
+
+/// A UART console that polls until `enable_interrupts` and from then on buffers both ways,
+/// putting readers to sleep until the interrupt handler has received something.
+pub struct BufferedUart<R> {
+    inner: SpinLock<BufferedUartInner<R>>,
+    rx_waiters: WaitQueue,
+}
+
+impl<R: UartRegisters> BufferedUartInner<R> {
+    /// Moves everything the receive FIFO holds into the receive buffer. Returns whether
+    /// anything arrived.
+    fn receive(&mut self) -> bool {
+        let mut received = false;
+        while let Some(b) = self.registers.receive_byte(&mut self.stats) {
+            self.stats.rx_bytes += 1;
+            if self.rx.push(b).is_err() {
+                self.stats.sw_overruns += 1;
+            }
+            received = true;
+        }
+        received
+    }
+
+    /// Feeds the transmit FIFO from the transmit buffer for as long as it has room.
+    fn transmit(&mut self) {
+        while !self.tx.is_empty() && self.registers.tx_ready() {
+            let b = self.tx.pop().unwrap();
+            self.registers.put_byte(b);
+        }
+    }
+
+    fn handle_interrupt(&mut self) -> bool {
+        if !self.registers.ack_interrupt() {
+            return false;
+        }
+
+        let received = self.receive();
+        self.transmit();
+        // Nothing more to send, stop asking for room in the FIFO.
+        if self.tx.is_empty() {
+            self.registers.set_tx_interrupt(false);
+        }
+        received
+    }
+
+    fn put_byte_polled(&mut self, b: u8) {
+        while !self.registers.tx_ready() {
+            aarch64_cpu::asm::nop();
+        }
+        self.registers.put_byte(b);
+    }
+
+    /// Sends everything still buffered, bypassing the interrupt.
+    fn drain_tx_polled(&mut self) {
+        while let Some(b) = self.tx.pop() {
+            self.put_byte_polled(b);
+        }
+    }
+
+    /// Queues `b` for the transmit interrupt, or writes it out directly while there is none.
+    /// Callers usually have IRQs masked, e.g. under the console lock, the interrupt takes over
+    /// once they are unmasked again.
+    fn write_byte(&mut self, b: u8) {
+        if b == b'\n' {
+            self.write_byte(b'\r');
+        }
+        self.stats.tx_bytes += 1;
+
+        if !self.stats.irq_mode {
+            self.put_byte_polled(b);
+            return;
+        }
+
+        if self.tx.is_full() {
+            // The interrupt can't run while the lock is held, make room by hand.
+            let oldest = self.tx.pop().unwrap();
+            self.put_byte_polled(oldest);
+        }
+        self.tx.push(b).unwrap();
+        self.transmit();
+        if !self.tx.is_empty() {
+            self.registers.set_tx_interrupt(true);
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        if !self.stats.irq_mode {
+            self.receive();
+        }
+        self.rx.pop()
+    }
+
+    /// Waits until everything queued has gone out on the line.
+    pub fn flush(&mut self) {
+        self.drain_tx_polled();
+        while !self.registers.tx_idle() {
+            aarch64_cpu::asm::nop();
+        }
+    }
+
+    fn clear_rx(&mut self) {
+        self.registers.clear_rx_fifo();
+        self.rx.clear();
+    }
+}
+
+impl<R: UartRegisters> BufferedUart<R> {
+    pub const fn with_registers(registers: R) -> Self {
+        Self {
+            inner: SpinLock::new(BufferedUartInner {
+                registers,
+                settings: LineSettings::DEFAULT,
+                rx: RingBuffer::new(),
+                tx: RingBuffer::new(),
+                stats: UartStats {
+                    irq_mode: false,
+                    rx_bytes: 0,
+                    tx_bytes: 0,
+                    hw_overruns: 0,
+                    sw_overruns: 0,
+                    framing_errors: 0,
+                    parity_errors: 0,
+                    breaks: 0,
+                },
+            }),
+            rx_waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Runs `f` on the locked state with IRQs masked.
+    pub fn with_inner<T>(&self, f: impl FnOnce(&mut BufferedUartInner<R>) -> T) -> T {
+        with_irqs_masked(&self.inner, f)
+    }
+
+    /// Switches from polling to interrupt-driven, buffered operation. The UART's interrupt must
+    /// already be routed to its `IrqHandler`.
+    pub fn enable_interrupts(&self) {
+        self.with_inner(|inner| {
+            inner.stats.irq_mode = true;
+            inner.registers.enable_interrupts();
+        });
+    }
+
+    pub fn line_settings(&self) -> LineSettings {
+        self.with_inner(|inner| inner.settings)
+    }
+
+    pub fn print_stats(&self) {
+        let (stats, rx_pending, tx_pending) =
+            self.with_inner(|inner| (inner.stats, inner.rx.len(), inner.tx.len()));
+
+        crate::println!("{} ({} mode):", R::NAME, if stats.irq_mode { "interrupt" } else { "polled" });
+        crate::println!("  rx bytes:            {:>10}", stats.rx_bytes);
+        crate::println!("  tx bytes:            {:>10}", stats.tx_bytes);
+        crate::println!("  rx buffered:         {:>10}", rx_pending);
+        crate::println!("  tx buffered:         {:>10}", tx_pending);
+        crate::println!("  rx FIFO overruns:    {:>10}", stats.hw_overruns);
+        crate::println!("  rx buffer overruns:  {:>10}", stats.sw_overruns);
+        if R::LINE_ERRORS {
+            crate::println!("  framing errors:      {:>10}", stats.framing_errors);
+            crate::println!("  parity errors:       {:>10}", stats.parity_errors);
+            crate::println!("  breaks:              {:>10}", stats.breaks);
+        }
+    }
+}
+
+impl<R: UartRegisters> exception::interface::IrqHandler for BufferedUart<R> {
+    fn handle(&self) {
+        let received = self.inner.lock().unwrap().handle_interrupt();
+        if received {
+            self.rx_waiters.wake_all();
+            console::input_arrived();
+        }
+    }
+}
+
+impl<R: UartRegisters> console::interface::Write for BufferedUart<R> {
+    fn write_char(&self, c: char) {
+        let mut bytes = [0; 4];
+        self.with_inner(|inner| {
+            for &b in c.encode_utf8(&mut bytes).as_bytes() {
+                inner.write_byte(b);
+            }
+        });
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.with_inner(|inner| fmt::Write::write_fmt(&mut UartWriter { inner }, args))
+    }
+
+    fn flush(&self) {
+        self.with_inner(|inner| inner.flush());
+    }
+}
+
+impl<R: UartRegisters> console::interface::Read for BufferedUart<R> {
+    fn read_char(&self) -> char {
+        loop {
+            let daif = exception::irq_save();
+            let mut data = self.inner.lock().unwrap();
+
+            if let Some(b) = data.read_byte() {
+                drop(data);
+                exception::irq_restore(daif);
+                return b as char;
+            }
+
+            // Sleep until the IRQ handler has buffered something, unless there is no process
+            // to put to sleep or nothing would wake it up.
+            if data.stats.irq_mode {
+                self.rx_waiters.sleep(daif, data);
+            } else {
+                drop(data);
+            }
+            exception::irq_restore(daif);
+        }
+    }
+
+    fn clear_rx(&self) {
+        self.with_inner(|inner| inner.clear_rx());
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        self.with_inner(|inner| inner.read_byte()).map(|b| b as char)
+    }
+
+    fn input_interrupt_driven(&self) -> bool {
+        self.with_inner(|inner| inner.stats.irq_mode)
+    }
+}
+
+impl<R: UartRegisters> console::interface::ReadWrite for BufferedUart<R> {}
+
+/// Formats straight into the transmit path of a locked `BufferedUartInner`.
+struct UartWriter<'a, R> {
+    inner: &'a mut BufferedUartInner<R>,
+}
+
+impl<R: UartRegisters> fmt::Write for UartWriter<'_, R> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            self.inner.write_byte(b);
+        }
+        Ok(())
+    }
+}
+
+/// Clock polarity (CPOL) and phase (CPHA), numbered the usual way.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SpiMode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+}
+
+impl SpiMode {
+    pub fn from_number(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(SpiMode::Mode0),
+            1 => Some(SpiMode::Mode1),
+            2 => Some(SpiMode::Mode2),
+            3 => Some(SpiMode::Mode3),
+            _ => None,
+        }
+    }
+
+    /// Idle clock level is high.
+    pub fn cpol(&self) -> bool {
+        matches!(self, SpiMode::Mode2 | SpiMode::Mode3)
+    }
+
+    /// Data is sampled on the second clock edge.
+    pub fn cpha(&self) -> bool {
+        matches!(self, SpiMode::Mode1 | SpiMode::Mode3)
+    }
+}
+
+/// Bus settings shared by the SPI master drivers.
+#[derive(Copy, Clone)]
+pub struct SpiSettings {
+    pub speed_hz: u32,
+    pub mode: SpiMode,
+    pub chip_select: u8,
+}
+
+impl SpiSettings {
+    /// 1 MHz, mode 0, chip select 0.
+    pub const DEFAULT: Self = Self {
+        speed_hz: 1_000_000,
+        mode: SpiMode::Mode0,
+        chip_select: 0,
+    };
+}
+
+impl fmt::Display for SpiSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} Hz mode {} cs {}",
+            self.speed_hz,
+            self.mode.cpol() as u8 * 2 + self.mode.cpha() as u8,
+            self.chip_select
+        )
+    }
+}
+
+/// A full-duplex SPI master.
+pub trait SpiBus {
+    /// Clocks out `tx` on the configured chip select, which stays asserted for the whole
+    /// transfer. `rx` is either empty, to discard what comes back, or as long as `tx`.
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), &'static str>;
+}
@@ -8,23 +8,207 @@ pub const PBASE_START: usize = 0x3F00_0000;
 pub const PBASE_END: usize = 0x4000_FFFF;
 pub const GPIO_ADDR: usize = PBASE_START + 0x0020_0000;
 pub const AUX_REGS_ADDR: usize = PBASE_START + 0x0021_5000;
-const _PL011_UART_ADDR: usize = PBASE_START + 0x0020_1000;
+const PL011_UART_ADDR: usize = PBASE_START + 0x0020_1000;
+const SPI1_ADDR: usize = AUX_REGS_ADDR + 0x80;
+const SPI2_ADDR: usize = AUX_REGS_ADDR + 0xC0;
 const SYS_TIMER_ADDR: usize = PBASE_START + 0x0000_3000;
+const DMA_ADDR: usize = PBASE_START + 0x0000_7000;
+const SPI0_ADDR: usize = PBASE_START + 0x0020_4000;
+const BSC0_ADDR: usize = PBASE_START + 0x0020_5000;
+const BSC1_ADDR: usize = PBASE_START + 0x0080_4000;
+const INTERRUPT_CONTROLLER_ADDR: usize = PBASE_START + 0x0000_B200;
+const MAILBOX_ADDR: usize = PBASE_START + 0x0000_B880;
 const QA7_REGS_ADDR: usize = 0x4000_0000;
 
+/// Peripheral interrupt numbers.
+pub const DMA_IRQ_BASE: usize = 16;
+pub const AUX_IRQ: usize = 29;
+pub const GPIO_BANK0_IRQ: usize = 49;
+pub const GPIO_BANK1_IRQ: usize = 50;
+pub const SPI0_IRQ: usize = 54;
+pub const UART0_IRQ: usize = 57;
+
+pub static INTERRUPT_CONTROLLER: device_driver::InterruptController =
+    unsafe { device_driver::InterruptController::new(INTERRUPT_CONTROLLER_ADDR) };
+pub static MAILBOX: device_driver::Mailbox = unsafe { device_driver::Mailbox::new(MAILBOX_ADDR) };
+pub static FRAMEBUFFER: device_driver::Framebuffer = device_driver::Framebuffer::new(&MAILBOX);
+pub static FB_CONSOLE: crate::console::FramebufferConsole = crate::console::FramebufferConsole::new(&FRAMEBUFFER);
+pub static DMA: device_driver::Dma = unsafe { device_driver::Dma::new(DMA_ADDR) };
 pub static GPIO: device_driver::GPIO = unsafe { device_driver::GPIO::new(GPIO_ADDR) };
 pub static MINI_UART: device_driver::MiniUart = unsafe { device_driver::MiniUart::new(AUX_REGS_ADDR) };
+pub static PL011_UART: device_driver::PL011Uart = unsafe { device_driver::PL011Uart::new(PL011_UART_ADDR) };
+pub static SPI1: device_driver::AuxSpi =
+    unsafe { device_driver::AuxSpi::new(AUX_REGS_ADDR, SPI1_ADDR, device_driver::AuxSpiIndex::Spi1) };
+pub static SPI2: device_driver::AuxSpi =
+    unsafe { device_driver::AuxSpi::new(AUX_REGS_ADDR, SPI2_ADDR, device_driver::AuxSpiIndex::Spi2) };
+pub static SPI0: device_driver::Spi0 = unsafe { device_driver::Spi0::new(SPI0_ADDR, &DMA) };
+pub static I2C0: device_driver::I2c = unsafe { device_driver::I2c::new(BSC0_ADDR) };
+pub static I2C1: device_driver::I2c = unsafe { device_driver::I2c::new(BSC1_ADDR) };
 pub static QA7_REGS: device_driver::QA7Registers = unsafe { device_driver::QA7Registers::new(QA7_REGS_ADDR) };
 pub static SYSTEM_TIMER: device_driver::SystemTimer = unsafe { device_driver::SystemTimer::new(SYS_TIMER_ADDR) };
 
 pub mod driver {
-    use crate::bsp::raspberrypi::{GPIO, MINI_UART};
+    use super::device_driver::{Function, InterruptController, Mailbox, GPIO as Gpio};
+    use super::{
+        AUX_IRQ, DMA, DMA_IRQ_BASE, FB_CONSOLE, FRAMEBUFFER, GPIO, GPIO_BANK0_IRQ, GPIO_BANK1_IRQ, I2C0, I2C1, INTERRUPT_CONTROLLER,
+        MAILBOX, MINI_UART, PL011_UART, QA7_REGS, SPI0, SPI0_IRQ, SPI1, SPI2, SYSTEM_TIMER, UART0_IRQ,
+    };
+    use crate::driver::{driver_manager, DeviceDriverDescriptor};
+    use crate::{console, warn};
+
+    // TXD, RXD, CTS and RTS of both UARTs. The console gets GPIO 14-17 on the header, the
+    // secondary port GPIO 30-33, which the Pi 3 wires to the Bluetooth module (QEMU simply
+    // exposes both as serial ports).
+    #[cfg(not(feature = "console_pl011"))]
+    const MINI_UART_PINS: [(usize, Function); 4] =
+        [(14, Function::Alt5), (15, Function::Alt5), (16, Function::Alt5), (17, Function::Alt5)];
+    #[cfg(not(feature = "console_pl011"))]
+    const PL011_UART_PINS: [(usize, Function); 4] =
+        [(32, Function::Alt3), (33, Function::Alt3), (30, Function::Alt3), (31, Function::Alt3)];
+
+    #[cfg(feature = "console_pl011")]
+    const MINI_UART_PINS: [(usize, Function); 4] =
+        [(32, Function::Alt5), (33, Function::Alt5), (30, Function::Alt5), (31, Function::Alt5)];
+    #[cfg(feature = "console_pl011")]
+    const PL011_UART_PINS: [(usize, Function); 4] =
+        [(14, Function::Alt0), (15, Function::Alt0), (16, Function::Alt3), (17, Function::Alt3)];
+
+    // CE0, (CE1,) MISO, MOSI and SCLK. SPI1's CE1/CE2 on GPIO 16/17 stay with the console's flow
+    // control, SPI2 is only brought out on the Compute Module.
+    const SPI0_PINS: [(usize, Function); 5] =
+        [(8, Function::Alt0), (7, Function::Alt0), (9, Function::Alt0), (10, Function::Alt0), (11, Function::Alt0)];
+    const SPI1_PINS: [(usize, Function); 4] =
+        [(18, Function::Alt4), (19, Function::Alt4), (20, Function::Alt4), (21, Function::Alt4)];
+    const SPI2_PINS: [(usize, Function); 4] =
+        [(43, Function::Alt4), (40, Function::Alt4), (41, Function::Alt4), (42, Function::Alt4)];
+
+    // SDA and SCL. The board already pulls both buses up, BSC0 is the HAT ID EEPROM's.
+    const I2C0_PINS: [(usize, Function); 2] = [(0, Function::Alt0), (1, Function::Alt0)];
+    const I2C1_PINS: [(usize, Function); 2] = [(2, Function::Alt0), (3, Function::Alt0)];
+
+    unsafe fn post_init_gpio() -> Result<(), &'static str> {
+        GPIO.init_alt_pins(&MINI_UART_PINS)?;
+        GPIO.init_alt_pins(&PL011_UART_PINS)?;
+
+        // Pin events are still latched in GPEDS without the interrupt, nothing delivers them.
+        for irq in [GPIO_BANK0_IRQ, GPIO_BANK1_IRQ] {
+            if let Err(x) = INTERRUPT_CONTROLLER.register_handler(irq, "GPIO", &GPIO) {
+                warn!("GPIO events unavailable: {}", x);
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn post_init_mini_uart() -> Result<(), &'static str> {
+        if cfg!(feature = "console_pl011") {
+            console::register_secondary("mini", &MINI_UART);
+        } else {
+            console::register_console("mini", &MINI_UART);
+        }
+
+        // Without the interrupt the UART keeps working, just polled.
+        match INTERRUPT_CONTROLLER.register_handler(AUX_IRQ, "mini UART", &MINI_UART) {
+            Ok(()) => MINI_UART.enable_interrupts(),
+            Err(x) => warn!("Mini UART stays in polled mode: {}", x),
+        }
+        Ok(())
+    }
+
+    unsafe fn post_init_pl011_uart() -> Result<(), &'static str> {
+        if cfg!(feature = "console_pl011") {
+            console::register_console("pl011", &PL011_UART);
+        } else {
+            console::register_secondary("pl011", &PL011_UART);
+        }
+
+        match INTERRUPT_CONTROLLER.register_handler(UART0_IRQ, "PL011 UART", &PL011_UART) {
+            Ok(()) => PL011_UART.enable_interrupts(),
+            Err(x) => warn!("PL011 UART stays in polled mode: {}", x),
+        }
+        Ok(())
+    }
+
+    unsafe fn post_init_framebuffer() -> Result<(), &'static str> {
+        console::register_console("fb", &FB_CONSOLE);
+        Ok(())
+    }
+
+    unsafe fn post_init_dma() -> Result<(), &'static str> {
+        // Without the interrupts transfers still complete, waiting for them just spins.
+        let mask = DMA.channel_mask();
+        for channel in (0..32).filter(|c| mask & (1 << c) != 0) {
+            if let Err(x) = INTERRUPT_CONTROLLER.register_handler(DMA_IRQ_BASE + channel, "DMA", &DMA) {
+                warn!("DMA completion is polled: {}", x);
+                return Ok(());
+            }
+        }
+        DMA.enable_interrupts();
+        Ok(())
+    }
+
+    unsafe fn post_init_spi0() -> Result<(), &'static str> {
+        GPIO.init_alt_pins(&SPI0_PINS)?;
+
+        match INTERRUPT_CONTROLLER.register_handler(SPI0_IRQ, "SPI0", &SPI0) {
+            Ok(()) => SPI0.enable_interrupts(),
+            Err(x) => warn!("SPI0 interrupt mode unavailable: {}", x),
+        }
+        Ok(())
+    }
+
+    unsafe fn post_init_spi1() -> Result<(), &'static str> {
+        GPIO.init_alt_pins(&SPI1_PINS)
+    }
+
+    unsafe fn post_init_spi2() -> Result<(), &'static str> {
+        GPIO.init_alt_pins(&SPI2_PINS)
+    }
+
+    unsafe fn post_init_i2c0() -> Result<(), &'static str> {
+        GPIO.init_alt_pins(&I2C0_PINS)
+    }
+
+    unsafe fn post_init_i2c1() -> Result<(), &'static str> {
+        GPIO.init_alt_pins(&I2C1_PINS)
+    }
 
     pub fn init() {
-        GPIO.init_mini_uart_pins();
-        MINI_UART.init();
+        let manager = driver_manager();
+
+        manager.register_driver(DeviceDriverDescriptor::new(&INTERRUPT_CONTROLLER, None, &[]));
+        manager.register_driver(DeviceDriverDescriptor::new(&MAILBOX, None, &[]));
+        manager.register_driver(DeviceDriverDescriptor::new(&GPIO, Some(post_init_gpio), &[InterruptController::COMPATIBLE]));
+        manager.register_driver(DeviceDriverDescriptor::new(
+            &MINI_UART,
+            Some(post_init_mini_uart),
+            &[Gpio::COMPATIBLE, InterruptController::COMPATIBLE],
+        ));
+        manager.register_driver(DeviceDriverDescriptor::new(
+            &PL011_UART,
+            Some(post_init_pl011_uart),
+            &[Gpio::COMPATIBLE, InterruptController::COMPATIBLE],
+        ));
+        manager.register_driver(DeviceDriverDescriptor::new(
+            &FRAMEBUFFER,
+            Some(post_init_framebuffer),
+            &[Mailbox::COMPATIBLE],
+        ));
+        manager.register_driver(DeviceDriverDescriptor::new(&DMA, Some(post_init_dma), &[InterruptController::COMPATIBLE]));
+        manager.register_driver(DeviceDriverDescriptor::new(
+            &SPI0,
+            Some(post_init_spi0),
+            &[Gpio::COMPATIBLE, InterruptController::COMPATIBLE],
+        ));
+        manager.register_driver(DeviceDriverDescriptor::new(&SPI1, Some(post_init_spi1), &[Gpio::COMPATIBLE]));
+        manager.register_driver(DeviceDriverDescriptor::new(&SPI2, Some(post_init_spi2), &[Gpio::COMPATIBLE]));
+        manager.register_driver(DeviceDriverDescriptor::new(&I2C0, Some(post_init_i2c0), &[Gpio::COMPATIBLE]));
+        manager.register_driver(DeviceDriverDescriptor::new(&I2C1, Some(post_init_i2c1), &[Gpio::COMPATIBLE]));
+        manager.register_driver(DeviceDriverDescriptor::new(&SYSTEM_TIMER, None, &[]));
+        manager.register_driver(DeviceDriverDescriptor::new(&QA7_REGS, None, &[]));
 
-        crate::console::register_console(&MINI_UART);
+        unsafe {
+            manager.init_drivers();
+        }
     }
 }
 
@@ -8,8 +8,21 @@ use super::{PBASE_END, PBASE_START};
 extern "Rust" {
     static __text_start: UnsafeCell<()>;
     static __text_end: UnsafeCell<()>;
-    static __mapped_dram_start: UnsafeCell<()>;
-    static __mapped_dram_end: UnsafeCell<()>;
+    static __rodata_start: UnsafeCell<()>;
+    static __rodata_end: UnsafeCell<()>;
+    static __data_start: UnsafeCell<()>;
+    static __data_end: UnsafeCell<()>;
+    static __stack_start_core0__: UnsafeCell<()>;
+    static __stack_end_core0__: UnsafeCell<()>;
+    static __stack_start_core1__: UnsafeCell<()>;
+    static __stack_end_core1__: UnsafeCell<()>;
+    static __stack_start_core2__: UnsafeCell<()>;
+    static __stack_end_core2__: UnsafeCell<()>;
+    static __stack_start_core3__: UnsafeCell<()>;
+    static __stack_end_core3__: UnsafeCell<()>;
+    static heap_start: UnsafeCell<()>;
+    static heap_end: UnsafeCell<()>;
+    static __kernel_img_end: UnsafeCell<()>;
 }
 
 pub struct KernelVirtualLayout<const NUM_SPECIAL_RANGES: usize> {
@@ -50,11 +63,12 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
     }
 }
 
-const NUM_MEM_RANGES: usize = 3;
+// Everything not listed here, including the guard page below each stack, stays unmapped.
+const NUM_MEM_RANGES: usize = 10;
 pub const KERNEL_VIRTUAL_LAYOUT: KernelVirtualLayout<NUM_MEM_RANGES> = KernelVirtualLayout {
     translation_descriptions: [
         TranslationDescription {
-            name: "Kernel code (.text, .rodata)",
+            name: "Kernel code (.text)",
             physical_start: text_start,
             physical_end: text_end,
             virtual_start: text_start,
@@ -65,16 +79,93 @@ pub const KERNEL_VIRTUAL_LAYOUT: KernelVirtualLayout<NUM_MEM_RANGES> = KernelVir
             },
         },
         TranslationDescription {
-            name: "Mapped DRAM (.data, stack, heap)",
-            physical_start: mapped_dram_start,
-            physical_end: mapped_dram_end,
-            virtual_start: mapped_dram_start,
+            name: "Kernel read-only data (.rodata)",
+            physical_start: rodata_start,
+            physical_end: rodata_end,
+            virtual_start: rodata_start,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadOnly,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
+        TranslationDescription {
+            name: "Kernel data (.data, .bss, .locks)",
+            physical_start: data_start,
+            physical_end: data_end,
+            virtual_start: data_start,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadWrite,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
+        TranslationDescription {
+            name: "Core 0 stack",
+            physical_start: stack_start_core0,
+            physical_end: stack_end_core0,
+            virtual_start: stack_start_core0,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadWrite,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
+        TranslationDescription {
+            name: "Core 1 stack",
+            physical_start: stack_start_core1,
+            physical_end: stack_end_core1,
+            virtual_start: stack_start_core1,
             attributes: AttributeFields {
                 execute_never: true,
                 permissions: AccessPermissions::ReadWrite,
                 memory_attributes: MemoryAttributes::CacheableDRAM,
             },
         },
+        TranslationDescription {
+            name: "Core 2 stack",
+            physical_start: stack_start_core2,
+            physical_end: stack_end_core2,
+            virtual_start: stack_start_core2,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadWrite,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
+        TranslationDescription {
+            name: "Core 3 stack",
+            physical_start: stack_start_core3,
+            physical_end: stack_end_core3,
+            virtual_start: stack_start_core3,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadWrite,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
+        TranslationDescription {
+            name: "Kernel heap",
+            physical_start: heap_start_addr,
+            physical_end: heap_end_addr,
+            virtual_start: heap_start_addr,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadWrite,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
+        TranslationDescription {
+            name: "Device tree blob",
+            physical_start: dtb_start,
+            physical_end: dtb_end,
+            virtual_start: dtb_start,
+            attributes: AttributeFields {
+                execute_never: true,
+                permissions: AccessPermissions::ReadOnly,
+                memory_attributes: MemoryAttributes::CacheableDRAM,
+            },
+        },
         TranslationDescription {
             name: "MMIO (memory-mapped peripherals)",
             physical_start: mmio_start,
@@ -100,13 +191,84 @@ fn text_end() -> usize {
 }
 
 #[inline(always)]
-fn mapped_dram_start() -> usize {
-    unsafe { __mapped_dram_start.get() as usize }
+fn rodata_start() -> usize {
+    unsafe { __rodata_start.get() as usize }
+}
+
+#[inline(always)]
+fn rodata_end() -> usize {
+    unsafe { __rodata_end.get() as usize }
+}
+
+#[inline(always)]
+fn data_start() -> usize {
+    unsafe { __data_start.get() as usize }
+}
+
+#[inline(always)]
+fn data_end() -> usize {
+    unsafe { __data_end.get() as usize }
+}
+
+#[inline(always)]
+fn stack_start_core0() -> usize {
+    unsafe { __stack_start_core0__.get() as usize }
+}
+
+#[inline(always)]
+fn stack_end_core0() -> usize {
+    unsafe { __stack_end_core0__.get() as usize }
+}
+
+#[inline(always)]
+fn stack_start_core1() -> usize {
+    unsafe { __stack_start_core1__.get() as usize }
+}
+
+#[inline(always)]
+fn stack_end_core1() -> usize {
+    unsafe { __stack_end_core1__.get() as usize }
 }
 
 #[inline(always)]
-fn mapped_dram_end() -> usize {
-    unsafe { __mapped_dram_end.get() as usize }
+fn stack_start_core2() -> usize {
+    unsafe { __stack_start_core2__.get() as usize }
+}
+
+#[inline(always)]
+fn stack_end_core2() -> usize {
+    unsafe { __stack_end_core2__.get() as usize }
+}
+
+#[inline(always)]
+fn stack_start_core3() -> usize {
+    unsafe { __stack_start_core3__.get() as usize }
+}
+
+#[inline(always)]
+fn stack_end_core3() -> usize {
+    unsafe { __stack_end_core3__.get() as usize }
+}
+
+#[inline(always)]
+fn heap_start_addr() -> usize {
+    unsafe { heap_start.get() as usize }
+}
+
+#[inline(always)]
+fn heap_end_addr() -> usize {
+    unsafe { heap_end.get() as usize }
+}
+
+// Rounded out to whole 64 KiB pages; `is_free_ram` keeps those pages clear of the kernel.
+#[inline(always)]
+fn dtb_start() -> usize {
+    crate::dtb::blob_range().0 & !0xFFFF
+}
+
+#[inline(always)]
+fn dtb_end() -> usize {
+    crate::dtb::blob_range().1.next_multiple_of(0x10000)
 }
 
 #[inline(always)]
@@ -119,6 +281,12 @@ fn mmio_end() -> usize {
     PBASE_END
 }
 
+/// Whether `[start, end)` is RAM that the kernel image, stacks and heap don't occupy.
+pub fn is_free_ram(start: usize, end: usize) -> bool {
+    let kernel_end = unsafe { __kernel_img_end.get() as usize };
+    start < end && end <= PBASE_START && (end <= text_start() || start >= kernel_end)
+}
+
 pub fn virt_mem_layout() -> &'static KernelVirtualLayout<NUM_MEM_RANGES> {
     &KERNEL_VIRTUAL_LAYOUT
 }
@@ -1,7 +1,16 @@
-use crate::synchronization::{FakeLock, interface::Mutex};
-use crate::console::null_console::NULL_CONSOLE;
+use core::fmt;
 
-mod null_console;
+use crate::scheduler::{WaitQueue, PTABLE};
+use crate::{exception, synchronization::{interface::Mutex, SpinLock}};
+
+mod fb_console;
+mod font;
+mod lock;
+
+pub use fb_console::FramebufferConsole;
+pub use lock::enter_panic_mode;
+
+use lock::ConsoleLock;
 
 pub mod interface {
     use core::fmt;
@@ -17,18 +26,272 @@ pub mod interface {
             ' '
         }
         fn clear_rx(&self);
+
+        /// False for output-only consoles, which never hand out input.
+        fn has_input(&self) -> bool {
+            true
+        }
+
+        /// A received character, if there is one, without blocking.
+        fn try_read_char(&self) -> Option<char> {
+            None
+        }
+
+        /// Whether arriving input calls `console::input_arrived`, so readers can sleep.
+        fn input_interrupt_driven(&self) -> bool {
+            false
+        }
     }
 
     pub trait ReadWrite: Write + Read {}
 }
 
-static CUR_CONSOLE: FakeLock<&'static (dyn interface::ReadWrite + Sync)> = FakeLock::new(&NULL_CONSOLE);
+/// Kernel log levels, most severe first.
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub const ALL: [Self; 4] = [Self::Error, Self::Warn, Self::Info, Self::Debug];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|level| level.name() == name)
+    }
+}
+
+const MAX_SINKS: usize = 6;
+
+type ConsoleRef = &'static (dyn interface::ReadWrite + Sync);
+
+/// A console attached to the console layer, and what it is used for.
+#[derive(Copy, Clone)]
+pub struct Sink {
+    pub name: &'static str,
+    console: ConsoleRef,
+    /// Receives everything printed.
+    pub output: bool,
+    /// Input is read from it.
+    pub input: bool,
+    /// The least severe level of the kernel log it receives, `None` for no log at all.
+    pub log_level: Option<LogLevel>,
+}
+
+static SINKS: SpinLock<[Option<Sink>; MAX_SINKS]> = SpinLock::new([None; MAX_SINKS]);
+
+static CONSOLE_LOCK: ConsoleLock = ConsoleLock::new();
+
+/// Woken by every interrupt-driven input source, for readers waiting on more than one.
+static INPUT_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Output fans out to the sinks, input is merged from all sources.
+struct ConsoleSet;
+
+static CONSOLE_SET: ConsoleSet = ConsoleSet;
+
+impl ConsoleSet {
+    /// Consoles are called with the lock released, one may print from its IRQ handler.
+    fn sinks(&self) -> impl Iterator<Item = Sink> {
+        let daif = exception::irq_save();
+        let sinks = *SINKS.lock().unwrap();
+        exception::irq_restore(daif);
+        sinks.into_iter().flatten()
+    }
+
+    fn outputs(&self) -> impl Iterator<Item = ConsoleRef> {
+        self.sinks().filter(|s| s.output).map(|s| s.console)
+    }
+
+    fn inputs(&self) -> impl Iterator<Item = ConsoleRef> {
+        self.sinks().filter(|s| s.input && s.console.has_input()).map(|s| s.console)
+    }
+
+    fn write_log(&self, level: LogLevel, args: fmt::Arguments) -> fmt::Result {
+        let _guard = CONSOLE_LOCK.lock();
+        self.sinks()
+            .filter(|s| s.log_level.is_some_and(|max| level <= max))
+            .try_for_each(|s| s.console.write_fmt(args))
+    }
+}
+
+impl interface::Write for ConsoleSet {
+    fn write_char(&self, c: char) {
+        let _guard = CONSOLE_LOCK.lock();
+        self.outputs().for_each(|console| console.write_char(c));
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        let _guard = CONSOLE_LOCK.lock();
+        self.outputs().try_for_each(|console| console.write_fmt(args))
+    }
+
+    /// Flushes every sink, including the ones that only get the log.
+    fn flush(&self) {
+        let _guard = CONSOLE_LOCK.lock();
+        self.sinks().for_each(|s| s.console.flush());
+    }
+}
+
+impl interface::Read for ConsoleSet {
+    fn read_char(&self) -> char {
+        // A single source sleeps on its own wait queue.
+        let mut inputs = self.inputs();
+        match (inputs.next(), inputs.next()) {
+            (Some(console), None) => console.read_char(),
+            _ => self.read_merged(),
+        }
+    }
+
+    fn clear_rx(&self) {
+        self.inputs().for_each(|console| console.clear_rx());
+    }
+}
+
+impl interface::ReadWrite for ConsoleSet {}
+
+impl ConsoleSet {
+    /// Waits for the first character from any of several sources.
+    fn read_merged(&self) -> char {
+        loop {
+            if let Some(c) = self.wait_for_input(None, &|| false) {
+                return c;
+            }
+        }
+    }
+
+    /// Waits for a character from any source, or for `done` to hold. `wakeups` is woken by
+    /// whoever makes `done` hold, so it isn't missed while asleep.
+    fn wait_for_input(&self, wakeups: Option<&WaitQueue>, done: &dyn Fn() -> bool) -> Option<char> {
+        loop {
+            let daif = exception::irq_save();
+
+            // Queued before polling, so input arriving in between still wakes us. With a polled
+            // source among them, or no process to put to sleep, we only yield.
+            let interrupt_driven = self.inputs().all(|console| console.input_interrupt_driven());
+            let sleeping = interrupt_driven && exception::irqs_enabled(daif) && PTABLE.prepare_to_wait(&INPUT_WAITERS);
+            if let (true, Some(queue)) = (sleeping, wakeups) {
+                PTABLE.prepare_to_wait(queue);
+            }
+
+            let result = match done() {
+                true => Some(None),
+                false => self.inputs().find_map(|console| console.try_read_char()).map(Some),
+            };
+            if result.is_none() && exception::irqs_enabled(daif) {
+                PTABLE.schedule();
+            }
+
+            if sleeping {
+                PTABLE.finish_wait(&INPUT_WAITERS);
+                wakeups.into_iter().for_each(|queue| PTABLE.finish_wait(queue));
+            }
+            exception::irq_restore(daif);
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+}
+
+/// Reads a character if one arrives before `done` holds, e.g. a key pressed while the shell
+/// waits for a task. `wakeups` is woken whenever `done` may have changed.
+pub fn read_char_until(wakeups: &WaitQueue, done: impl Fn() -> bool) -> Option<char> {
+    CONSOLE_SET.wait_for_input(Some(wakeups), &done)
+}
+
+/// Called by input sources from their IRQ handler when a character has been buffered.
+pub fn input_arrived() {
+    INPUT_WAITERS.wake_all();
+}
+
+fn add_sink(sink: Sink) {
+    let daif = exception::irq_save();
+    let mut sinks = SINKS.lock().unwrap();
+    let registered = sinks.iter().flatten().any(|s| core::ptr::addr_eq(s.console, sink.console));
+    let first_log = !sinks.iter().flatten().any(|s| s.log_level.is_some());
+    let slot = sinks.iter_mut().find(|s| s.is_none());
+    let full = !registered && slot.is_none();
+    let added = !registered && !full;
+    if let (true, Some(slot)) = (added, slot) {
+        *slot = Some(sink);
+    }
+    drop(sinks);
+    exception::irq_restore(daif);
+
+    if full {
+        panic!("too many consoles");
+    }
+    // Everything logged so far had nowhere to go.
+    if let (true, true, Some(level)) = (added, first_log, sink.log_level) {
+        let _guard = CONSOLE_LOCK.lock();
+        crate::log::replay(sink.console, level);
+    }
+}
+
+/// Adds a console, e.g. the framebuffer next to the serial port. It gets all output and the
+/// kernel log up to `info`, and is read from if it has input. Registering one twice has no
+/// effect.
+pub fn register_console(name: &'static str, new_console: ConsoleRef) {
+    add_sink(Sink {
+        name,
+        console: new_console,
+        output: true,
+        input: new_console.has_input(),
+        log_level: Some(LogLevel::Info),
+    });
+}
+
+/// Adds a console that is attached but unused until turned on with `configure_sink`, like the
+/// serial port that isn't the console.
+pub fn register_secondary(name: &'static str, new_console: ConsoleRef) {
+    add_sink(Sink {
+        name,
+        console: new_console,
+        output: false,
+        input: false,
+        log_level: None,
+    });
+}
+
+/// The attached sinks, in registration order.
+pub fn sinks() -> impl Iterator<Item = Sink> {
+    CONSOLE_SET.sinks()
+}
 
-pub fn register_console(new_console: &'static (dyn interface::ReadWrite + Sync)) {
-    let mut lock = CUR_CONSOLE.lock().unwrap();
-    *lock = new_console;
+/// Changes what the sink called `name` is used for.
+pub fn configure_sink(name: &str, f: impl FnOnce(&mut Sink)) -> Result<(), &'static str> {
+    let daif = exception::irq_save();
+    let result = match SINKS.lock().unwrap().iter_mut().flatten().find(|s| s.name == name) {
+        Some(sink) => {
+            f(sink);
+            Ok(())
+        }
+        None => Err("no such console"),
+    };
+    exception::irq_restore(daif);
+
+    // Readers may be waiting on a different set of sources now.
+    input_arrived();
+    result
 }
 
 pub fn console() -> &'static dyn interface::ReadWrite {
-    *CUR_CONSOLE.lock().unwrap()
-}
\ No newline at end of file
+    &CONSOLE_SET
+}
+
+/// Writes a kernel log message to the sinks whose level lets it through.
+pub fn log(level: LogLevel, args: fmt::Arguments) -> fmt::Result {
+    CONSOLE_SET.write_log(level, args)
+}
@@ -0,0 +1,327 @@
+//! Text console drawn into the framebuffer, with scrolling and a subset of the ANSI escape
+//! sequences: SGR colours, cursor movement and erasing.
+
+use core::fmt;
+
+use super::{
+    font::{self, GLYPH_HEIGHT, GLYPH_WIDTH},
+    interface,
+};
+use crate::bsp::device_driver::{Color, Framebuffer};
+use crate::{exception, synchronization::{interface::Mutex, SpinLock}};
+
+/// The 16 VGA colours, bright ones last.
+const PALETTE: [Color; 16] = [
+    Color::new(0, 0, 0),
+    Color::new(170, 0, 0),
+    Color::new(0, 170, 0),
+    Color::new(170, 85, 0),
+    Color::new(0, 0, 170),
+    Color::new(170, 0, 170),
+    Color::new(0, 170, 170),
+    Color::new(170, 170, 170),
+    Color::new(85, 85, 85),
+    Color::new(255, 85, 85),
+    Color::new(85, 255, 85),
+    Color::new(255, 255, 85),
+    Color::new(85, 85, 255),
+    Color::new(255, 85, 255),
+    Color::new(85, 255, 255),
+    Color::new(255, 255, 255),
+];
+
+const DEFAULT_FG: usize = 7;
+const DEFAULT_BG: usize = 0;
+const BRIGHT: usize = 8;
+
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMS: usize = 4;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Normal,
+    Escape,
+    /// Inside `ESC [`, collecting numeric parameters.
+    Csi,
+}
+
+pub struct FramebufferConsole {
+    inner: SpinLock<FramebufferConsoleInner>,
+}
+
+impl FramebufferConsole {
+    pub const fn new(fb: &'static Framebuffer) -> Self {
+        Self {
+            inner: SpinLock::new(FramebufferConsoleInner::new(fb)),
+        }
+    }
+
+    fn with_inner<R>(&self, f: impl FnOnce(&mut FramebufferConsoleInner) -> R) -> R {
+        let daif = exception::irq_save();
+        let result = f(&mut self.inner.lock().unwrap());
+        exception::irq_restore(daif);
+        result
+    }
+}
+
+impl interface::Write for FramebufferConsole {
+    fn write_char(&self, c: char) {
+        self.with_inner(|inner| {
+            inner.set_cursor_visible(false);
+            inner.put(c);
+            inner.set_cursor_visible(true);
+        });
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.with_inner(|inner| {
+            inner.set_cursor_visible(false);
+            let result = fmt::Write::write_fmt(inner, args);
+            inner.set_cursor_visible(true);
+            result
+        })
+    }
+
+    fn flush(&self) {}
+}
+
+impl interface::Read for FramebufferConsole {
+    fn clear_rx(&self) {}
+
+    fn has_input(&self) -> bool {
+        false
+    }
+}
+
+impl interface::ReadWrite for FramebufferConsole {}
+
+struct FramebufferConsoleInner {
+    fb: &'static Framebuffer,
+    col: usize,
+    row: usize,
+    fg: usize,
+    bg: usize,
+    bold: bool,
+    cursor_visible: bool,
+    state: State,
+    params: [usize; MAX_PARAMS],
+    num_params: usize,
+}
+
+impl FramebufferConsoleInner {
+    const fn new(fb: &'static Framebuffer) -> Self {
+        Self {
+            fb,
+            col: 0,
+            row: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            cursor_visible: false,
+            state: State::Normal,
+            params: [0; MAX_PARAMS],
+            num_params: 0,
+        }
+    }
+
+    /// Text columns and rows that fit on the screen.
+    fn dimensions(&self) -> (usize, usize) {
+        let (width, height) = self.fb.size();
+        (width / GLYPH_WIDTH, height / GLYPH_HEIGHT)
+    }
+
+    fn foreground(&self) -> Color {
+        // Bold picks the bright variant, as most terminals do.
+        let idx = if self.bold { self.fg | BRIGHT } else { self.fg };
+        PALETTE[idx]
+    }
+
+    /// The cursor is an inverted underline, drawing it again takes it away.
+    fn set_cursor_visible(&mut self, visible: bool) {
+        let (cols, rows) = self.dimensions();
+        if visible == self.cursor_visible || self.col >= cols || self.row >= rows {
+            return;
+        }
+
+        let x = self.col * GLYPH_WIDTH;
+        let y = self.row * GLYPH_HEIGHT + GLYPH_HEIGHT - 1;
+        self.fb.invert_rect(x, y, GLYPH_WIDTH, 1);
+        self.cursor_visible = visible;
+    }
+
+    fn put(&mut self, c: char) {
+        match self.state {
+            State::Normal => self.put_normal(c),
+            State::Escape => {
+                if c == '[' {
+                    self.params = [0; MAX_PARAMS];
+                    self.num_params = 0;
+                    self.state = State::Csi;
+                } else {
+                    self.state = State::Normal;
+                }
+            }
+            State::Csi => self.put_csi(c),
+        }
+    }
+
+    fn put_normal(&mut self, c: char) {
+        let (cols, _) = self.dimensions();
+        if cols == 0 {
+            return;
+        }
+
+        match c {
+            '\x1b' => self.state = State::Escape,
+            // The serial consoles turn "\n" into "\r\n", this does the same.
+            '\n' => {
+                self.col = 0;
+                self.newline();
+            }
+            '\r' => self.col = 0,
+            '\x08' => self.col = self.col.saturating_sub(1),
+            '\t' => {
+                self.col = (self.col / TAB_WIDTH + 1) * TAB_WIDTH;
+                if self.col >= cols {
+                    self.col = 0;
+                    self.newline();
+                }
+            }
+            c if c.is_control() => {}
+            c => {
+                if self.col >= cols {
+                    self.col = 0;
+                    self.newline();
+                }
+                self.draw_glyph(c);
+                self.col += 1;
+            }
+        }
+    }
+
+    fn put_csi(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                let idx = self.num_params.max(1) - 1;
+                self.num_params = self.num_params.max(1);
+                if let Some(param) = self.params.get_mut(idx) {
+                    *param = param.saturating_mul(10).saturating_add(c as usize - '0' as usize);
+                }
+            }
+            ';' => self.num_params = (self.num_params.max(1) + 1).min(MAX_PARAMS),
+            // Parameter and intermediate bytes of sequences that aren't supported.
+            '\x20'..='\x3f' => {}
+            c => {
+                self.state = State::Normal;
+                self.execute_csi(c);
+            }
+        }
+    }
+
+    fn param(&self, idx: usize, default: usize) -> usize {
+        // Zero and missing parameters both mean the default.
+        match self.params[idx] {
+            0 => default,
+            value => value,
+        }
+    }
+
+    fn execute_csi(&mut self, command: char) {
+        let (cols, rows) = self.dimensions();
+
+        match command {
+            'm' => self.select_graphic_rendition(),
+            'A' => self.row = self.row.saturating_sub(self.param(0, 1)),
+            'B' => self.row = (self.row + self.param(0, 1)).min(rows.saturating_sub(1)),
+            'C' => self.col = (self.col + self.param(0, 1)).min(cols.saturating_sub(1)),
+            'D' => self.col = self.col.saturating_sub(self.param(0, 1)),
+            'H' | 'f' => {
+                self.row = (self.param(0, 1) - 1).min(rows.saturating_sub(1));
+                self.col = (self.param(1, 1) - 1).min(cols.saturating_sub(1));
+            }
+            'J' => match self.param(0, 0) {
+                // To the end of the screen.
+                0 => {
+                    self.clear_cells(self.row, self.col, cols);
+                    self.clear_rows(self.row + 1, rows);
+                }
+                1 => {
+                    self.clear_rows(0, self.row);
+                    self.clear_cells(self.row, 0, self.col + 1);
+                }
+                _ => self.clear_rows(0, rows),
+            },
+            'K' => match self.param(0, 0) {
+                0 => self.clear_cells(self.row, self.col, cols),
+                1 => self.clear_cells(self.row, 0, self.col + 1),
+                _ => self.clear_cells(self.row, 0, cols),
+            },
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        for idx in 0..self.num_params.max(1) {
+            match self.params[idx] {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                code @ 30..=37 => self.fg = code - 30,
+                39 => self.fg = DEFAULT_FG,
+                code @ 40..=47 => self.bg = code - 40,
+                49 => self.bg = DEFAULT_BG,
+                code @ 90..=97 => self.fg = code - 90 + BRIGHT,
+                code @ 100..=107 => self.bg = code - 100 + BRIGHT,
+                _ => {}
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        let (_, rows) = self.dimensions();
+        if self.row + 1 < rows {
+            self.row += 1;
+            return;
+        }
+
+        self.fb.scroll_up(GLYPH_HEIGHT, PALETTE[self.bg]);
+        self.clear_rows(rows - 1, rows);
+    }
+
+    fn draw_glyph(&self, c: char) {
+        let glyph = font::glyph(c);
+        let (fg, bg) = (self.foreground(), PALETTE[self.bg]);
+
+        let mut pixels = [bg; GLYPH_WIDTH * GLYPH_HEIGHT];
+        for (y, bits) in glyph.iter().enumerate() {
+            for x in (0..GLYPH_WIDTH).filter(|x| bits & (1 << x) != 0) {
+                pixels[y * GLYPH_WIDTH + x] = fg;
+            }
+        }
+        self.fb.blit(self.col * GLYPH_WIDTH, self.row * GLYPH_HEIGHT, GLYPH_WIDTH, &pixels);
+    }
+
+    /// Clears columns `from..to` of `row`.
+    fn clear_cells(&self, row: usize, from: usize, to: usize) {
+        let width = to.saturating_sub(from) * GLYPH_WIDTH;
+        self.fb.fill_rect(from * GLYPH_WIDTH, row * GLYPH_HEIGHT, width, GLYPH_HEIGHT, PALETTE[self.bg]);
+    }
+
+    /// Clears rows `from..to` entirely.
+    fn clear_rows(&self, from: usize, to: usize) {
+        let (width, _) = self.fb.size();
+        let height = to.saturating_sub(from) * GLYPH_HEIGHT;
+        self.fb.fill_rect(0, from * GLYPH_HEIGHT, width, height, PALETTE[self.bg]);
+    }
+}
+
+impl fmt::Write for FramebufferConsoleInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(|c| self.put(c));
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+//! Serialises console output across cores, so every `println!` and log line comes out whole.
+//!
+//! IRQs stay masked while the lock is held, which also keeps the holder from being scheduled
+//! away. The UARTs still queue into their transmit buffers and only write polled once a buffer
+//! is full, the transmit interrupt catches up after the lock is released. The owning core may
+//! take the lock again, e.g. for a panic raised while printing, and once a core panics, waiting
+//! for another core to finish is given up on.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{exception, utils::get_core};
+
+const NO_OWNER: usize = usize::MAX;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+pub struct ConsoleLock {
+    owner: AtomicUsize,
+    /// Only touched by the owning core.
+    depth: AtomicUsize,
+}
+
+impl ConsoleLock {
+    pub const fn new() -> Self {
+        Self {
+            owner: AtomicUsize::new(NO_OWNER),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn lock(&self) -> ConsoleGuard<'_> {
+        let daif = exception::irq_save();
+        let core = get_core() as usize;
+
+        // Only this core ever stores its own number, so the check can't race.
+        if self.owner.load(Ordering::Relaxed) != core {
+            while self
+                .owner
+                .compare_exchange_weak(NO_OWNER, core, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                if PANICKING.load(Ordering::Relaxed) {
+                    return ConsoleGuard { lock: self, daif, owned: false };
+                }
+                spin_loop();
+            }
+        }
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        ConsoleGuard { lock: self, daif, owned: true }
+    }
+}
+
+pub struct ConsoleGuard<'a> {
+    lock: &'a ConsoleLock,
+    daif: usize,
+    /// False if the lock was bypassed during a panic.
+    owned: bool,
+}
+
+impl Drop for ConsoleGuard<'_> {
+    fn drop(&mut self) {
+        if self.owned && self.lock.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.lock.owner.store(NO_OWNER, Ordering::Release);
+        }
+        exception::irq_restore(self.daif);
+    }
+}
+
+/// From now on, output no longer waits for other cores. The panic message must get out even if
+/// the core holding the lock never lets go of it.
+pub fn enter_panic_mode() {
+    PANICKING.store(true, Ordering::Relaxed);
+}
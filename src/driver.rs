@@ -1,10 +1,15 @@
-use crate::{info, synchronization::{interface::Mutex, SpinLock}};
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::{dtb, exception, error, info, println, synchronization::{interface::Mutex, SpinLock}};
 
 static DRIVER_MANAGER: DriverManager = DriverManager::new();
 
-const NUM_DRIVERS: usize = 0;
+pub fn driver_manager() -> &'static DriverManager {
+    &DRIVER_MANAGER
+}
 
-struct DriverManager {
+pub struct DriverManager {
     inner: SpinLock<DriverManagerInner>,
 }
 
@@ -16,69 +21,217 @@ impl DriverManager {
     }
 
     pub fn register_driver(&self, descriptor: DeviceDriverDescriptor) {
-        let mut inner = self.inner.lock().unwrap();
-        let idx = inner.next_index;
-        inner.descriptors[idx] = Some(descriptor);
-        inner.next_index += 1;
+        let daif = exception::irq_save();
+        self.inner.lock().unwrap().entries.push(DriverEntry {
+            descriptor,
+            state: DriverState::Registered,
+            node: None,
+        });
+        exception::irq_restore(daif);
     }
 
-    fn for_each_descriptor(&self, f: impl FnMut(& DeviceDriverDescriptor)) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.descriptors.iter().filter_map(|x| x.as_ref()).for_each(f);
+    fn with_inner<R>(&self, f: impl FnOnce(&mut DriverManagerInner) -> R) -> R {
+        let daif = exception::irq_save();
+        let result = f(&mut self.inner.lock().unwrap());
+        exception::irq_restore(daif);
+        result
     }
 
+    /// Initializes every registered driver once all of its dependencies have been initialized.
+    ///
+    /// If the firmware passed a device tree, only drivers with a matching `compatible` node are
+    /// bound; without one every driver is assumed to have its device present. A driver that
+    /// fails, or whose dependencies failed or are missing, is recorded as such and skipped; the
+    /// remaining drivers are still brought up.
+    ///
+    /// # Safety
+    ///
+    /// Calls into each driver's `init`, which may touch hardware directly.
     pub unsafe fn init_drivers(&self) {
-        self.for_each_descriptor(|descriptor| {
-            // 1. Initialize driver.
-            if let Err(x) = descriptor.device_driver.init() {
-                panic!(
-                    "Error initializing driver: {}: {}",
-                    descriptor.device_driver.compatible(),
-                    x
-                );
-            }
+        if let Some(dt) = dtb::device_tree() {
+            self.with_inner(|inner| inner.bind(&dt));
+        }
 
-            // 2. Call corresponding post init callback.
-            if let Some(callback) = &descriptor.post_init_callback {
-                if let Err(x) = callback() {
-                    panic!(
-                        "Error during driver post-init callback: {}: {}",
-                        descriptor.device_driver.compatible(),
-                        x
-                    );
+        while let Some((idx, descriptor)) = self.with_inner(|inner| inner.next_ready()) {
+            let state = match Self::init_driver(&descriptor) {
+                Ok(()) => DriverState::Initialized,
+                Err(x) => {
+                    error!("Error initializing driver: {}: {}", descriptor.device_driver.compatible(), x);
+                    DriverState::Failed(x)
                 }
+            };
+            self.with_inner(|inner| inner.entries[idx].state = state);
+        }
+
+        // Whatever is still waiting depends on itself in some way.
+        self.with_inner(|inner| {
+            for entry in inner.entries.iter_mut().filter(|e| e.state == DriverState::Registered) {
+                entry.state = DriverState::Failed("circular dependency");
             }
         });
     }
 
+    unsafe fn init_driver(descriptor: &DeviceDriverDescriptor) -> Result<(), &'static str> {
+        // 1. Initialize driver.
+        descriptor.device_driver.init()?;
+
+        // 2. Call corresponding post init callback.
+        if let Some(callback) = &descriptor.post_init_callback {
+            callback()?;
+        }
+
+        Ok(())
+    }
+
+    /// Logs the drivers and their state while booting.
     pub fn enumerate(&self) {
-        let mut i: usize = 1;
-        self.for_each_descriptor(|descriptor| {
-            info!("      {}. {}", i, descriptor.device_driver.compatible());
+        for line in self.describe() {
+            info!("      {}", line);
+        }
+    }
 
-            i += 1;
-        });
+    /// Prints the drivers and their state, for the `drivers` command.
+    pub fn print_drivers(&self) {
+        for line in self.describe() {
+            println!("  {}", line);
+        }
+    }
+
+    fn describe(&self) -> Vec<String> {
+        let entries = self.with_inner(|inner| inner.entries.clone());
+        let mut lines = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let compatible = entry.descriptor.device_driver.compatible();
+            match entry.state {
+                DriverState::Registered => lines.push(format!("{}. {} (not initialized)", i + 1, compatible)),
+                DriverState::Initialized => match entry.node {
+                    Some(node) => {
+                        lines.push(format!("{}. {} ({})", i + 1, compatible, node.name));
+                        if let Some((addr, size)) = node.reg().next() {
+                            lines.push(format!("     reg 0x{:X} + 0x{:X}", addr, size));
+                        }
+                        let mut irqs = String::new();
+                        for cell in node.interrupts() {
+                            let _ = write!(irqs, " {}", cell);
+                        }
+                        if !irqs.is_empty() {
+                            lines.push(format!("     interrupts{}", irqs));
+                        }
+                    }
+                    None => lines.push(format!("{}. {}", i + 1, compatible)),
+                },
+                DriverState::NotPresent => lines.push(format!("{}. {} (not in device tree)", i + 1, compatible)),
+                DriverState::Failed(x) => lines.push(format!("{}. {} (failed: {})", i + 1, compatible, x)),
+                DriverState::Blocked(dep) => lines.push(format!("{}. {} (skipped, needs {})", i + 1, compatible, dep)),
+            }
+        }
+        lines
     }
 }
 
+#[derive(Copy, Clone, PartialEq)]
+enum DriverState {
+    Registered,
+    Initialized,
+    Failed(&'static str),
+    /// The device tree has no enabled node for this driver.
+    NotPresent,
+    /// A dependency failed or was never registered.
+    Blocked(&'static str),
+}
+
+#[derive(Copy, Clone)]
+struct DriverEntry {
+    descriptor: DeviceDriverDescriptor,
+    state: DriverState,
+    /// Name of the device tree node the driver was bound to.
+    node: Option<dtb::Node>,
+}
+
 struct DriverManagerInner {
-    next_index: usize,
-    descriptors: [Option<DeviceDriverDescriptor>; NUM_DRIVERS],
+    entries: Vec<DriverEntry>,
 }
 
 impl DriverManagerInner {
     pub const fn new() -> Self {
         Self {
-            next_index: 0,
-            descriptors: [None; NUM_DRIVERS],
+            entries: Vec::new(),
         }
     }
+
+    fn bind(&mut self, dt: &dtb::DeviceTree) {
+        for idx in 0..self.entries.len() {
+            if self.entries[idx].state != DriverState::Registered {
+                continue;
+            }
+
+            // Drivers sharing a compatible string, e.g. several instances of one peripheral,
+            // are told apart by their register address.
+            let driver = self.entries[idx].descriptor.device_driver;
+            let node = dt.find_compatible(driver.compatible()).filter(|node| node.is_enabled()).find(|node| {
+                match driver.mmio_start_addr() {
+                    Some(addr) => node.address == Some(addr as u64),
+                    None => true,
+                }
+            });
+
+            match node {
+                Some(node) => self.entries[idx].node = Some(node),
+                None => self.entries[idx].state = DriverState::NotPresent,
+            }
+        }
+    }
+
+    fn state_of(&self, compatible: &str) -> Option<DriverState> {
+        self.entries
+            .iter()
+            .find(|e| e.descriptor.device_driver.compatible() == compatible)
+            .map(|e| e.state)
+    }
+
+    /// Finds the next driver whose dependencies are all initialized, marking drivers that can
+    /// never be initialized as blocked along the way.
+    fn next_ready(&mut self) -> Option<(usize, DeviceDriverDescriptor)> {
+        for idx in 0..self.entries.len() {
+            if self.entries[idx].state != DriverState::Registered {
+                continue;
+            }
+
+            let mut ready = true;
+            for &dep in self.entries[idx].descriptor.dependencies {
+                match self.state_of(dep) {
+                    Some(DriverState::Initialized) => {}
+                    Some(DriverState::Registered) => ready = false,
+                    Some(DriverState::Failed(_))
+                    | Some(DriverState::NotPresent)
+                    | Some(DriverState::Blocked(_))
+                    | None => {
+                        self.entries[idx].state = DriverState::Blocked(dep);
+                        ready = false;
+                        break;
+                    }
+                }
+            }
+
+            if ready {
+                return Some((idx, self.entries[idx].descriptor));
+            }
+        }
+        None
+    }
 }
 
 pub mod interface {
     pub trait DeviceDriver {
+        /// Matched against the `compatible` property of device tree nodes.
         fn compatible(&self) -> &'static str;
 
+        /// Base address of the registers, needed by drivers of peripherals with several
+        /// instances to find their device tree node.
+        fn mmio_start_addr(&self) -> Option<usize> {
+            None
+        }
+
         unsafe fn init(&self) -> Result<(), &'static str> {
             Ok(())
         }
@@ -91,4 +244,20 @@ pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), &'static str>;
 pub struct DeviceDriverDescriptor {
     device_driver: &'static (dyn interface::DeviceDriver + Sync),
     post_init_callback: Option<DeviceDriverPostInitCallback>,
+    /// `compatible()` strings of drivers that must be initialized first.
+    dependencies: &'static [&'static str],
+}
+
+impl DeviceDriverDescriptor {
+    pub fn new(
+        device_driver: &'static (dyn interface::DeviceDriver + Sync),
+        post_init_callback: Option<DeviceDriverPostInitCallback>,
+        dependencies: &'static [&'static str],
+    ) -> Self {
+        Self {
+            device_driver,
+            post_init_callback,
+            dependencies,
+        }
+    }
 }
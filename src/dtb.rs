@@ -0,0 +1,423 @@
+//! Flattened device tree (FDT) parser.
+//!
+//! The firmware passes the address of the blob in x0. It is validated before the MMU comes up,
+//! mapped read-only alongside the kernel and then parsed in place, nothing is copied.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Defaults from the devicetree specification for nodes without `#address-cells`/`#size-cells`.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+const MAX_DEPTH: usize = 16;
+
+/// Property values longer than this many cells are cut short by `print`.
+const MAX_PRINTED_CELLS: usize = 16;
+
+static DTB_ADDR: AtomicUsize = AtomicUsize::new(0);
+static DTB_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks the header of the blob at `addr` and remembers it if it is usable.
+///
+/// # Safety
+///
+/// Must be called before the MMU is enabled, while `addr` is still identity mapped.
+pub unsafe fn init(addr: usize) -> Result<(), &'static str> {
+    if addr == 0 {
+        return Err("no device tree passed by the firmware");
+    }
+    if addr % 8 != 0 {
+        return Err("device tree is misaligned");
+    }
+
+    let header = core::slice::from_raw_parts(addr as *const u8, FDT_HEADER_SIZE);
+    if be32(header, 0) != Some(FDT_MAGIC) {
+        return Err("bad device tree magic");
+    }
+    if be32(header, 24).is_none_or(|v| v > FDT_LAST_COMP_VERSION) {
+        return Err("unsupported device tree version");
+    }
+
+    let size = be32(header, 4).unwrap() as usize;
+    if size < FDT_HEADER_SIZE || !crate::bsp::memory::is_free_ram(addr, addr + size) {
+        return Err("device tree overlaps the kernel or lies outside of RAM");
+    }
+
+    DTB_ADDR.store(addr, Ordering::Relaxed);
+    DTB_SIZE.store(size, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Physical range occupied by the blob, empty if there is none.
+pub fn blob_range() -> (usize, usize) {
+    let addr = DTB_ADDR.load(Ordering::Relaxed);
+    (addr, addr + DTB_SIZE.load(Ordering::Relaxed))
+}
+
+pub fn device_tree() -> Option<DeviceTree> {
+    let (start, end) = blob_range();
+    if start == 0 {
+        return None;
+    }
+
+    let blob = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    DeviceTree::new(blob)
+}
+
+fn be32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a number made up of `cells` big-endian 32-bit cells.
+fn read_cells(buf: &[u8], offset: usize, cells: u32) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..cells as usize {
+        value = (value << 32) | be32(buf, offset + i * 4)? as u64;
+    }
+    Some(value)
+}
+
+fn c_str(buf: &[u8], offset: usize) -> Option<&str> {
+    let bytes = buf.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+#[derive(Copy, Clone)]
+pub struct DeviceTree {
+    structs: &'static [u8],
+    strings: &'static [u8],
+}
+
+impl DeviceTree {
+    fn new(blob: &'static [u8]) -> Option<Self> {
+        let struct_off = be32(blob, 8)? as usize;
+        let strings_off = be32(blob, 12)? as usize;
+        let strings_size = be32(blob, 32)? as usize;
+        let struct_size = be32(blob, 36)? as usize;
+
+        Some(Self {
+            structs: blob.get(struct_off..struct_off.checked_add(struct_size)?)?,
+            strings: blob.get(strings_off..strings_off.checked_add(strings_size)?)?,
+        })
+    }
+
+    /// All nodes in depth-first order, starting with the root node.
+    pub fn nodes(&self) -> NodeIter {
+        NodeIter {
+            dt: *self,
+            offset: 0,
+            depth: 0,
+            cells: [(DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS); MAX_DEPTH + 1],
+            ranges: [None; MAX_DEPTH + 1],
+        }
+    }
+
+    pub fn find_compatible<'a>(&self, compatible: &'a str) -> impl Iterator<Item = Node> + 'a {
+        self.nodes().filter(move |n| n.is_compatible(compatible))
+    }
+
+    pub fn print(&self) {
+        let mut open = 0;
+        for node in self.nodes() {
+            while open > node.depth {
+                open -= 1;
+                crate::println!("{:indent$}}};", "", indent = open * 2);
+            }
+
+            let name = if node.depth == 0 { "/" } else { node.name };
+            crate::println!("{:indent$}{} {{", "", name, indent = node.depth * 2);
+            for prop in node.properties() {
+                crate::print!("{:indent$}", "", indent = node.depth * 2 + 2);
+                prop.print();
+            }
+            open = node.depth + 1;
+        }
+        while open > 0 {
+            open -= 1;
+            crate::println!("{:indent$}}};", "", indent = open * 2);
+        }
+    }
+
+    fn token(&self, offset: usize) -> Option<u32> {
+        be32(self.structs, offset)
+    }
+
+    /// Offset just past the NUL-terminated, 4-byte padded name of a node.
+    fn skip_name(&self, offset: usize) -> Option<(&'static str, usize)> {
+        let name = c_str(self.structs, offset)?;
+        Some((name, (offset + name.len() + 1).next_multiple_of(4)))
+    }
+
+    /// Parses the property at `offset`, which must point past its `FDT_PROP` token.
+    fn property(&self, offset: usize) -> Option<(Property, usize)> {
+        let len = be32(self.structs, offset)? as usize;
+        let name_off = be32(self.structs, offset + 4)? as usize;
+        let value_off = offset + 8;
+
+        let property = Property {
+            name: c_str(self.strings, name_off)?,
+            value: self.structs.get(value_off..value_off + len)?,
+        };
+        Some((property, (value_off + len).next_multiple_of(4)))
+    }
+}
+
+/// Depth-first walk over the structure block.
+pub struct NodeIter {
+    dt: DeviceTree,
+    offset: usize,
+    depth: usize,
+    /// `#address-cells` and `#size-cells` that apply to the children of the node at each depth.
+    cells: [(u32, u32); MAX_DEPTH + 1],
+    /// `ranges` of the node at each depth.
+    ranges: [Option<Property>; MAX_DEPTH + 1],
+}
+
+impl NodeIter {
+    /// Translates an address on the bus of the node at `depth` up through the `ranges` of its
+    /// ancestors. `None` if a bus on the way has no `ranges`, i.e. isn't memory mapped.
+    fn translate(&self, mut addr: u64, depth: usize) -> Option<u64> {
+        for d in (1..=depth).rev() {
+            let ranges = self.ranges[d]?;
+            let (child_cells, size_cells) = self.cells[d];
+            let parent_cells = self.cells[d - 1].0;
+            let entry_size = (child_cells + parent_cells + size_cells) as usize * 4;
+
+            // Empty `ranges` maps the bus one to one.
+            if ranges.value.is_empty() {
+                continue;
+            }
+            addr = (0..ranges.value.len().checked_div(entry_size).unwrap_or(0)).find_map(|i| {
+                let offset = i * entry_size;
+                let child = read_cells(ranges.value, offset, child_cells)?;
+                let parent = read_cells(ranges.value, offset + child_cells as usize * 4, parent_cells)?;
+                let size = read_cells(ranges.value, offset + (child_cells + parent_cells) as usize * 4, size_cells)?;
+                (child..child.checked_add(size)?).contains(&addr).then(|| addr - child + parent)
+            })?;
+        }
+        Some(addr)
+    }
+}
+
+impl Iterator for NodeIter {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        loop {
+            let token = self.dt.token(self.offset)?;
+            self.offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    if self.depth > MAX_DEPTH {
+                        return None;
+                    }
+                    let (name, props_offset) = self.dt.skip_name(self.offset)?;
+                    self.offset = props_offset;
+
+                    let (address_cells, size_cells) = if self.depth == 0 {
+                        (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS)
+                    } else {
+                        self.cells[self.depth - 1]
+                    };
+                    let mut node = Node {
+                        dt: self.dt,
+                        name,
+                        depth: self.depth,
+                        props_offset,
+                        address_cells,
+                        size_cells,
+                        address: None,
+                    };
+                    // The first `reg` address is on the bus of the parent, one level up.
+                    node.address = match (node.reg().next(), self.depth.checked_sub(1)) {
+                        (Some((addr, _)), Some(parent)) => self.translate(addr, parent),
+                        _ => None,
+                    };
+
+                    self.cells[self.depth] = (
+                        node.property("#address-cells").and_then(|p| p.as_u32()).unwrap_or(DEFAULT_ADDRESS_CELLS),
+                        node.property("#size-cells").and_then(|p| p.as_u32()).unwrap_or(DEFAULT_SIZE_CELLS),
+                    );
+                    self.ranges[self.depth] = node.property("ranges");
+                    self.depth += 1;
+                    return Some(node);
+                }
+                FDT_END_NODE => self.depth = self.depth.checked_sub(1)?,
+                FDT_PROP => self.offset = self.dt.property(self.offset)?.1,
+                FDT_NOP => {}
+                FDT_END => return None,
+                // Malformed blob.
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Node {
+    dt: DeviceTree,
+    pub name: &'static str,
+    pub depth: usize,
+    props_offset: usize,
+    /// Cell counts defined by the parent, used to decode `reg`.
+    address_cells: u32,
+    size_cells: u32,
+    /// The first `reg` address as the CPU sees it, translated through the parents' `ranges`.
+    pub address: Option<u64>,
+}
+
+impl Node {
+    pub fn properties(&self) -> PropertyIter {
+        PropertyIter {
+            dt: self.dt,
+            offset: self.props_offset,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    pub fn compatible(&self) -> impl Iterator<Item = &'static str> {
+        self.property("compatible").into_iter().flat_map(|p| p.as_str_list())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// Whether the device is in use, i.e. its `status` is absent or `"okay"`.
+    pub fn is_enabled(&self) -> bool {
+        self.property("status")
+            .and_then(|p| p.as_str_list().next())
+            .is_none_or(|status| status == "okay" || status == "ok")
+    }
+
+    /// `(address, size)` pairs of the `reg` property, as seen from the parent bus.
+    pub fn reg(&self) -> impl Iterator<Item = (u64, u64)> {
+        let value = self.property("reg").map_or(&[][..], |p| p.value);
+        let (address_cells, size_cells) = (self.address_cells, self.size_cells);
+        let entry_size = (address_cells + size_cells) as usize * 4;
+
+        (0..value.len().checked_div(entry_size).unwrap_or(0)).filter_map(move |i| {
+            let offset = i * entry_size;
+            Some((
+                read_cells(value, offset, address_cells)?,
+                read_cells(value, offset + address_cells as usize * 4, size_cells)?,
+            ))
+        })
+    }
+
+    /// Raw cells of the `interrupts` property. How they group into specifiers depends on the
+    /// `#interrupt-cells` of the node's interrupt parent.
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> {
+        self.property("interrupts").into_iter().flat_map(|p| p.cells())
+    }
+}
+
+pub struct PropertyIter {
+    dt: DeviceTree,
+    offset: usize,
+}
+
+impl Iterator for PropertyIter {
+    type Item = Property;
+
+    fn next(&mut self) -> Option<Property> {
+        loop {
+            match self.dt.token(self.offset)? {
+                FDT_PROP => {
+                    let (property, next) = self.dt.property(self.offset + 4)?;
+                    self.offset = next;
+                    return Some(property);
+                }
+                FDT_NOP => self.offset += 4,
+                // Properties always precede child nodes.
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Property {
+    pub name: &'static str,
+    pub value: &'static [u8],
+}
+
+impl Property {
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() == 4 {
+            be32(self.value, 0)
+        } else {
+            None
+        }
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = u32> {
+        let value = self.value;
+        (0..value.len() / 4).filter_map(move |i| be32(value, i * 4))
+    }
+
+    pub fn as_str_list(&self) -> impl Iterator<Item = &'static str> {
+        let value = self.value.strip_suffix(&[0]).unwrap_or(self.value);
+        value
+            .split(|&b| b == 0)
+            .filter_map(|s| core::str::from_utf8(s).ok())
+            .filter(|s| !s.is_empty())
+    }
+
+    fn is_printable(&self) -> bool {
+        match self.value.split_last() {
+            Some((0, rest)) => {
+                !rest.is_empty()
+                    && rest.first() != Some(&0)
+                    && rest.iter().all(|&b| b == 0 || (0x20..0x7F).contains(&b))
+            }
+            _ => false,
+        }
+    }
+
+    fn print(&self) {
+        if self.value.is_empty() {
+            crate::println!("{};", self.name);
+        } else if self.is_printable() {
+            crate::print!("{} =", self.name);
+            for (i, s) in self.as_str_list().enumerate() {
+                crate::print!("{} \"{}\"", if i == 0 { "" } else { "," }, s);
+            }
+            crate::println!(";");
+        } else if self.value.len() % 4 == 0 {
+            crate::print!("{} = <", self.name);
+            for (i, cell) in self.cells().take(MAX_PRINTED_CELLS).enumerate() {
+                crate::print!("{}0x{:x}", if i == 0 { "" } else { " " }, cell);
+            }
+            if self.value.len() / 4 > MAX_PRINTED_CELLS {
+                crate::print!(" ...");
+            }
+            crate::println!(">;");
+        } else {
+            crate::print!("{} = [", self.name);
+            for (i, b) in self.value.iter().take(MAX_PRINTED_CELLS * 4).enumerate() {
+                crate::print!("{}{:02x}", if i == 0 { "" } else { " " }, b);
+            }
+            if self.value.len() > MAX_PRINTED_CELLS * 4 {
+                crate::print!(" ...");
+            }
+            crate::println!("];");
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use core::arch::global_asm;
 
-use crate::{print, println, utils::get_core};
+use crate::{error, utils::get_core};
 
 global_asm!(include_str!("exception.s"));
 
@@ -8,6 +8,14 @@ global_asm!(include_str!("exception.s"));
 #[path = "_arch/aarch64/exception.rs"]
 mod arch_exception;
 
+pub mod interface {
+    /// Implemented by drivers that service a peripheral interrupt.
+    pub trait IrqHandler {
+        /// Called in IRQ context, with IRQs masked.
+        fn handle(&self);
+    }
+}
+
 
 const EXCEPTION_ERROR_MESSAGES: [&str; 16] = [
     "SYNC_INVALID_EL1t",
@@ -34,17 +42,18 @@ const EXCEPTION_ERROR_MESSAGES: [&str; 16] = [
 
 #[no_mangle]
 pub fn show_invalid_entry_message(exception_type: usize, esr_el1: usize, elr_el1: usize, sp: usize) {
-    println!("[core {}] invalid exception: {}, ESR_EL1: {:x}, ELR_EL1: {:x}\n\nRegister dump:", get_core(), EXCEPTION_ERROR_MESSAGES[exception_type], esr_el1, elr_el1);
-    unsafe {
-        let sp = *(sp as *const [u64; 32]);
-        for i in 0..32 {
-            print!("x{:<2}: 0x{:016X}  ", i, sp[i]);
-            if (i+1) % 4 == 0{
-                println!();
-            }
-        }
+    // Logged rather than printed, the interrupted process's stdout may well be a pipe.
+    error!("[core {}] invalid exception: {}, ESR_EL1: {:x}, ELR_EL1: {:x}", get_core(), EXCEPTION_ERROR_MESSAGES[exception_type], esr_el1, elr_el1);
+    error!("Register dump:");
+    let regs = unsafe { *(sp as *const [u64; 32]) };
+    for (row, regs) in regs.chunks(4).enumerate() {
+        error!(
+            "x{:<2}: 0x{:016X}  x{:<2}: 0x{:016X}  x{:<2}: 0x{:016X}  x{:<2}: 0x{:016X}",
+            row * 4, regs[0], row * 4 + 1, regs[1], row * 4 + 2, regs[2], row * 4 + 3, regs[3]
+        );
     }
-    println!();
+    // IRQs stay masked from here on, nothing else would send what the UARTs still buffer.
+    crate::console::console().flush();
     loop {}
 }
 
@@ -58,4 +67,25 @@ pub fn irq_disable() {
     unsafe {
         core::arch::asm!("msr daifset, #2");
     }
-}
\ No newline at end of file
+}
+
+/// Masks IRQs on the current core and returns the previous DAIF value for `irq_restore`.
+pub fn irq_save() -> usize {
+    let daif: usize;
+    unsafe {
+        core::arch::asm!("mrs {}, daif", out(reg) daif);
+    }
+    irq_disable();
+    daif
+}
+
+/// Whether IRQs were unmasked according to a DAIF value saved by `irq_save`.
+pub fn irqs_enabled(daif: usize) -> bool {
+    daif & (1 << 7) == 0
+}
+
+pub fn irq_restore(daif: usize) {
+    unsafe {
+        core::arch::asm!("msr daif, {}", in(reg) daif);
+    }
+}
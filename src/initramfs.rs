@@ -0,0 +1,17 @@
+//! Files built into the kernel image and copied into the ramfs at boot, e.g. scripts for the
+//! shell to `source`. They live in `initramfs/`, add new ones to `FILES`.
+
+use crate::{error, ramfs};
+
+const FILES: [(&str, &[u8]); 2] = [
+    ("boot.sh", include_bytes!("../initramfs/boot.sh")),
+    ("tests.sh", include_bytes!("../initramfs/tests.sh")),
+];
+
+pub fn load() {
+    for (name, data) in FILES {
+        if let Err(e) = ramfs::create(name).and_then(|()| ramfs::append(name, data)) {
+            error!("initramfs: {}: {}", name, e);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Kernel log: every `error!`, `warn!`, `info!` and `debug!` message goes into a ring buffer,
+//! whatever the consoles let through. Messages logged before the first console is registered
+//! are replayed to it.
+//!
+//! Writers claim a slot with a single atomic increment, so logging never waits on a lock and is
+//! safe from IRQ handlers and the panic handler.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{fence, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::console::{interface::Write, LogLevel};
+use crate::{console, scheduler, time, utils::get_core};
+
+const NUM_RECORDS: usize = 256;
+/// Longer messages are truncated in the ring, consoles still get them whole.
+const TEXT_LEN: usize = 120;
+
+#[derive(Copy, Clone)]
+pub struct Record {
+    pub timestamp: Duration,
+    pub level: LogLevel,
+    pub core: u8,
+    pub pid: Option<usize>,
+    len: usize,
+    text: [u8; TEXT_LEN],
+}
+
+impl Record {
+    const fn empty() -> Self {
+        Self {
+            timestamp: Duration::ZERO,
+            level: LogLevel::Info,
+            core: 0,
+            pid: None,
+            len: 0,
+            text: [0; TEXT_LEN],
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        // Truncation only ever happens on a char boundary.
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("<invalid>")
+    }
+}
+
+impl fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(TEXT_LEN - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// A record and a stamp saying which message it holds: odd while being written, `2 * seq + 2`
+/// once message `seq` is complete.
+struct Slot {
+    stamp: AtomicU64,
+    record: UnsafeCell<Record>,
+}
+
+struct Ring {
+    next: AtomicU64,
+    /// Messages before this one were cleared.
+    start: AtomicU64,
+    slots: [Slot; NUM_RECORDS],
+}
+
+unsafe impl Sync for Ring {}
+
+static RING: Ring = Ring {
+    next: AtomicU64::new(0),
+    start: AtomicU64::new(0),
+    slots: [const {
+        Slot {
+            stamp: AtomicU64::new(0),
+            record: UnsafeCell::new(Record::empty()),
+        }
+    }; NUM_RECORDS],
+};
+
+impl Ring {
+    fn push(&self, record: &Record) {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[seq as usize % NUM_RECORDS];
+
+        slot.stamp.store(2 * seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { core::ptr::write_volatile(slot.record.get(), *record) };
+        slot.stamp.store(2 * seq + 2, Ordering::Release);
+    }
+
+    /// Message `seq`, unless it is still being written or has been overwritten.
+    fn get(&self, seq: u64) -> Option<Record> {
+        let slot = &self.slots[seq as usize % NUM_RECORDS];
+
+        let stamp = slot.stamp.load(Ordering::Acquire);
+        if stamp != 2 * seq + 2 {
+            return None;
+        }
+        let record = unsafe { core::ptr::read_volatile(slot.record.get()) };
+        fence(Ordering::Acquire);
+        (slot.stamp.load(Ordering::Relaxed) == stamp).then_some(record)
+    }
+
+    /// Sequence numbers of the messages still in the ring.
+    fn range(&self) -> core::ops::Range<u64> {
+        let next = self.next.load(Ordering::Acquire);
+        let start = self.start.load(Ordering::Relaxed);
+        next.saturating_sub(NUM_RECORDS as u64).max(start)..next
+    }
+}
+
+/// The tag in front of console lines: blank for info, so plain messages read as before.
+fn tag(level: LogLevel) -> char {
+    match level {
+        LogLevel::Error => 'E',
+        LogLevel::Warn => 'W',
+        LogLevel::Info => ' ',
+        LogLevel::Debug => 'D',
+    }
+}
+
+/// A message as printed on the consoles.
+struct Line<T> {
+    level: LogLevel,
+    timestamp: Duration,
+    message: T,
+}
+
+impl<T: fmt::Display> fmt::Display for Line<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (secs, micros) = (self.timestamp.as_secs(), self.timestamp.subsec_micros());
+        writeln!(f, "[{} {:>3}.{:06}] {}", tag(self.level), secs, micros, self.message)
+    }
+}
+
+/// Records a message and prints it on the consoles whose level lets it through.
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    let mut record = Record {
+        timestamp: time::time_manager().uptime(),
+        level,
+        core: get_core(),
+        pid: scheduler::current_pid(),
+        ..Record::empty()
+    };
+    let _ = fmt::Write::write_fmt(&mut record, args);
+    RING.push(&record);
+
+    let line = Line {
+        level,
+        timestamp: record.timestamp,
+        message: args,
+    };
+    let _ = console::log(level, format_args!("{}", line));
+}
+
+/// The messages still in the ring, oldest first.
+pub fn records() -> impl Iterator<Item = Record> {
+    RING.range().filter_map(|seq| RING.get(seq))
+}
+
+/// Forgets the messages logged so far.
+pub fn clear() {
+    RING.start.store(RING.next.load(Ordering::Acquire), Ordering::Relaxed);
+}
+
+/// Prints what was logged so far on a console that has just been attached, for messages that
+/// had nowhere to go.
+pub fn replay(console: &dyn Write, max_level: LogLevel) {
+    for record in records().filter(|r| r.level <= max_level) {
+        let line = Line {
+            level: record.level,
+            timestamp: record.timestamp,
+            message: record.text(),
+        };
+        let _ = console.write_fmt(format_args!("{}", line));
+    }
+}
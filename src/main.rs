@@ -2,14 +2,21 @@
 #![no_std]
 #![allow(unstable_features)]
 #![feature(format_args_nl)]
+#![feature(alloc_error_handler)]
 
 mod bsp;
 mod console;
+mod driver;
+mod dtb;
 mod exception;
+mod initramfs;
+mod log;
 mod memory;
 mod print;
+mod ramfs;
 mod scheduler;
 mod start;
+mod stdio;
 mod synchronization;
 mod tasks;
 mod time;
@@ -36,11 +43,14 @@ extern "C" fn init_core() {
 }
 
 #[no_mangle]
-pub fn kernel_main() -> ! {
+pub fn kernel_main(dtb_addr: usize) -> ! {
+    // The blob has to be validated while it is still identity mapped, so it can be mapped in.
+    let dtb_status = unsafe { dtb::init(dtb_addr) };
 
     crate::memory::mmu::map_translation_table();
     crate::memory::mmu::enable_mmu_and_caching();
     crate::memory::init_heap();
+    initramfs::load();
 
     bsp::driver::init();
 
@@ -54,6 +64,16 @@ pub fn kernel_main() -> ! {
     aarch64_cpu::registers::CNTP_CTL_EL0.write(aarch64_cpu::registers::CNTP_CTL_EL0::ENABLE::SET);
 
     bsp::memory::virt_mem_layout().print_layout_info();
+    memory::mmu::check_wx_policy();
+    info!("W^X check passed");
+
+    match dtb_status {
+        Ok(()) => info!("Device tree at 0x{:X}", dtb_addr),
+        Err(x) => warn!("Device tree unavailable: {}, all drivers are assumed present", x),
+    }
+
+    info!("Drivers loaded:");
+    driver::driver_manager().enumerate();
 
     bsp::raspberrypi::QA7_REGS.enable_core_timer_irqs();
 
@@ -61,28 +81,34 @@ pub fn kernel_main() -> ! {
         core_execute(1, init_core);
     }
 
-    tasks::register_cmd("ptable", || {
+    use tasks::{Arg, ArgType, Command};
+
+    tasks::register(Command::new("ptable", || {
         scheduler::PTABLE.print();
-    });
+    }).description("List the processes"));
 
-    tasks::register_cmd("test_loop", || {
-        let max = 10;
+    tasks::register(Command::new("test_loop", || {
+        let max = tasks::parsed_args().int("count").unwrap_or(10);
         for i in 0..max {
             bsp::system_timer().wait_for_ms(1000);
+            if tasks::interrupted() {
+                return;
+            }
             println!("loop {}/{}", i + 1, max);
         }
-    });
+    }).description("Count once a second, ten times by default")
+        .args(&[Arg::OptionalPositional("count", ArgType::Int)]));
 
-    tasks::register_cmd("loop_forever", || {
+    tasks::register(Command::new("loop_forever", || {
         let mut c = 0;
-        loop {
+        while !tasks::interrupted() {
             bsp::system_timer().wait_for_ms(5000);
             c += 1;
             info!("loop {}", c);
         }
-    });
+    }).description("Log a message every five seconds until interrupted"));
 
-    tasks::register_cmd("uptime", || {
+    tasks::register(Command::new("uptime", || {
         let ticks = bsp::system_timer().get_ticks();
         let ms = ticks / 1000;
         let s = ms / 1000;
@@ -90,7 +116,91 @@ pub fn kernel_main() -> ! {
         let h = m / 60;
         let d = h / 24;
         crate::println!("uptime: {}d {}h {}m {}s", d, h % 24, m % 60, s % 60);
-    });
+    }).description("Time since boot"));
+
+    tasks::register(Command::new("heap_bench", memory::heap_benchmark).description("Benchmark the heap allocator"));
+    tasks::register(Command::new("slabinfo", memory::print_slab_caches).description("Show the slab caches"));
+    tasks::register(Command::new("meminfo", memory::print_meminfo).description("Show heap statistics"));
+    tasks::register(Command::new("leaks", memory::print_leaks).description("List live allocations (heap_tracking)"));
+    tasks::register(Command::new("drivers", || driver::driver_manager().print_drivers()).description("List the drivers"));
+    tasks::register(Command::new("dt", || match dtb::device_tree() {
+        Some(dt) => dt.print(),
+        None => println!("No device tree"),
+    }).description("Print the device tree"));
+    tasks::register(Command::new("uartstat", tasks::uart::uartstat).description("Show UART statistics"));
+    tasks::register(Command::new("irqs", || bsp::INTERRUPT_CONTROLLER.print_handlers()).description("List the IRQ handlers"));
+    tasks::register(Command::new("uart", tasks::uart::uart)
+        .description("Show or change the UART line settings")
+        .usage(tasks::uart::USAGE)
+        .args(&[
+            Arg::OptionalPositional("port", ArgType::OneOf(&["mini", "pl011"])),
+            Arg::OptionalPositional("baud", ArgType::Int),
+            Arg::OptionalPositional("format", ArgType::Str),
+            Arg::OptionalPositional("flow control", ArgType::OneOf(&["rtscts"])),
+        ]));
+    tasks::register(Command::new("console", tasks::console::console)
+        .description("Show or change what each console is used for")
+        .usage(tasks::console::USAGE)
+        .args(&[
+            Arg::OptionalPositional("name", ArgType::Str),
+            Arg::OptionalPositional("setting", ArgType::OneOf(&["output", "input", "log"])),
+            Arg::OptionalPositional("value", ArgType::Str),
+        ]));
+    tasks::register(Command::new("dmesg", tasks::dmesg::dmesg)
+        .description("Print the kernel log")
+        .usage(tasks::dmesg::USAGE)
+        .args(&[
+            Arg::Flag("-c"),
+            Arg::Option("-l", "level", ArgType::Str),
+            Arg::Option("-n", "console level", ArgType::Str),
+        ]));
+    tasks::register(Command::new("echo", tasks::files::echo)
+        .description("Print the arguments")
+        .args(&[Arg::Rest("words")]));
+    tasks::register(Command::new("cat", tasks::files::cat)
+        .description("Print a file, or copy stdin to stdout")
+        .args(&[Arg::OptionalPositional("file", ArgType::Str)]));
+    tasks::register(Command::new("grep", tasks::files::grep)
+        .description("Print the lines of stdin containing a pattern")
+        .args(&[Arg::Flag("-v"), Arg::Positional("pattern", ArgType::Str)]));
+    tasks::register(Command::new("ls", tasks::files::ls).description("List the files in the ramfs"));
+    tasks::register(Command::new("rm", tasks::files::rm)
+        .description("Remove a file from the ramfs")
+        .args(&[Arg::Positional("file", ArgType::Str)]));
+    tasks::register(Command::new("gpio", tasks::gpio::gpio)
+        .description("Show or change GPIO pins")
+        .usage(tasks::gpio::USAGE)
+        .args(&[
+            Arg::OptionalPositional("pin", ArgType::Int),
+            Arg::OptionalPositional("action", ArgType::OneOf(tasks::gpio::ACTIONS)),
+            Arg::OptionalPositional("value", ArgType::Str),
+        ]));
+    tasks::register(Command::new("spi", tasks::spi::spi)
+        .description("Configure the SPI masters and transfer data")
+        .usage(tasks::spi::USAGE)
+        .args(&[
+            Arg::OptionalPositional("bus", ArgType::OneOf(&["spi0", "spi1", "spi2"])),
+            Arg::Rest("args"),
+        ]));
+    tasks::register(Command::new("i2c", tasks::i2c::i2c)
+        .description("Configure the I2C masters and transfer data")
+        .usage(tasks::i2c::USAGE)
+        .args(&[Arg::OptionalPositional("bus", ArgType::Int), Arg::Rest("args")]));
+    tasks::register(Command::new("i2cdetect", tasks::i2c::i2cdetect)
+        .description("Scan an I2C bus for devices")
+        .args(&[Arg::Positional("bus", ArgType::Int)]));
+    tasks::register(Command::new("sysinfo", tasks::sysinfo::sysinfo)
+        .description("Show what the firmware reports about the board")
+        .usage(tasks::sysinfo::USAGE)
+        .args(&[
+            Arg::OptionalPositional("part", ArgType::OneOf(&["board", "memory", "clocks", "power"])),
+            Arg::OptionalPositional("device", ArgType::Str),
+            Arg::OptionalPositional("state", ArgType::OneOf(&["on", "off"])),
+        ]));
+    tasks::register(Command::new("fb", tasks::fb::fb)
+        .description("Show the framebuffer size or draw a test pattern")
+        .usage(tasks::fb::USAGE)
+        .args(&[Arg::OptionalPositional("action", ArgType::OneOf(&["test"]))]));
 
     
     scheduler::PTABLE.init_core();
@@ -98,5 +208,8 @@ pub fn kernel_main() -> ! {
 
     exception::irq_enable();
 
-    loop {}
+    // Idle until the next interrupt rather than spinning while every task is asleep.
+    loop {
+        aarch64_cpu::asm::wfi();
+    }
 }
\ No newline at end of file
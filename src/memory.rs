@@ -1,5 +1,10 @@
+#[cfg(feature = "buddy_allocator")]
 pub mod alloc;
+#[cfg(feature = "heap_debug")]
+mod debug;
 pub mod mmu;
+mod slab;
+mod stats;
 
 use core::{alloc::GlobalAlloc, sync::atomic::{AtomicBool, Ordering}};
 
@@ -8,7 +13,67 @@ use linked_list_allocator::Heap;
 use crate::{synchronization::{interface::Mutex, SpinLock}, warn};
 
 #[global_allocator]
-static ALLOCATOR: SpinLock<Heap> = SpinLock::new(Heap::empty());
+static ALLOCATOR: KernelHeap = KernelHeap::new();
+
+/// The global allocator: slab caches in front of the page allocator, plus bookkeeping.
+struct KernelHeap {
+    slab: slab::SlabAllocator,
+    stats: stats::HeapStats,
+    #[cfg(feature = "heap_tracking")]
+    tracker: stats::AllocationTracker,
+    #[cfg(feature = "heap_debug")]
+    debugger: debug::HeapDebugger,
+}
+
+impl KernelHeap {
+    const fn new() -> Self {
+        Self {
+            slab: slab::SlabAllocator::new(),
+            stats: stats::HeapStats::new(),
+            #[cfg(feature = "heap_tracking")]
+            tracker: stats::AllocationTracker::new(),
+            #[cfg(feature = "heap_debug")]
+            debugger: debug::HeapDebugger::new(),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        #[cfg(feature = "heap_debug")]
+        let ptr = self.debugger.alloc(&self.slab, layout);
+        #[cfg(not(feature = "heap_debug"))]
+        let ptr = self.slab.alloc(layout);
+
+        if ptr.is_null() {
+            self.stats.record_failure();
+            return ptr;
+        }
+
+        self.stats.record_alloc(layout.size());
+        #[cfg(feature = "heap_tracking")]
+        self.tracker.insert(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        self.stats.record_dealloc(layout.size());
+        #[cfg(feature = "heap_tracking")]
+        self.tracker.remove(ptr);
+
+        #[cfg(feature = "heap_debug")]
+        self.debugger.dealloc(&self.slab, ptr, layout);
+        #[cfg(not(feature = "heap_debug"))]
+        self.slab.dealloc(ptr, layout);
+    }
+}
+
+/// Backing allocator for slabs and for requests too large for the slab caches.
+#[cfg(not(feature = "buddy_allocator"))]
+static PAGE_ALLOCATOR: SpinLock<Heap> = SpinLock::new(Heap::empty());
+
+#[cfg(feature = "buddy_allocator")]
+static PAGE_ALLOCATOR: self::alloc::KernelAllocator = self::alloc::KernelAllocator::new();
 
 unsafe impl GlobalAlloc for SpinLock<Heap> {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
@@ -41,13 +106,188 @@ pub fn init_heap() {
         return;
     }
 
-    let mut inner = ALLOCATOR.lock().unwrap();
-
     unsafe {
         let heap_bottom = heap_start.get() as *mut u8;
         let heap_size = heap_end.get() as usize - heap_start.get() as usize;
-        inner.init(heap_bottom, heap_size)
+        init_allocator(heap_bottom, heap_size);
     }
+
+    #[cfg(feature = "heap_tracking")]
+    ALLOCATOR.tracker.init();
     
     INIT_DONE.store(true, Ordering::Relaxed);
-}
\ No newline at end of file
+}
+
+#[cfg(not(feature = "buddy_allocator"))]
+unsafe fn init_allocator(heap_bottom: *mut u8, heap_size: usize) {
+    PAGE_ALLOCATOR.lock().unwrap().init(heap_bottom, heap_size);
+}
+
+#[cfg(feature = "buddy_allocator")]
+unsafe fn init_allocator(heap_bottom: *mut u8, heap_size: usize) {
+    PAGE_ALLOCATOR.init(heap_bottom, heap_size);
+}
+
+/// Snapshot of the page allocator's view of the heap.
+struct PageAllocatorUsage {
+    size: usize,
+    used: usize,
+    free: usize,
+    largest_free: usize,
+}
+
+#[cfg(not(feature = "buddy_allocator"))]
+fn page_allocator_usage() -> PageAllocatorUsage {
+    let daif = crate::exception::irq_save();
+    let usage = {
+        let mut inner = PAGE_ALLOCATOR.lock().unwrap();
+
+        // The linked list heap can't report its holes, so find the largest block that can still
+        // be allocated by bisection.
+        let (mut lo, mut hi) = (0, inner.free());
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            let layout = core::alloc::Layout::from_size_align(mid, 8).unwrap();
+            match inner.allocate_first_fit(layout) {
+                Ok(ptr) => {
+                    unsafe { inner.deallocate(ptr, layout) };
+                    lo = mid;
+                }
+                Err(_) => hi = mid - 1,
+            }
+        }
+
+        PageAllocatorUsage {
+            size: inner.size(),
+            used: inner.used(),
+            free: inner.free(),
+            largest_free: lo,
+        }
+    };
+    crate::exception::irq_restore(daif);
+    usage
+}
+
+#[cfg(feature = "buddy_allocator")]
+fn page_allocator_usage() -> PageAllocatorUsage {
+    let daif = crate::exception::irq_save();
+    let usage = PageAllocatorUsage {
+        size: PAGE_ALLOCATOR.size(),
+        used: PAGE_ALLOCATOR.used(),
+        free: PAGE_ALLOCATOR.free(),
+        largest_free: PAGE_ALLOCATOR.largest_free_block(),
+    };
+    crate::exception::irq_restore(daif);
+    usage
+}
+
+#[cfg(not(feature = "buddy_allocator"))]
+const ALLOCATOR_NAME: &str = "linked list";
+
+#[cfg(feature = "buddy_allocator")]
+const ALLOCATOR_NAME: &str = "buddy";
+
+/// Runs a fixed pseudo-random alloc/free workload against the global allocator and reports the
+/// elapsed time and the heap state afterwards, so allocators can be compared like for like.
+pub fn heap_benchmark() {
+    use core::alloc::Layout;
+
+    const SLOTS: usize = 256;
+    const ITERATIONS: usize = 20000;
+
+    let mut slots: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    let mut seed: u32 = 0x1234_5678;
+    let mut next_rand = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as usize
+    };
+    let mut failed = 0;
+
+    let used_before = page_allocator_usage().used;
+    let start = crate::time::time_manager().uptime();
+    for _ in 0..ITERATIONS {
+        let slot = next_rand() % SLOTS;
+        match slots[slot].take() {
+            Some((ptr, layout)) => unsafe { ::alloc::alloc::dealloc(ptr, layout) },
+            None => {
+                // Mostly small objects with the occasional large buffer.
+                let size = if next_rand() % 16 == 0 {
+                    4096 + next_rand() % 65536
+                } else {
+                    16 + next_rand() % 512
+                };
+                let layout = Layout::from_size_align(size, 16).unwrap();
+                let ptr = unsafe { ::alloc::alloc::alloc(layout) };
+                if ptr.is_null() {
+                    failed += 1;
+                } else {
+                    slots[slot] = Some((ptr, layout));
+                }
+            }
+        }
+    }
+    let elapsed = crate::time::time_manager().uptime() - start;
+
+    let live = slots.iter().flatten().count();
+    let live_bytes: usize = slots.iter().flatten().map(|(_, layout)| layout.size()).sum();
+    let PageAllocatorUsage { used, free, .. } = page_allocator_usage();
+
+    crate::println!("slab + {} allocator: {} ops in {}us ({} failed)", ALLOCATOR_NAME, ITERATIONS, elapsed.as_micros(), failed);
+    crate::println!("  live allocations: {} ({} B requested)", live, live_bytes);
+    crate::println!("  heap used: {} B, free: {} B", used, free);
+    let consumed = used.saturating_sub(used_before);
+    if let Some(overhead) = (consumed.saturating_sub(live_bytes) * 100).checked_div(consumed) {
+        crate::println!("  overhead: {}%", overhead);
+    }
+    #[cfg(feature = "buddy_allocator")]
+    {
+        let daif = crate::exception::irq_save();
+        PAGE_ALLOCATOR.print_free_lists();
+        crate::exception::irq_restore(daif);
+    }
+
+    for (ptr, layout) in slots.iter().flatten() {
+        unsafe { ::alloc::alloc::dealloc(*ptr, *layout) };
+    }
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    // Logged, stdout may be a pipe or a file that would have to allocate.
+    let usage = page_allocator_usage();
+    crate::error!("Out of memory allocating {} B (align {})", layout.size(), layout.align());
+    crate::error!(
+        "Heap ({} allocator): {} KiB used, {} KiB free, largest free {} KiB",
+        ALLOCATOR_NAME,
+        usage.used / 1024,
+        usage.free / 1024,
+        usage.largest_free / 1024
+    );
+    panic!("memory allocation of {} bytes failed", layout.size());
+}
+
+pub fn print_slab_caches() {
+    ALLOCATOR.slab.print_caches();
+}
+
+pub fn print_meminfo() {
+    let usage = page_allocator_usage();
+    let fragmentation = (usage.largest_free * 100).checked_div(usage.free).map_or(0, |largest| 100 - largest);
+
+    crate::println!("Heap ({} allocator):", ALLOCATOR_NAME);
+    crate::println!("  total:          {:>10} KiB", usage.size / 1024);
+    crate::println!("  used:           {:>10} KiB", usage.used / 1024);
+    crate::println!("  free:           {:>10} KiB", usage.free / 1024);
+    crate::println!("  largest free:   {:>10} KiB", usage.largest_free / 1024);
+    crate::println!("  fragmentation:  {:>10}%", fragmentation);
+    ALLOCATOR.stats.print();
+}
+
+pub fn print_leaks() {
+    #[cfg(feature = "heap_tracking")]
+    ALLOCATOR.tracker.print();
+
+    #[cfg(not(feature = "heap_tracking"))]
+    crate::println!("Allocation tracking is disabled, rebuild with the `heap_tracking` feature");
+}
+
@@ -1,92 +1,273 @@
-
 use crate::synchronization::{SpinLock, interface::Mutex};
-use core::alloc::GlobalAlloc;
+use core::alloc::{GlobalAlloc, Layout};
 
-extern "C" {
-    static heap_start: u8;
-    //static heap_end: u8;
-}
+/// Smallest block handed out by the buddy allocator. Must fit a `FreeBlock`.
+const MIN_BLOCK_SHIFT: usize = 6;
+const MIN_BLOCK_SIZE: usize = 1 << MIN_BLOCK_SHIFT;
 
-/*enum BuddyBlockState {
-    Free,
-    Allocated,
-    SplitLeft,
-    SplitRight,
-    SplitBoth,
-}*/
+/// Largest block order; an order `n` block is `MIN_BLOCK_SIZE << n` bytes (64 MiB here).
+const MAX_ORDER: usize = 20;
+const NUM_ORDERS: usize = MAX_ORDER + 1;
+const MAX_HEAP_SIZE: usize = MIN_BLOCK_SIZE << MAX_ORDER;
 
-struct KernelAllocator {
-    inner: SpinLock<KernelAllocatorInner>,
+/// One bit per block of every order: 2^MAX_ORDER + 2^(MAX_ORDER-1) + ... + 1 bits. The map is
+/// kept at the bottom of the heap itself rather than in .bss.
+const FREE_MAP_BITS: usize = (1 << NUM_ORDERS) - 1;
+const FREE_MAP_WORDS: usize = FREE_MAP_BITS.div_ceil(64);
+const FREE_MAP_SIZE: usize = (FREE_MAP_WORDS * 8).next_multiple_of(MIN_BLOCK_SIZE);
+
+/// Intrusive doubly-linked list node stored in the first bytes of each free block.
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
 }
 
-const NUM_HEAP_PAGES: usize = 1024;
-struct KernelAllocatorInner {
-    map: [u8; NUM_HEAP_PAGES],
+pub struct KernelAllocator {
+    inner: SpinLock<KernelAllocatorInner>,
 }
 
-//#[global_allocator]
-static KERNEL_ALLOCATOR: KernelAllocator = KernelAllocator {
-    inner: SpinLock::new(KernelAllocatorInner { map: [0; 1024] })
-};
+impl KernelAllocator {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(KernelAllocatorInner::new()),
+        }
+    }
 
-unsafe impl GlobalAlloc for KernelAllocator {
-unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-    let numbytes = layout.size();
-    let mut pages = numbytes / 65536;
-    let extra = numbytes - pages * 65536;
-    if extra > 0 {
-        pages += 1;
-    }
-    
-    let mut inner = self.inner.lock().unwrap();
-    inner.get_pages(pages)
+    /// Hands the region `[heap_bottom, heap_bottom + heap_size)` to the allocator.
+    ///
+    /// # Safety
+    ///
+    /// The region must be unused, writable memory and this must only be called once.
+    pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.init(heap_bottom, heap_size);
+    }
+
+    pub fn size(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.heap_size
+    }
+
+    pub fn largest_free_block(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .free_lists
+            .iter()
+            .rposition(|head| !head.is_null())
+            .map_or(0, KernelAllocatorInner::block_size)
+    }
+
+    pub fn free(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.free_bytes
+    }
+
+    pub fn used(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.heap_size - inner.free_bytes
+    }
+
+    pub fn print_free_lists(&self) {
+        let inner = self.inner.lock().unwrap();
+        inner.print_free_lists();
+    }
 }
 
-unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-    let numbytes = layout.size();
-    let mut pages = numbytes / 65536;
-    let extra = numbytes - pages * 65536;
-    if extra > 0 {
-        pages += 1;
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.order_for(layout) {
+            Some(order) => inner.alloc_block(order),
+            None => core::ptr::null_mut(),
+        }
     }
 
-    let mut inner = self.inner.lock().unwrap();
-    inner.free_pages(ptr, pages);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut inner = self.inner.lock().unwrap();
+        let order = inner.order_for(layout).unwrap();
+        inner.free_block(ptr, order);
+    }
 }
+
+struct KernelAllocatorInner {
+    base: usize,
+    heap_size: usize,
+    free_bytes: usize,
+    free_lists: [*mut FreeBlock; NUM_ORDERS],
+    free_map: *mut u64,
 }
 
+// The raw pointers only ever refer to heap memory owned by the allocator.
+unsafe impl Send for KernelAllocatorInner {}
+
 impl KernelAllocatorInner {
-    fn get_pages(&mut self, num_pages: usize) -> *mut u8 {
-        let base_addr = unsafe { &heap_start as *const u8 as usize };
-        
-        let iter = NUM_HEAP_PAGES / num_pages;
-        for i in 0..iter {
-            let mut valid = true;
-            for _ in 0..num_pages {
-                if self.map[i] == 1 {
-                    valid = false;
-                }
+    const fn new() -> Self {
+        Self {
+            base: 0,
+            heap_size: 0,
+            free_bytes: 0,
+            free_lists: [core::ptr::null_mut(); NUM_ORDERS],
+            free_map: core::ptr::null_mut(),
+        }
+    }
+
+    const fn block_size(order: usize) -> usize {
+        MIN_BLOCK_SIZE << order
+    }
+
+    unsafe fn init(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        let heap_size = heap_size.min(MAX_HEAP_SIZE) & !(MIN_BLOCK_SIZE - 1);
+        if heap_size <= FREE_MAP_SIZE {
+            return;
+        }
+
+        self.base = heap_bottom as usize;
+        self.heap_size = heap_size;
+        self.free_map = heap_bottom as *mut u64;
+        core::ptr::write_bytes(self.free_map, 0, FREE_MAP_WORDS);
+
+        // Carve the rest of the region into the largest naturally aligned blocks that fit.
+        let mut offset = FREE_MAP_SIZE;
+        while heap_size - offset >= MIN_BLOCK_SIZE {
+            let mut order = MAX_ORDER;
+            while Self::block_size(order) > heap_size - offset || !offset.is_multiple_of(Self::block_size(order)) {
+                order -= 1;
             }
-            if valid {
-                for j in 0..num_pages {
-                    self.map[i+j] = 1;
-                }
-                let addr = (base_addr + 65536 * i) as *mut u8;
-                return addr;
+            self.push_free(order, offset);
+            offset += Self::block_size(order);
+        }
+    }
+
+    /// Smallest order whose blocks satisfy both the size and alignment of `layout`.
+    fn order_for(&self, layout: Layout) -> Option<usize> {
+        // Blocks are aligned to their size relative to `base`, so the base itself
+        // must be at least as aligned as the request.
+        if !self.base.is_multiple_of(layout.align()) {
+            return None;
+        }
+
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(MIN_BLOCK_SIZE)
+            .checked_next_power_of_two()?;
+        let order = (size.trailing_zeros() as usize) - MIN_BLOCK_SHIFT;
+
+        if order > MAX_ORDER {
+            None
+        } else {
+            Some(order)
+        }
+    }
+
+    fn alloc_block(&mut self, order: usize) -> *mut u8 {
+        let Some(mut current) = (order..NUM_ORDERS).find(|&o| !self.free_lists[o].is_null()) else {
+            return core::ptr::null_mut();
+        };
+
+        let offset = self.pop_free(current);
+
+        // Split down to the requested order, returning the upper halves to the free lists.
+        while current > order {
+            current -= 1;
+            self.push_free(current, offset + Self::block_size(current));
+        }
+
+        (self.base + offset) as *mut u8
+    }
+
+    fn free_block(&mut self, ptr: *mut u8, order: usize) {
+        let mut offset = ptr as usize - self.base;
+        let mut order = order;
+
+        if self.is_free(order, offset) {
+            panic!("Double free error at {:x}", ptr as usize);
+        }
+
+        // Coalesce with the buddy for as long as it is free at the same order.
+        while order < MAX_ORDER {
+            let buddy = offset ^ Self::block_size(order);
+            if buddy >= self.heap_size || !self.is_free(order, buddy) {
+                break;
+            }
+            self.remove_free(order, buddy);
+            offset = offset.min(buddy);
+            order += 1;
+        }
+
+        self.push_free(order, offset);
+    }
+
+    fn map_index(order: usize, offset: usize) -> usize {
+        let order_start = (1 << NUM_ORDERS) - (1 << (NUM_ORDERS - order));
+        order_start + (offset >> (order + MIN_BLOCK_SHIFT))
+    }
+
+    fn is_free(&self, order: usize, offset: usize) -> bool {
+        let idx = Self::map_index(order, offset);
+        unsafe { *self.free_map.add(idx / 64) & (1 << (idx % 64)) != 0 }
+    }
+
+    fn set_free(&mut self, order: usize, offset: usize, free: bool) {
+        let idx = Self::map_index(order, offset);
+        let word = unsafe { &mut *self.free_map.add(idx / 64) };
+        if free {
+            *word |= 1 << (idx % 64);
+        } else {
+            *word &= !(1 << (idx % 64));
+        }
+    }
+
+    fn push_free(&mut self, order: usize, offset: usize) {
+        let block = (self.base + offset) as *mut FreeBlock;
+        let head = self.free_lists[order];
+        unsafe {
+            (*block).next = head;
+            (*block).prev = core::ptr::null_mut();
+            if !head.is_null() {
+                (*head).prev = block;
             }
         }
-        panic!("No available page!");
+        self.free_lists[order] = block;
+        self.set_free(order, offset, true);
+        self.free_bytes += Self::block_size(order);
     }
 
-    fn free_pages(&mut self, ptr: *const u8, num_pages: usize) {
-        let base_addr = unsafe { &heap_start as *const u8 as usize };
-        let offset = ptr as usize - base_addr;
-        let index = offset / 65536;
-        for i in 0..num_pages {
-            if self.map[index+i] == 0 {
-                panic!("Double free error at {:x}", ptr as usize);
+    fn pop_free(&mut self, order: usize) -> usize {
+        let offset = self.free_lists[order] as usize - self.base;
+        self.remove_free(order, offset);
+        offset
+    }
+
+    fn remove_free(&mut self, order: usize, offset: usize) {
+        let block = (self.base + offset) as *mut FreeBlock;
+        unsafe {
+            let FreeBlock { next, prev } = *block;
+            if prev.is_null() {
+                self.free_lists[order] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
             }
-            self.map[index+i] = 0;
         }
+        self.set_free(order, offset, false);
+        self.free_bytes -= Self::block_size(order);
     }
-}
\ No newline at end of file
+
+    fn print_free_lists(&self) {
+        crate::println!("Free blocks by order:");
+        for (order, &head) in self.free_lists.iter().enumerate() {
+            let mut count = 0;
+            let mut cur = head;
+            while !cur.is_null() {
+                count += 1;
+                cur = unsafe { (*cur).next };
+            }
+            if count > 0 {
+                crate::println!("  {:>2} ({:>8} B): {}", order, Self::block_size(order), count);
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+};
+
+use crate::{exception, synchronization::{interface::Mutex, SpinLock}, utils::return_addresses};
+
+/// Bytes of guard pattern on either side of every allocation.
+const REDZONE_SIZE: usize = 16;
+
+const REDZONE_BYTE: u8 = 0xFD;
+const UNINIT_BYTE: u8 = 0xCD;
+const POISON_BYTE: u8 = 0xDD;
+
+const ALLOCATED_MAGIC: usize = 0xA110_CA7E_DA11_0C00;
+const FREED_MAGIC: usize = 0xF4EE_DF4E_EDF4_EE00;
+
+const SITE_DEPTH: usize = 4;
+/// Frames belonging to the debugger and `KernelHeap` itself.
+const SKIPPED_FRAMES: usize = 2;
+
+/// Freed blocks are held back this long before being returned, so use-after-free writes and
+/// double frees are still caught once the memory would otherwise have been reused.
+const QUARANTINE_SIZE: usize = 256;
+
+/// Bookkeeping stored in front of the leading red zone.
+#[repr(C)]
+struct DebugHeader {
+    /// Left alone so the backing allocator's free list can't clobber the fields below.
+    _reserved: [usize; 2],
+    magic: usize,
+    size: usize,
+    alloc_site: [usize; SITE_DEPTH],
+    free_site: [usize; SITE_DEPTH],
+}
+
+/// Wraps each allocation as `[header][red zone][user data][red zone]`, fills new memory with
+/// `UNINIT_BYTE`, poisons freed memory with `POISON_BYTE` and checks everything on the way out.
+pub struct HeapDebugger {
+    quarantine: SpinLock<Quarantine>,
+}
+
+impl HeapDebugger {
+    pub const fn new() -> Self {
+        Self {
+            quarantine: SpinLock::new(Quarantine::new()),
+        }
+    }
+
+    /// Returns the layout actually requested from the backing allocator and the offset of the
+    /// user data within it.
+    fn guarded_layout(layout: Layout) -> (Layout, usize) {
+        let align = layout.align().max(core::mem::align_of::<DebugHeader>());
+        let front = (core::mem::size_of::<DebugHeader>() + REDZONE_SIZE).next_multiple_of(align);
+        let size = front + layout.size() + REDZONE_SIZE;
+        (Layout::from_size_align(size, align).unwrap(), front)
+    }
+
+    pub unsafe fn alloc(&self, backend: &impl GlobalAlloc, layout: Layout) -> *mut u8 {
+        let (guarded, front) = Self::guarded_layout(layout);
+        let base = backend.alloc(guarded);
+        if base.is_null() {
+            return base;
+        }
+
+        let header = &mut *(base as *mut DebugHeader);
+        header.magic = ALLOCATED_MAGIC;
+        header.size = layout.size();
+        header.alloc_site = return_addresses::<SITE_DEPTH>(SKIPPED_FRAMES);
+        header.free_site = [0; SITE_DEPTH];
+
+        let header_size = core::mem::size_of::<DebugHeader>();
+        let ptr = base.add(front);
+        core::ptr::write_bytes(base.add(header_size), REDZONE_BYTE, front - header_size);
+        core::ptr::write_bytes(ptr, UNINIT_BYTE, layout.size());
+        core::ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+
+        ptr
+    }
+
+    pub unsafe fn dealloc(&self, backend: &impl GlobalAlloc, ptr: *mut u8, layout: Layout) {
+        let (_, front) = Self::guarded_layout(layout);
+        let base = ptr.sub(front);
+        let header = &mut *(base as *mut DebugHeader);
+
+        match header.magic {
+            ALLOCATED_MAGIC => {}
+            FREED_MAGIC => report("double free", ptr, header),
+            _ => {
+                crate::error!("heap: free of 0x{:X} ({:?}) which was never allocated or whose header was overwritten", ptr as usize, layout);
+                panic!("heap corruption detected");
+            }
+        }
+        if header.size != layout.size() {
+            crate::error!("heap: 0x{:X} freed with size {} but allocated with {}", ptr as usize, layout.size(), header.size);
+            report("size mismatch", ptr, header);
+        }
+
+        let header_size = core::mem::size_of::<DebugHeader>();
+        let front_ok = all_bytes(base.add(header_size), front - header_size, REDZONE_BYTE);
+        let back_ok = all_bytes(ptr.add(layout.size()), REDZONE_SIZE, REDZONE_BYTE);
+        if !front_ok || !back_ok {
+            report(if front_ok { "buffer overflow" } else { "buffer underflow" }, ptr, header);
+        }
+
+        header.magic = FREED_MAGIC;
+        header.free_site = return_addresses::<SITE_DEPTH>(SKIPPED_FRAMES);
+        core::ptr::write_bytes(ptr, POISON_BYTE, layout.size());
+
+        let daif = exception::irq_save();
+        let evicted = self.quarantine.lock().unwrap().push(ptr as usize, layout);
+        exception::irq_restore(daif);
+
+        if let Some((old_ptr, old_layout)) = evicted {
+            let old_ptr = old_ptr as *mut u8;
+            let (old_guarded, old_front) = Self::guarded_layout(old_layout);
+            let old_base = old_ptr.sub(old_front);
+            if !all_bytes(old_ptr, old_layout.size(), POISON_BYTE) {
+                report("write after free", old_ptr, &*(old_base as *const DebugHeader));
+            }
+            backend.dealloc(old_base, old_guarded);
+        }
+    }
+}
+
+unsafe fn all_bytes(ptr: *const u8, len: usize, value: u8) -> bool {
+    core::slice::from_raw_parts(ptr, len).iter().all(|&b| b == value)
+}
+
+/// Return addresses of an allocation or free, up to the first unused slot.
+struct Site<'a>(&'a [usize; SITE_DEPTH]);
+
+impl fmt::Display for Site<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().take_while(|&&a| a != 0).try_for_each(|addr| write!(f, " 0x{:X}", addr))
+    }
+}
+
+// Reports are logged rather than printed, stdout may be a pipe or a file that would allocate.
+fn print_site(label: &str, site: &[usize; SITE_DEPTH]) {
+    crate::error!("  {}:{}", label, Site(site));
+}
+
+fn report(kind: &str, ptr: *mut u8, header: &DebugHeader) -> ! {
+    crate::error!("heap: {} at 0x{:X} ({} B)", kind, ptr as usize, header.size);
+    print_site("allocated at", &header.alloc_site);
+    if header.magic == FREED_MAGIC {
+        print_site("freed at", &header.free_site);
+    }
+    panic!("heap corruption detected");
+}
+
+struct Quarantine {
+    entries: [Option<(usize, Layout)>; QUARANTINE_SIZE],
+    next: usize,
+}
+
+impl Quarantine {
+    const fn new() -> Self {
+        Self {
+            entries: [None; QUARANTINE_SIZE],
+            next: 0,
+        }
+    }
+
+    /// Queues a freed block and returns the oldest one once the quarantine is full.
+    fn push(&mut self, ptr: usize, layout: Layout) -> Option<(usize, Layout)> {
+        let evicted = self.entries[self.next].replace((ptr, layout));
+        self.next = (self.next + 1) % QUARANTINE_SIZE;
+        evicted
+    }
+}
@@ -11,6 +11,8 @@ pub enum AccessPermissions {
 #[derive(Clone, Debug)]
 pub enum MemoryAttributes {
     CacheableDRAM,
+    /// Normal memory that bypasses the caches, for buffers shared with the VideoCore.
+    NonCacheableDRAM,
     Device,
 }
 
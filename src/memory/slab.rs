@@ -0,0 +1,215 @@
+use core::{alloc::{GlobalAlloc, Layout}, cell::UnsafeCell};
+
+use crate::{bsp::NUM_CORES, exception, synchronization::{interface::Mutex, SpinLock}, utils::get_core};
+
+use super::PAGE_ALLOCATOR;
+
+/// Object caches exist for every power of two from 16 B up to 4 KiB. Anything larger goes
+/// straight to the page allocator.
+const MIN_OBJECT_SHIFT: usize = 4;
+const NUM_CLASSES: usize = 9;
+const MAX_OBJECT_SIZE: usize = 1 << (MIN_OBJECT_SHIFT + NUM_CLASSES - 1);
+
+/// Bytes requested from the page allocator whenever a cache runs dry.
+const SLAB_SIZE: usize = 65536;
+
+/// Objects each core keeps per size class before it has to touch the shared cache.
+const MAGAZINE_SIZE: usize = 32;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+#[derive(Copy, Clone)]
+struct Magazine {
+    count: usize,
+    objects: [*mut u8; MAGAZINE_SIZE],
+}
+
+impl Magazine {
+    const fn empty() -> Self {
+        Self {
+            count: 0,
+            objects: [core::ptr::null_mut(); MAGAZINE_SIZE],
+        }
+    }
+}
+
+/// Fixed-size object allocator layered over the page allocator.
+///
+/// Each core owns a magazine of free objects per size class which it only touches with IRQs
+/// masked, so the common path never takes a lock. Magazines are refilled from and flushed to a
+/// per-class shared cache, which in turn grows by whole slabs from `PAGE_ALLOCATOR`. Slabs are
+/// never handed back.
+pub struct SlabAllocator {
+    magazines: UnsafeCell<[[Magazine; NUM_CLASSES]; NUM_CORES]>,
+    caches: [SpinLock<ObjectCache>; NUM_CLASSES],
+}
+
+// Each core only ever accesses its own row of `magazines`, with IRQs disabled.
+unsafe impl Sync for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        Self {
+            magazines: UnsafeCell::new([[Magazine::empty(); NUM_CLASSES]; NUM_CORES]),
+            caches: [const { SpinLock::new(ObjectCache::new()) }; NUM_CLASSES],
+        }
+    }
+
+    fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).next_power_of_two();
+        if size > MAX_OBJECT_SIZE {
+            return None;
+        }
+        Some((size.trailing_zeros() as usize).saturating_sub(MIN_OBJECT_SHIFT))
+    }
+
+    const fn object_size(class: usize) -> usize {
+        1 << (class + MIN_OBJECT_SHIFT)
+    }
+
+    /// # Safety
+    ///
+    /// IRQs must be disabled for as long as the returned reference is used.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn local_magazine(&self, class: usize) -> &mut Magazine {
+        &mut (*self.magazines.get())[get_core() as usize][class]
+    }
+
+    fn refill(&self, class: usize, magazine: &mut Magazine) {
+        let mut cache = self.caches[class].lock().unwrap();
+        while magazine.count < MAGAZINE_SIZE / 2 {
+            match cache.pop(Self::object_size(class)) {
+                Some(ptr) => {
+                    magazine.objects[magazine.count] = ptr;
+                    magazine.count += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn flush(&self, class: usize, magazine: &mut Magazine) {
+        let mut cache = self.caches[class].lock().unwrap();
+        while magazine.count > MAGAZINE_SIZE / 2 {
+            magazine.count -= 1;
+            cache.push(magazine.objects[magazine.count]);
+        }
+    }
+
+    pub fn print_caches(&self) {
+        crate::println!("Slab caches:");
+        crate::println!("  {:>6} {:>6} {:>8}", "size", "slabs", "free");
+        for (class, cache) in self.caches.iter().enumerate() {
+            // Cache locks are also taken from the IRQs-off allocation path, so never hold one
+            // with IRQs enabled.
+            let daif = exception::irq_save();
+            let (num_slabs, num_free) = {
+                let cache = cache.lock().unwrap();
+                (cache.num_slabs, cache.num_free)
+            };
+            exception::irq_restore(daif);
+
+            if num_slabs > 0 {
+                crate::println!("  {:>6} {:>6} {:>8}", Self::object_size(class), num_slabs, num_free);
+            }
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    // IRQs stay masked across the page allocator calls as well: its lock is also taken from the
+    // slab refill path, and a core must never spin on a lock that it holds itself.
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let daif = exception::irq_save();
+        let ptr = match Self::size_class(layout) {
+            Some(class) => {
+                let magazine = self.local_magazine(class);
+                if magazine.count == 0 {
+                    self.refill(class, magazine);
+                }
+                if magazine.count > 0 {
+                    magazine.count -= 1;
+                    magazine.objects[magazine.count]
+                } else {
+                    core::ptr::null_mut()
+                }
+            }
+            None => PAGE_ALLOCATOR.alloc(layout),
+        };
+        exception::irq_restore(daif);
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let daif = exception::irq_save();
+        match Self::size_class(layout) {
+            Some(class) => {
+                let magazine = self.local_magazine(class);
+                if magazine.count == MAGAZINE_SIZE {
+                    self.flush(class, magazine);
+                }
+                magazine.objects[magazine.count] = ptr;
+                magazine.count += 1;
+            }
+            None => PAGE_ALLOCATOR.dealloc(ptr, layout),
+        }
+        exception::irq_restore(daif);
+    }
+}
+
+/// Shared free list for one size class.
+struct ObjectCache {
+    free: *mut FreeObject,
+    num_free: usize,
+    num_slabs: usize,
+}
+
+// The free list only links objects inside slabs owned by this cache.
+unsafe impl Send for ObjectCache {}
+
+impl ObjectCache {
+    const fn new() -> Self {
+        Self {
+            free: core::ptr::null_mut(),
+            num_free: 0,
+            num_slabs: 0,
+        }
+    }
+
+    fn push(&mut self, ptr: *mut u8) {
+        let object = ptr as *mut FreeObject;
+        unsafe {
+            (*object).next = self.free;
+        }
+        self.free = object;
+        self.num_free += 1;
+    }
+
+    fn pop(&mut self, object_size: usize) -> Option<*mut u8> {
+        if self.free.is_null() && !self.grow(object_size) {
+            return None;
+        }
+
+        let object = self.free;
+        self.free = unsafe { (*object).next };
+        self.num_free -= 1;
+        Some(object as *mut u8)
+    }
+
+    fn grow(&mut self, object_size: usize) -> bool {
+        let layout = Layout::from_size_align(SLAB_SIZE, object_size).unwrap();
+        let slab = unsafe { PAGE_ALLOCATOR.alloc(layout) };
+        if slab.is_null() {
+            return false;
+        }
+
+        for i in 0..SLAB_SIZE / object_size {
+            self.push(unsafe { slab.add(i * object_size) });
+        }
+        self.num_slabs += 1;
+        true
+    }
+}
@@ -0,0 +1,262 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocation counters maintained by the global allocator.
+pub struct HeapStats {
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    failures: AtomicUsize,
+    bytes_in_use: AtomicUsize,
+    peak_bytes: AtomicUsize,
+}
+
+impl HeapStats {
+    pub const fn new() -> Self {
+        Self {
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            bytes_in_use: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record_alloc(&self, size: usize) {
+        self.allocs.fetch_add(1, Ordering::Relaxed);
+        let in_use = self.bytes_in_use.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_bytes.fetch_max(in_use, Ordering::Relaxed);
+    }
+
+    pub fn record_dealloc(&self, size: usize) {
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in_use.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn print(&self) {
+        let allocs = self.allocs.load(Ordering::Relaxed);
+        let frees = self.frees.load(Ordering::Relaxed);
+
+        crate::println!("Allocations:");
+        crate::println!("  allocs:         {:>10}", allocs);
+        crate::println!("  frees:          {:>10}", frees);
+        crate::println!("  outstanding:    {:>10}", allocs.saturating_sub(frees));
+        crate::println!("  failed:         {:>10}", self.failures.load(Ordering::Relaxed));
+        crate::println!("  requested:      {:>10} B", self.bytes_in_use.load(Ordering::Relaxed));
+        crate::println!("  peak requested: {:>10} B", self.peak_bytes.load(Ordering::Relaxed));
+    }
+}
+
+#[cfg(feature = "heap_tracking")]
+pub use tracking::AllocationTracker;
+
+#[cfg(feature = "heap_tracking")]
+mod tracking {
+    use core::alloc::{GlobalAlloc, Layout};
+
+    use crate::{exception, synchronization::{interface::Mutex, SpinLock}, utils::return_addresses};
+
+    const TRACKED_ALLOCATIONS: usize = 8192;
+    const CALLER_DEPTH: usize = 4;
+    /// Frames belonging to the tracker and `KernelHeap::alloc` itself.
+    const SKIPPED_FRAMES: usize = 2;
+    const REPORTED_SITES: usize = 32;
+
+    /// A live allocation. Slots with `ptr == 0` are empty.
+    #[derive(Copy, Clone)]
+    struct AllocationRecord {
+        ptr: usize,
+        size: usize,
+        callers: [usize; CALLER_DEPTH],
+    }
+
+    #[derive(Copy, Clone)]
+    struct AllocationSite {
+        callers: [usize; CALLER_DEPTH],
+        count: usize,
+        bytes: usize,
+    }
+
+    /// Records the size and call site of every live allocation in an open addressing table.
+    ///
+    /// The table is carved straight out of the page allocator by `init` so it doesn't bloat
+    /// .bss or recurse into the global allocator.
+    pub struct AllocationTracker {
+        inner: SpinLock<AllocationTrackerInner>,
+    }
+
+    impl AllocationTracker {
+        pub const fn new() -> Self {
+            Self {
+                inner: SpinLock::new(AllocationTrackerInner::new()),
+            }
+        }
+
+        pub fn init(&self) {
+            let layout = Layout::array::<AllocationRecord>(TRACKED_ALLOCATIONS).unwrap();
+            let records = unsafe { super::super::PAGE_ALLOCATOR.alloc_zeroed(layout) };
+
+            let daif = exception::irq_save();
+            self.inner.lock().unwrap().records = records as *mut AllocationRecord;
+            exception::irq_restore(daif);
+        }
+
+        pub fn insert(&self, ptr: *mut u8, size: usize) {
+            let callers = return_addresses::<CALLER_DEPTH>(SKIPPED_FRAMES);
+
+            let daif = exception::irq_save();
+            self.inner.lock().unwrap().insert(AllocationRecord {
+                ptr: ptr as usize,
+                size,
+                callers,
+            });
+            exception::irq_restore(daif);
+        }
+
+        pub fn remove(&self, ptr: *mut u8) {
+            let daif = exception::irq_save();
+            self.inner.lock().unwrap().remove(ptr as usize);
+            exception::irq_restore(daif);
+        }
+
+        /// Prints outstanding allocations grouped by call site, largest first.
+        pub fn print(&self) {
+            let mut sites = [AllocationSite { callers: [0; CALLER_DEPTH], count: 0, bytes: 0 }; REPORTED_SITES];
+            let mut num_sites = 0;
+            let mut other = (0, 0);
+
+            // Aggregate under the lock but print afterwards, printing is slow.
+            let daif = exception::irq_save();
+            let (count, bytes, dropped) = {
+                let inner = self.inner.lock().unwrap();
+                for record in inner.iter() {
+                    match sites[..num_sites].iter_mut().find(|s| s.callers == record.callers) {
+                        Some(site) => {
+                            site.count += 1;
+                            site.bytes += record.size;
+                        }
+                        None if num_sites < REPORTED_SITES => {
+                            sites[num_sites] = AllocationSite { callers: record.callers, count: 1, bytes: record.size };
+                            num_sites += 1;
+                        }
+                        None => {
+                            other.0 += 1;
+                            other.1 += record.size;
+                        }
+                    }
+                }
+                (inner.count, inner.iter().map(|r| r.size).sum::<usize>(), inner.dropped)
+            };
+            exception::irq_restore(daif);
+
+            sites[..num_sites].sort_unstable_by_key(|s| core::cmp::Reverse(s.bytes));
+
+            crate::println!("{} outstanding allocations, {} B", count, bytes);
+            for site in &sites[..num_sites] {
+                crate::print!("  {:>6} x {:>8} B  at", site.count, site.bytes);
+                for caller in site.callers.iter().take_while(|&&c| c != 0) {
+                    crate::print!(" 0x{:X}", caller);
+                }
+                crate::println!();
+            }
+            if other.0 > 0 {
+                crate::println!("  {:>6} x {:>8} B  at other sites", other.0, other.1);
+            }
+            if dropped > 0 {
+                crate::println!("{} allocations were not tracked, the table was full", dropped);
+            }
+        }
+    }
+
+    struct AllocationTrackerInner {
+        records: *mut AllocationRecord,
+        count: usize,
+        dropped: usize,
+    }
+
+    // `records` points into memory reserved for the tracker.
+    unsafe impl Send for AllocationTrackerInner {}
+
+    impl AllocationTrackerInner {
+        const fn new() -> Self {
+            Self {
+                records: core::ptr::null_mut(),
+                count: 0,
+                dropped: 0,
+            }
+        }
+
+        fn slots(&mut self) -> &mut [AllocationRecord] {
+            unsafe { core::slice::from_raw_parts_mut(self.records, TRACKED_ALLOCATIONS) }
+        }
+
+        fn iter(&self) -> impl Iterator<Item = &AllocationRecord> {
+            let slots: &[AllocationRecord] = if self.records.is_null() {
+                &[]
+            } else {
+                unsafe { core::slice::from_raw_parts(self.records, TRACKED_ALLOCATIONS) }
+            };
+            slots.iter().filter(|r| r.ptr != 0)
+        }
+
+        fn home(ptr: usize) -> usize {
+            ((ptr >> 4).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % TRACKED_ALLOCATIONS
+        }
+
+        fn insert(&mut self, record: AllocationRecord) {
+            // Allocations made before `init` (or once the table is full) go uncounted.
+            if self.records.is_null() || self.count == TRACKED_ALLOCATIONS - 1 {
+                self.dropped += 1;
+                return;
+            }
+
+            let mut idx = Self::home(record.ptr);
+            let slots = self.slots();
+            while slots[idx].ptr != 0 {
+                idx = (idx + 1) % TRACKED_ALLOCATIONS;
+            }
+            slots[idx] = record;
+            self.count += 1;
+        }
+
+        fn remove(&mut self, ptr: usize) {
+            if self.records.is_null() {
+                return;
+            }
+
+            let slots = self.slots();
+            let mut hole = Self::home(ptr);
+            loop {
+                match slots[hole].ptr {
+                    0 => return,
+                    p if p == ptr => break,
+                    _ => hole = (hole + 1) % TRACKED_ALLOCATIONS,
+                }
+            }
+
+            // Backward-shift deletion: pull later entries of the probe run into the hole unless
+            // their home slot lies between the hole and their current position.
+            let mut next = hole;
+            loop {
+                next = (next + 1) % TRACKED_ALLOCATIONS;
+                if slots[next].ptr == 0 {
+                    break;
+                }
+                let home = Self::home(slots[next].ptr);
+                let stays = if hole <= next {
+                    hole < home && home <= next
+                } else {
+                    hole < home || home <= next
+                };
+                if !stays {
+                    slots[hole] = slots[next];
+                    hole = next;
+                }
+            }
+            slots[hole].ptr = 0;
+            self.count -= 1;
+        }
+    }
+}
@@ -2,7 +2,12 @@ use core::fmt;
 
 //#[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    crate::console::console().write_fmt(args).unwrap();
+    crate::stdio::print(args).unwrap();
+}
+
+/// Records a kernel log message, see `crate::log`.
+pub fn _print_log(level: crate::console::LogLevel, args: fmt::Arguments) {
+    crate::log::log(level, args);
 }
 
 /// Prints without a newline.
@@ -24,50 +29,26 @@ macro_rules! println {
     })
 }
 
-/// Prints an info, with a newline.
+/// Logs an error, with a newline.
 #[macro_export]
-macro_rules! info {
-    ($string:expr) => ({
-        let timestamp = $crate::time::time_manager().uptime();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:06}] ", $string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        let timestamp = $crate::time::time_manager().uptime();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:06}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-            $($arg)*
-        ));
-    })
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::print::_print_log($crate::console::LogLevel::Error, format_args!($($arg)*)));
 }
 
-/// Prints a warning, with a newline.
+/// Logs a warning, with a newline.
 #[macro_export]
 macro_rules! warn {
-    ($string:expr) => ({
-        let timestamp = $crate::time::time_manager().uptime();
+    ($($arg:tt)*) => ($crate::print::_print_log($crate::console::LogLevel::Warn, format_args!($($arg)*)));
+}
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:06}] ", $string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        let timestamp = $crate::time::time_manager().uptime();
+/// Logs an info, with a newline.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::print::_print_log($crate::console::LogLevel::Info, format_args!($($arg)*)));
+}
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:06}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-            $($arg)*
-        ));
-    })
-}
\ No newline at end of file
+/// Logs a debug message, with a newline. Consoles don't show these unless asked to.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::print::_print_log($crate::console::LogLevel::Debug, format_args!($($arg)*)));
+}
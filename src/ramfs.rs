@@ -0,0 +1,70 @@
+//! A flat in-memory file system: named byte buffers on the heap, gone at reboot.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{exception, synchronization::{interface::Mutex, SpinLock}};
+
+struct File {
+    name: String,
+    data: Vec<u8>,
+}
+
+static FILES: SpinLock<Vec<File>> = SpinLock::new(Vec::new());
+
+fn with_files<R>(f: impl FnOnce(&mut Vec<File>) -> R) -> R {
+    let daif = exception::irq_save();
+    let result = f(&mut FILES.lock().unwrap());
+    exception::irq_restore(daif);
+    result
+}
+
+fn check_name(name: &str) -> Result<(), &'static str> {
+    match name {
+        "" => Err("empty file name"),
+        name if name.contains(char::is_whitespace) => Err("file names can't contain spaces"),
+        _ => Ok(()),
+    }
+}
+
+/// Creates an empty file, or empties an existing one.
+pub fn create(name: &str) -> Result<(), &'static str> {
+    check_name(name)?;
+    with_files(|files| match files.iter_mut().find(|f| f.name == name) {
+        Some(file) => file.data.clear(),
+        None => files.push(File {
+            name: name.into(),
+            data: Vec::new(),
+        }),
+    });
+    Ok(())
+}
+
+/// Appends to a file, creating it if needed.
+pub fn append(name: &str, data: &[u8]) -> Result<(), &'static str> {
+    check_name(name)?;
+    with_files(|files| match files.iter_mut().find(|f| f.name == name) {
+        Some(file) => file.data.extend_from_slice(data),
+        None => files.push(File {
+            name: name.into(),
+            data: data.into(),
+        }),
+    });
+    Ok(())
+}
+
+pub fn read(name: &str) -> Option<Vec<u8>> {
+    with_files(|files| files.iter().find(|f| f.name == name).map(|f| f.data.clone()))
+}
+
+pub fn remove(name: &str) -> bool {
+    with_files(|files| {
+        let count = files.len();
+        files.retain(|f| f.name != name);
+        files.len() != count
+    })
+}
+
+/// Names and sizes of all files.
+pub fn list() -> Vec<(String, usize)> {
+    with_files(|files| files.iter().map(|f| (f.name.clone(), f.data.len())).collect())
+}
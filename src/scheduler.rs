@@ -1,8 +1,47 @@
-use crate::{utils::get_core, synchronization::{SpinLock, interface::Mutex}, exception};
-use alloc::boxed::Box;
+use crate::{utils::get_core, synchronization::{SpinLock, interface::Mutex}, exception, stdio::Stdio};
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
 pub static PTABLE: PTable = PTable::new();
 
+/// Woken whenever a process exits.
+pub static EXIT_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Exit codes of this many exited processes are kept for `take_exit_code`.
+const EXIT_CODES_KEPT: usize = 32;
+
+/// Exit code of a process that was interrupted and didn't set one, as in `sh` after Ctrl-C.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Pid running on each core, zero for none. Readable without the table lock, e.g. by the kernel
+/// log from an IRQ handler that interrupted the scheduler.
+static CURRENT_PIDS: [AtomicUsize; 4] = [const { AtomicUsize::new(0) }; 4];
+
+/// Stdio of the process running on each core, null for none. Lives in the process, which
+/// stays around at least as long as it runs.
+static CURRENT_STDIO: [AtomicPtr<Stdio>; 4] = [const { AtomicPtr::new(core::ptr::null_mut()) }; 4];
+
+static CONSOLE_STDIO: Stdio = Stdio::console();
+
+/// Calls `f` with the current process's stdin and stdout, or the console without a process.
+pub fn with_stdio<R>(f: impl FnOnce(&Stdio) -> R) -> R {
+    // Read with IRQs masked, so the process can't move to another core in between.
+    let daif = exception::irq_save();
+    let stdio = CURRENT_STDIO[get_core() as usize].load(Ordering::Acquire);
+    exception::irq_restore(daif);
+
+    // A process only ever sees its own stdio, which lives as long as it does.
+    f(unsafe { stdio.as_ref() }.unwrap_or(&CONSOLE_STDIO))
+}
+
+pub fn current_pid() -> Option<usize> {
+    match CURRENT_PIDS[get_core() as usize].load(Ordering::Relaxed) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
 extern "C" {
     fn cpu_switch_to(prev: usize, next: usize);
 }
@@ -77,6 +116,8 @@ enum TaskState {
     Unused,
     Sleeping,
     Running,
+    /// Stopped by job control, not scheduled until resumed.
+    Stopped,
     Zombie,
 }
 
@@ -86,6 +127,13 @@ struct Process {
     state: TaskState,
     name: &'static str,
     pid: usize,
+    /// Command line arguments, not including the name.
+    args: Vec<String>,
+    /// Asked to finish early, e.g. with Ctrl-C. Tasks check `PTable::interrupted`.
+    interrupted: bool,
+    /// Zero for success, set with `PTable::set_exit_code`.
+    exit_code: i32,
+    stdio: Stdio,
     stack: Box<[u8; 65536]>,
     next: Option<Box<Process>>,
 }
@@ -97,6 +145,10 @@ impl Process {
             state: TaskState::Unused,
             name: "",
             pid: 0,
+            args: Vec::new(),
+            interrupted: false,
+            exit_code: 0,
+            stdio: Stdio::console(),
             stack: Box::new([0; 65536]),
             next: None
         }
@@ -107,6 +159,8 @@ trait ProcessList<T> {
   fn add_proc(&mut self, item: T);
   fn remove_zombies(&mut self) -> usize;
   fn get_first(&mut self) -> Self;
+  fn take_first_runnable(&mut self) -> Self;
+  fn wake(&mut self, pids: &[usize]);
 }
 
 impl ProcessList<Box<Process>> for Option<Box<Process>> {
@@ -142,6 +196,69 @@ impl ProcessList<Box<Process>> for Option<Box<Process>> {
     }
     first
   }
+
+  fn take_first_runnable(&mut self) -> Option<Box<Process>> {
+    let mut current = self;
+    loop {
+        match current {
+            None => return None,
+            Some(proc) if proc.state == TaskState::Running => return current.get_first(),
+            Some(proc) => {
+                current = &mut proc.next;
+            }
+        }
+    }
+  }
+
+  fn wake(&mut self, pids: &[usize]) {
+    let mut current = self;
+    while let Some(proc) = current {
+        if proc.state == TaskState::Sleeping && pids.contains(&proc.pid) {
+            proc.state = TaskState::Running;
+        }
+        current = &mut proc.next;
+    }
+  }
+}
+
+/// Processes waiting for some event, such as a character arriving on a UART.
+///
+/// A waiter calls `PTable::prepare_to_wait` while still holding the lock protecting the
+/// condition it waits for, releases it, calls `PTable::schedule` and then `PTable::finish_wait`.
+/// The waker updates the condition under the same lock before calling `wake_all`, so a wakeup
+/// can't slip in between the check and going to sleep.
+pub struct WaitQueue {
+    pids: SpinLock<Vec<usize>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            pids: SpinLock::new(Vec::new()),
+        }
+    }
+
+    /// Sleeps until woken up, releasing `guard`, the lock protecting the condition waited for.
+    /// IRQs must be masked, `daif` being what they were before. Returns false without sleeping
+    /// if there is no process to put to sleep or IRQs were already masked, the caller polls.
+    pub fn sleep<G>(&self, daif: usize, guard: G) -> bool {
+        let sleeping = exception::irqs_enabled(daif) && PTABLE.prepare_to_wait(self);
+        drop(guard);
+        if sleeping {
+            PTABLE.schedule();
+            PTABLE.finish_wait(self);
+        }
+        sleeping
+    }
+
+    pub fn wake_all(&self) {
+        let daif = exception::irq_save();
+        let pids = core::mem::take(&mut *self.pids.lock().unwrap());
+        if !pids.is_empty() {
+            PTABLE.inner.lock().unwrap().wake(&pids);
+        }
+        exception::irq_restore(daif);
+    }
 }
 
 pub struct PTable {
@@ -160,11 +277,77 @@ impl PTable {
         table.init_core_inner(get_core());
     }
 
-    pub fn new_process(&self, name: &'static str, f: fn()) {
+    pub fn new_process(&self, name: &'static str, f: fn()) -> usize {
+        self.new_process_with_args(name, f, Vec::new())
+    }
+
+    pub fn new_process_with_args(&self, name: &'static str, f: fn(), args: Vec<String>) -> usize {
+        self.new_process_with_stdio(name, f, args, Stdio::console())
+    }
+
+    /// Starts a process and returns its pid.
+    pub fn new_process_with_stdio(&self, name: &'static str, f: fn(), args: Vec<String>, stdio: Stdio) -> usize {
         crate::exception::irq_disable();
         let mut table = self.inner.lock().unwrap();
-        table.new_process_inner(name, f);
+        let pid = table.new_process_inner(name, f, args, stdio);
+        drop(table);
         crate::exception::irq_enable();
+        pid
+    }
+
+    /// Arguments the current process was started with.
+    pub fn current_args(&self) -> Vec<String> {
+        let daif = exception::irq_save();
+        let args = {
+            let table = self.inner.lock().unwrap();
+            table.running[get_core() as usize]
+                .as_ref()
+                .map_or(Vec::new(), |proc| proc.args.clone())
+        };
+        exception::irq_restore(daif);
+        args
+    }
+
+    /// Name of the current process, which for commands is the command name.
+    pub fn current_name(&self) -> &'static str {
+        let daif = exception::irq_save();
+        let name = {
+            let table = self.inner.lock().unwrap();
+            table.running[get_core() as usize].as_ref().map_or("", |proc| proc.name)
+        };
+        exception::irq_restore(daif);
+        name
+    }
+
+    /// Puts the current process to sleep on `queue`. IRQs must stay masked until the following
+    /// `schedule`. Returns false if there is no process to put to sleep, e.g. during boot.
+    /// A process stopped in the meantime stays stopped, `resume` lets it check again.
+    pub fn prepare_to_wait(&self, queue: &WaitQueue) -> bool {
+        let mut pids = queue.pids.lock().unwrap();
+        let mut table = self.inner.lock().unwrap();
+        match &mut table.running[get_core() as usize] {
+            Some(proc) => {
+                if proc.state == TaskState::Running {
+                    proc.state = TaskState::Sleeping;
+                }
+                pids.push(proc.pid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the current process runnable again, whether or not it was woken up, unless it has
+    /// been stopped since.
+    pub fn finish_wait(&self, queue: &WaitQueue) {
+        let mut pids = queue.pids.lock().unwrap();
+        let mut table = self.inner.lock().unwrap();
+        if let Some(proc) = &mut table.running[get_core() as usize] {
+            if proc.state == TaskState::Sleeping {
+                proc.state = TaskState::Running;
+            }
+            pids.retain(|&pid| pid != proc.pid);
+        }
     }
 
     pub fn schedule(&self) {
@@ -172,23 +355,116 @@ impl PTable {
         let mut table = self.inner.lock().unwrap();
         table.schedule_inner();
     }
+
+    /// Lets other processes run before carrying on, without going to sleep. Does nothing
+    /// outside a process.
+    pub fn yield_now(&self) {
+        if current_pid().is_some() {
+            let daif = exception::irq_save();
+            self.schedule();
+            exception::irq_restore(daif);
+        }
+    }
     
     fn exit(&self) {
       crate::exception::irq_disable();
-      {
+      let stdio = {
         let mut table = self.inner.lock().unwrap();
-        table.exit_current_process();
-      }
+        table.exit_current_process()
+      };
+      // Closing a pipe wakes the other end, which needs the table lock.
+      drop(stdio);
+      EXIT_WAITERS.wake_all();
       self.schedule();
     }
 
+    /// Sets the exit code the current process will exit with.
+    pub fn set_exit_code(&self, code: i32) {
+        let daif = exception::irq_save();
+        if let Some(proc) = self.inner.lock().unwrap().running[get_core() as usize].as_mut() {
+            proc.exit_code = code;
+        }
+        exception::irq_restore(daif);
+    }
+
+    /// Exit code of `pid` once it has exited, which is forgotten once taken. Only the most
+    /// recent ones are kept.
+    pub fn take_exit_code(&self, pid: usize) -> Option<i32> {
+        let daif = exception::irq_save();
+        let code = {
+            let mut table = self.inner.lock().unwrap();
+            let index = table.exit_codes.iter().position(|&(p, _)| p == pid);
+            index.and_then(|i| table.exit_codes.remove(i)).map(|(_, code)| code)
+        };
+        exception::irq_restore(daif);
+        code
+    }
+
+    fn with_process<R>(&self, pid: usize, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+        let daif = exception::irq_save();
+        let result = self.inner.lock().unwrap().find(pid).map(f);
+        exception::irq_restore(daif);
+        result
+    }
+
+    /// Whether `pid` is still around, running, sleeping or stopped.
+    pub fn is_alive(&self, pid: usize) -> bool {
+        self.with_process(pid, |proc| proc.state != TaskState::Zombie).unwrap_or(false)
+    }
+
+    /// Keeps `pid` from being scheduled until `resume`. A process running on another core
+    /// stops at its next reschedule.
+    pub fn stop(&self, pid: usize) -> bool {
+        self.with_process(pid, |proc| {
+            if proc.state != TaskState::Zombie {
+                proc.state = TaskState::Stopped;
+            }
+        })
+        .is_some()
+    }
+
+    /// Lets a stopped process run again. If it was sleeping, it wakes up and checks what it was
+    /// waiting for.
+    pub fn resume(&self, pid: usize) -> bool {
+        self.with_process(pid, |proc| {
+            if proc.state == TaskState::Stopped {
+                proc.state = TaskState::Running;
+            }
+        })
+        .is_some()
+    }
+
+    /// Asks `pid` to finish early, waking it up if it sleeps. It's up to the process to check
+    /// `interrupted`.
+    pub fn interrupt(&self, pid: usize) -> bool {
+        self.with_process(pid, |proc| {
+            proc.interrupted = true;
+            if proc.state == TaskState::Sleeping {
+                proc.state = TaskState::Running;
+            }
+        })
+        .is_some()
+    }
+
+    /// Whether the current process has been asked to finish early.
+    pub fn interrupted(&self) -> bool {
+        let daif = exception::irq_save();
+        let interrupted = {
+            let table = self.inner.lock().unwrap();
+            table.running[get_core() as usize].as_ref().is_some_and(|proc| proc.interrupted)
+        };
+        exception::irq_restore(daif);
+        interrupted
+    }
+
     pub fn print(&self) {
         crate::exception::irq_disable();
-        {
+        let text = {
             let table = self.inner.lock().unwrap();
-            table.print();
-        }
+            table.format()
+        };
         crate::exception::irq_enable();
+        crate::print!("{}", text);
     }
     
     fn unlock(& self) {
@@ -200,6 +476,8 @@ struct PTableInner {
     num_procs: usize,
     head: Option<Box<Process>>,
     running: [Option<Box<Process>>; 4],
+    /// Pids and exit codes of exited processes, oldest first.
+    exit_codes: VecDeque<(usize, i32)>,
 }
 
 impl PTableInner {
@@ -208,6 +486,7 @@ impl PTableInner {
             num_procs: 0,
             head: None,
             running: [None, None, None, None],
+            exit_codes: VecDeque::new(),
         }
     }
 
@@ -217,19 +496,29 @@ impl PTableInner {
             state: TaskState::Running,
             name: "kthread",
             pid: self.num_procs + 1,
+            args: Vec::new(),
+            interrupted: false,
+            exit_code: 0,
+            stdio: Stdio::console(),
             stack: Box::new([0; 65536]),
             next: None,
         });
+        CURRENT_PIDS[core as usize].store(init_proc.pid, Ordering::Relaxed);
+        CURRENT_STDIO[core as usize].store(&init_proc.stdio as *const Stdio as *mut Stdio, Ordering::Release);
         self.running[core as usize] = Some(init_proc);
         self.num_procs += 1;
     }
 
-    fn new_process_inner(&mut self, name: &'static str, f: fn()) {
+    fn new_process_inner(&mut self, name: &'static str, f: fn(), args: Vec<String>, stdio: Stdio) -> usize {
         let mut new_proc = Box::new(Process {
             ctx: CPUContext::empty(),
             state: TaskState::Running,
             name,
             pid: self.num_procs + 1,
+            args,
+            interrupted: false,
+            exit_code: 0,
+            stdio,
             stack: Box::new([0; 65536]),
             next: None,
         });
@@ -238,8 +527,10 @@ impl PTableInner {
         new_proc.ctx.set_pc(ret_from_fork as usize);
         new_proc.ctx.set_sp(sp);
 
+        let pid = new_proc.pid;
         self.num_procs += 1;
         self.head.add_proc(new_proc);
+        pid
     }
 
     fn schedule_inner(&mut self) {
@@ -247,16 +538,16 @@ impl PTableInner {
         
         self.head.remove_zombies();
 
-        if self.head.is_none() {
+        let Some(next) = self.head.take_first_runnable() else {
             return;
-        }
-
-        let next = self.head.get_first().unwrap();
+        };
         let prev = self.running[core as usize].take().unwrap();
         
         let prev_ptr = &prev.ctx as *const CPUContext as usize;
         let next_ptr = &next.ctx as *const CPUContext as usize;
         
+        CURRENT_PIDS[core as usize].store(next.pid, Ordering::Relaxed);
+        CURRENT_STDIO[core as usize].store(&next.stdio as *const Stdio as *mut Stdio, Ordering::Release);
         self.running[core as usize] = Some(next);
         self.head.add_proc(prev);
         
@@ -266,38 +557,74 @@ impl PTableInner {
         }
     }
     
-    fn exit_current_process(&mut self) {
-      if let Some(proc) = &mut self.running[crate::utils::get_core() as usize] {
-        proc.state = TaskState::Zombie;
+    /// Marks the current process a zombie and hands back its stdio to be closed.
+    fn exit_current_process(&mut self) -> Option<Stdio> {
+      let proc = self.running[crate::utils::get_core() as usize].as_mut()?;
+      proc.state = TaskState::Zombie;
+
+      let code = match proc.exit_code {
+        0 if proc.interrupted => EXIT_INTERRUPTED,
+        code => code,
+      };
+      if self.exit_codes.len() == EXIT_CODES_KEPT {
+        self.exit_codes.pop_front();
       }
+      self.exit_codes.push_back((proc.pid, code));
+      Some(core::mem::replace(&mut proc.stdio, Stdio::console()))
+    }
+
+    fn wake(&mut self, pids: &[usize]) {
+        for proc in self.running.iter_mut().flatten() {
+            if proc.state == TaskState::Sleeping && pids.contains(&proc.pid) {
+                proc.state = TaskState::Running;
+            }
+        }
+        self.head.wake(pids);
     }
 
     fn kill(&mut self, pid: usize) {
 
     }
 
-    fn print(&self) {
-        crate::println!("\nProcess Table");
+    fn find(&mut self, pid: usize) -> Option<&mut Process> {
+        if let Some(proc) = self.running.iter_mut().flatten().find(|proc| proc.pid == pid) {
+            return Some(proc);
+        }
+        let mut current = &mut self.head;
+        while let Some(proc) = current {
+            if proc.pid == pid {
+                return Some(proc);
+            }
+            current = &mut proc.next;
+        }
+        None
+    }
+
+    /// The table as `ptable` shows it. Printed by the caller once the lock is released, output
+    /// may go to a pipe and sleep.
+    fn format(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "\nProcess Table");
         for i in 0..4 {
             if let Some(curproc) = &self.running[i] {
                 let page = &curproc.ctx as *const CPUContext as usize;
                 let name = curproc.name;
                 let pid = curproc.pid;
 
-                crate::println!("  [core {}] pid {}, context: 0x{:X}, sp: 0x{:X}, {}", i, pid, page, curproc.ctx.sp, name);
+                let _ = writeln!(text, "  [core {}] pid {}, context: 0x{:X}, sp: 0x{:X}, {}", i, pid, page, curproc.ctx.sp, name);
             }
         }
-        crate::println!("\nWaiting to run:");
+        let _ = writeln!(text, "\nWaiting to run:");
         let mut cur = &self.head;
         while let Some(curproc) = cur {
             let page = &curproc.ctx as *const CPUContext as usize;
             let name = curproc.name;
             let pid = curproc.pid;
 
-            crate::println!("  pid {}, context: 0x{:X}, sp: 0x{:X}, {}", pid, page, curproc.ctx.sp, name);
+            let _ = writeln!(text, "  pid {}, context: 0x{:X}, sp: 0x{:X}, {}", pid, page, curproc.ctx.sp, name);
             cur = &curproc.next;
         }
-        crate::println!("\n> ");
+        text
     }
 }
 
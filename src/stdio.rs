@@ -0,0 +1,228 @@
+//! Standard input and output of processes: the console, a pipe to or from another process, or
+//! for output a file in the ramfs. `print!` and `println!` go to the current process's stdout.
+
+use alloc::{collections::VecDeque, string::String, sync::Arc};
+use core::fmt;
+
+use crate::console;
+use crate::scheduler::{self, WaitQueue};
+use crate::{exception, ramfs, synchronization::{interface::Mutex, SpinLock}};
+
+/// Writers sleep once this much is buffered. Without a process to put to sleep the buffer grows.
+const PIPE_CAPACITY: usize = 4096;
+
+const CTRL_D: char = '\x04';
+
+struct PipeInner {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+struct Pipe {
+    inner: SpinLock<PipeInner>,
+    /// Woken when there is something to read, or no writers left.
+    readable: WaitQueue,
+    /// Woken when there is room, or no readers left.
+    writable: WaitQueue,
+}
+
+/// The reading end of a pipe, reads return `None` once all writers are gone.
+pub struct PipeReader(Arc<Pipe>);
+
+/// The writing end of a pipe, output is discarded once all readers are gone.
+pub struct PipeWriter(Arc<Pipe>);
+
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        inner: SpinLock::new(PipeInner {
+            buffer: VecDeque::new(),
+            readers: 1,
+            writers: 1,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+impl PipeReader {
+    pub fn read_byte(&self) -> Option<u8> {
+        loop {
+            let daif = exception::irq_save();
+            let mut inner = self.0.inner.lock().unwrap();
+
+            if let Some(b) = inner.buffer.pop_front() {
+                drop(inner);
+                self.0.writable.wake_all();
+                exception::irq_restore(daif);
+                return Some(b);
+            }
+            // Ctrl-C ends the input as well, for readers that never check.
+            if inner.writers == 0 || scheduler::PTABLE.interrupted() {
+                drop(inner);
+                exception::irq_restore(daif);
+                return None;
+            }
+
+            self.0.readable.sleep(daif, inner);
+            exception::irq_restore(daif);
+        }
+    }
+
+    /// Decodes the next UTF-8 character. Malformed or truncated sequences come out as U+FFFD.
+    pub fn read_char(&self) -> Option<char> {
+        let mut bytes = [self.read_byte()?, 0, 0, 0];
+        let len = match bytes[0] {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        for b in &mut bytes[1..len] {
+            match self.read_byte() {
+                Some(byte) => *b = byte,
+                None => return Some(char::REPLACEMENT_CHARACTER),
+            }
+        }
+        let c = core::str::from_utf8(&bytes[..len]).ok().and_then(|s| s.chars().next());
+        Some(c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+}
+
+impl PipeWriter {
+    pub fn write(&self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let daif = exception::irq_save();
+            let mut inner = self.0.inner.lock().unwrap();
+            if inner.readers == 0 {
+                drop(inner);
+                exception::irq_restore(daif);
+                return;
+            }
+
+            let room = PIPE_CAPACITY.saturating_sub(inner.buffer.len());
+            let can_sleep = exception::irqs_enabled(daif) && scheduler::current_pid().is_some();
+            if room == 0 && can_sleep {
+                self.0.writable.sleep(daif, inner);
+                exception::irq_restore(daif);
+                continue;
+            }
+
+            let count = if room == 0 { data.len() } else { room.min(data.len()) };
+            inner.buffer.extend(&data[..count]);
+            data = &data[count..];
+            drop(inner);
+            self.0.readable.wake_all();
+            exception::irq_restore(daif);
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let daif = exception::irq_save();
+        self.0.inner.lock().unwrap().readers -= 1;
+        self.0.writable.wake_all();
+        exception::irq_restore(daif);
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let daif = exception::irq_save();
+        self.0.inner.lock().unwrap().writers -= 1;
+        self.0.readable.wake_all();
+        exception::irq_restore(daif);
+    }
+}
+
+pub enum Input {
+    Console,
+    Pipe(PipeReader),
+}
+
+pub enum Output {
+    Console,
+    Pipe(PipeWriter),
+    /// Appended to a ramfs file.
+    File(String),
+}
+
+pub struct Stdio {
+    pub input: Input,
+    pub output: Output,
+}
+
+impl Stdio {
+    pub const fn console() -> Self {
+        Self {
+            input: Input::Console,
+            output: Output::Console,
+        }
+    }
+
+    /// A character from stdin, `None` at the end of a pipe or on Ctrl-D at the console.
+    pub fn read_char(&self) -> Option<char> {
+        match &self.input {
+            Input::Console => Some(console::console().read_char()).filter(|&c| c != CTRL_D),
+            Input::Pipe(reader) => reader.read_char(),
+        }
+    }
+}
+
+/// Formats into a process's stdout.
+struct Writer<'a>(&'a Stdio);
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match &self.0.output {
+            Output::Console => console::console().write_fmt(format_args!("{}", s)),
+            Output::Pipe(writer) => {
+                writer.write(s.as_bytes());
+                Ok(())
+            }
+            Output::File(name) => ramfs::append(name, s.as_bytes()).map_err(|_| fmt::Error),
+        }
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
+        match &self.0.output {
+            // One call, so the console lock keeps the line together.
+            Output::Console => console::console().write_fmt(args),
+            _ => fmt::write(self, args),
+        }
+    }
+}
+
+/// Writes to the current process's stdout.
+pub fn print(args: fmt::Arguments) -> fmt::Result {
+    scheduler::with_stdio(|stdio| fmt::Write::write_fmt(&mut Writer(stdio), args))
+}
+
+/// Reads a line from the current process's stdin, without the line ending. `None` at the end of
+/// input.
+pub fn read_line() -> Option<String> {
+    scheduler::with_stdio(|stdio| {
+        let mut line = String::new();
+        loop {
+            match stdio.read_char() {
+                Some('\n') => return Some(line),
+                // The console ends lines with CR, pipes with LF.
+                Some('\r') if matches!(stdio.input, Input::Console) => {
+                    console::console().write_char('\n');
+                    return Some(line);
+                }
+                Some(c) => {
+                    if matches!(stdio.input, Input::Console) {
+                        console::console().write_char(c);
+                    }
+                    line.push(c);
+                }
+                None if line.is_empty() => return None,
+                None => return Some(line),
+            }
+        }
+    })
+}
@@ -1,97 +1,125 @@
+mod command;
+pub mod console;
+pub mod dmesg;
+pub mod files;
+pub mod fb;
+pub mod gpio;
+pub mod i2c;
 pub mod shell;
+pub mod spi;
+pub mod sysinfo;
+pub mod uart;
 
+use alloc::{string::String, vec::Vec};
 
+use crate::println;
 use crate::synchronization::{interface::Mutex, SpinLock};
 
-const NUM_CMDS: usize = 10;
+pub use command::{Arg, ArgType, Args, Command};
 
 static CMD_LIST: CommandList = CommandList::new();
 
-pub fn register_cmd(name: &'static str, entry: fn()) {
-    CMD_LIST.register_cmd(name, entry);
+/// Exit status for a command that doesn't exist, as in `sh`.
+const EXIT_NOT_FOUND: i32 = 127;
+/// Exit status for a command given the wrong arguments.
+const EXIT_USAGE: i32 = 2;
+
+pub fn register(command: Command) {
+    CMD_LIST.register(command);
 }
 
-struct CommandList {
-    inner: SpinLock<CommandListInner>,
+/// Starts a task for a prepared command, with its stdin and stdout, and returns its pid.
+fn spawn(cmd: &Command, args: Vec<String>, stdio: crate::stdio::Stdio) -> usize {
+    crate::scheduler::PTABLE.new_process_with_stdio(cmd.name, cmd.entry, args, stdio)
 }
 
-impl CommandList {
-    const fn new() -> Self {
-        Self {
-            inner: SpinLock::new(CommandListInner::new()),
-        }
-    }
+/// Arguments passed to the running command.
+pub fn args() -> Vec<String> {
+    crate::scheduler::PTABLE.current_args()
+}
 
-    fn register_cmd(&self, name: &'static str, entry: fn()) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.register_cmd(name, entry);
-    }
+/// Whether the running command has been asked to stop early, e.g. with Ctrl-C. Commands that
+/// run for a while should check it.
+pub fn interrupted() -> bool {
+    crate::scheduler::PTABLE.interrupted()
+}
 
-    fn print_cmds(&self) {
-        let inner = self.inner.lock().unwrap();
-        inner.print_cmds();
-    }
+/// Sets the running command's exit status, zero for success, which the shell sees as `$?`.
+pub fn set_exit_code(code: i32) {
+    crate::scheduler::PTABLE.set_exit_code(code);
+}
 
-    fn run_cmd(&self, cmd_with_args: &str) {
-        let inner = self.inner.lock().unwrap();
-        inner.run_cmd(cmd_with_args);
-    }
+/// Arguments passed to the running command, parsed against its spec. They were checked before
+/// the command was started.
+pub fn parsed_args() -> Args {
+    let name = crate::scheduler::PTABLE.current_name();
+    let args = args();
+    let tokens: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    CMD_LIST
+        .find(name)
+        .and_then(|cmd| cmd.parse(&tokens).ok())
+        .unwrap_or_default()
 }
 
-struct CommandListInner {
-    next_idx: usize,
-    cmds: [Option<Command>; NUM_CMDS],
+struct CommandList {
+    cmds: SpinLock<Vec<Command>>,
 }
 
-impl CommandListInner {
+impl CommandList {
     const fn new() -> Self {
         Self {
-            next_idx: 0,
-            cmds: [None; NUM_CMDS],
+            cmds: SpinLock::new(Vec::new()),
         }
     }
 
-    fn register_cmd(&mut self, name: &'static str, entry: fn()) {
-        assert!(self.next_idx < NUM_CMDS);
+    fn register(&self, command: Command) {
+        let mut cmds = self.cmds.lock().unwrap();
+        cmds.retain(|cmd| cmd.name != command.name);
+        cmds.push(command);
+    }
 
-        let idx = self.next_idx;
-        self.cmds[idx] = Some(Command::new(name, entry));
-        self.next_idx += 1;
+    fn find(&self, name: &str) -> Option<Command> {
+        let cmds = self.cmds.lock().unwrap();
+        cmds.iter().find(|cmd| cmd.name == name).cloned()
     }
 
     fn print_cmds(&self) {
-        crate::println!("Here are the available commands:");
-        for i in 0..self.next_idx {
-            if let Some(cmd) = &self.cmds[i] {
-                crate::println!("  {}", cmd.name);
-            }
+        let cmds = self.cmds.lock().unwrap().clone();
+        let width = cmds.iter().map(|cmd| cmd.name.len()).max().unwrap_or(0);
+
+        println!("Here are the available commands, `help <cmd>` for more:");
+        for cmd in cmds {
+            println!("  {:<width$}  {}", cmd.name, cmd.description, width = width);
         }
     }
 
-    fn run_cmd(&self, cmd_with_args: &str) {
-        let tokens: alloc::vec::Vec<&str> = cmd_with_args.split(' ').collect();
-        let cmd_name = tokens[0];
-
-        for i in 0..self.next_idx {
-            if let Some(cmd) = &self.cmds[i] {
-                if cmd.name.cmp(cmd_name) == core::cmp::Ordering::Equal {
-                    crate::scheduler::PTABLE.new_process(cmd.name, cmd.entry);
-                    return;
-                }
-            }
+    fn print_help(&self, name: &str) {
+        match self.find(name) {
+            Some(cmd) => cmd.print_help(),
+            None => println!("help: no such command: {}", name),
         }
     }
 
-}
-
-#[derive(Copy, Clone)]
-struct Command {
-    name: &'static str,
-    entry: fn(),
-}
+    /// Looks up the command and checks its arguments, telling the user what is wrong and
+    /// returning the exit status if that fails.
+    fn prepare(&self, words: &[String]) -> Result<(Command, Vec<String>), i32> {
+        let tokens: Vec<&str> = words.iter().map(String::as_str).collect();
+        let (&name, args) = tokens.split_first().ok_or(EXIT_USAGE)?;
+        let Some(cmd) = self.find(name) else {
+            println!("{}: command not found", name);
+            return Err(EXIT_NOT_FOUND);
+        };
+
+        if let Err(x) = cmd.parse(args) {
+            println!("{}: {}", cmd.name, x);
+            println!("{}", cmd.usage_string());
+            return Err(EXIT_USAGE);
+        }
+        Ok((cmd, args.iter().map(|t| (*t).into()).collect()))
+    }
 
-impl Command {
-    fn new(name: &'static str, entry: fn()) -> Self {
-        Self { name, entry }
+    fn names(&self) -> Vec<&'static str> {
+        let cmds = self.cmds.lock().unwrap();
+        cmds.iter().map(|cmd| cmd.name).collect()
     }
 }
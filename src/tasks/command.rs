@@ -0,0 +1,199 @@
+//! Shell commands: what they are called, what they do, and the arguments they take. Arguments
+//! are checked against the spec before a task is started for the command.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ArgType {
+    Int,
+    Str,
+    /// One of a few words, read with `Args::str`.
+    OneOf(&'static [&'static str]),
+}
+
+impl ArgType {
+    fn parse(self, token: &str) -> Result<Value, String> {
+        match self {
+            Self::Int => parse_int(token).map(Value::Int).ok_or_else(|| format!("not a number: {}", token)),
+            Self::Str => Ok(Value::Str(token.into())),
+            Self::OneOf(words) if words.contains(&token) => Ok(Value::Str(token.into())),
+            Self::OneOf(words) => Err(format!("expected {}: {}", words.join("|"), token)),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Arg {
+    /// `-x`, present or not.
+    Flag(&'static str),
+    /// `-x <value>`, may be left out.
+    Option(&'static str, &'static str, ArgType),
+    Positional(&'static str, ArgType),
+    /// Positional, may be left out. Only followed by other optional ones.
+    OptionalPositional(&'static str, ArgType),
+    /// Whatever follows the other arguments, for commands that parse their own subcommands
+    /// from `Args::rest`. Always last.
+    Rest(&'static str),
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+}
+
+/// Arguments as parsed against a command's spec.
+#[derive(Default)]
+pub struct Args {
+    flags: Vec<&'static str>,
+    values: Vec<(&'static str, Value)>,
+    rest: Vec<String>,
+}
+
+impl Args {
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(&name)
+    }
+
+    /// An integer option or positional, by name.
+    pub fn int(&self, name: &str) -> Option<i64> {
+        self.values.iter().find_map(|(n, v)| match v {
+            Value::Int(i) if *n == name => Some(*i),
+            _ => None,
+        })
+    }
+
+    /// A string option or positional, by name.
+    pub fn str(&self, name: &str) -> Option<&str> {
+        self.values.iter().find_map(|(n, v)| match v {
+            Value::Str(s) if *n == name => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The tokens taken by `Arg::Rest`, empty if there were none.
+    pub fn rest(&self) -> Vec<&str> {
+        self.rest.iter().map(String::as_str).collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Command {
+    pub name: &'static str,
+    pub entry: fn(),
+    pub description: &'static str,
+    usage: Option<&'static str>,
+    args: &'static [Arg],
+}
+
+impl Command {
+    pub const fn new(name: &'static str, entry: fn()) -> Self {
+        Self {
+            name,
+            entry,
+            description: "",
+            usage: None,
+            args: &[],
+        }
+    }
+
+    pub const fn description(mut self, description: &'static str) -> Self {
+        self.description = description;
+        self
+    }
+
+    pub const fn args(mut self, args: &'static [Arg]) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Replaces the usage generated from the arguments, for commands with subcommands.
+    pub const fn usage(mut self, usage: &'static str) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    pub fn usage_string(&self) -> String {
+        match self.usage {
+            Some(usage) => usage.into(),
+            None => format!("usage: {}", Usage(self)),
+        }
+    }
+
+    /// Checks `tokens` against the spec.
+    pub fn parse(&self, tokens: &[&str]) -> Result<Args, String> {
+        let mut args = Args::default();
+        let mut positionals = self.args.iter().filter(|a| matches!(a, Arg::Positional(..) | Arg::OptionalPositional(..)));
+        let takes_rest = self.args.iter().any(|a| matches!(a, Arg::Rest(_)));
+        let mut tokens = tokens.iter();
+
+        while let Some(&token) = tokens.next() {
+            let option = self.args.iter().find(|a| matches!(a, Arg::Flag(f) | Arg::Option(f, ..) if *f == token));
+            match option {
+                Some(Arg::Flag(flag)) => args.flags.push(flag),
+                Some(Arg::Option(flag, name, ty)) => {
+                    let value = tokens.next().ok_or_else(|| format!("{} needs a <{}>", flag, name))?;
+                    let value = ty.parse(value).map_err(|e| format!("{}: {}", flag, e))?;
+                    args.values.push((name, value));
+                }
+                _ => match positionals.next() {
+                    Some(Arg::Positional(name, ty) | Arg::OptionalPositional(name, ty)) => {
+                        let value = ty.parse(token).map_err(|e| format!("<{}>: {}", name, e))?;
+                        args.values.push((name, value));
+                    }
+                    _ if takes_rest => {
+                        args.rest = core::iter::once(token).chain(tokens.by_ref().copied()).map(String::from).collect();
+                        break;
+                    }
+                    _ => return Err(format!("unexpected argument: {}", token)),
+                },
+            }
+        }
+
+        if let Some(Arg::Positional(name, _)) = positionals.next() {
+            return Err(format!("missing <{}>", name));
+        }
+        Ok(args)
+    }
+
+    /// What `help <cmd>` prints.
+    pub fn print_help(&self) {
+        crate::println!("{}", self.usage_string());
+        if !self.description.is_empty() {
+            crate::println!("{}", self.description);
+        }
+    }
+}
+
+/// The usage line generated from a command's arguments.
+struct Usage<'a>(&'a Command);
+
+impl fmt::Display for Usage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.name)?;
+        for arg in self.0.args {
+            match arg {
+                Arg::Flag(flag) => write!(f, " [{}]", flag)?,
+                Arg::Option(flag, name, _) => write!(f, " [{} <{}>]", flag, name)?,
+                Arg::Positional(name, _) => write!(f, " <{}>", name)?,
+                Arg::OptionalPositional(name, _) => write!(f, " [{}]", name)?,
+                Arg::Rest(name) => write!(f, " [{}...]", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decimal, or hex with `0x`.
+fn parse_int(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,46 @@
+use crate::console::{self, LogLevel};
+use crate::println;
+
+pub const USAGE: &str = "usage: console [<name> output|input on|off | <name> log error|warn|info|debug|off]";
+
+/// Lists the consoles and what they are used for, or changes it for one of them.
+pub fn console() {
+    let args = super::parsed_args();
+
+    let result = match (args.str("name"), args.str("setting"), args.str("value")) {
+        (None, ..) => {
+            list();
+            Ok(())
+        }
+        (Some(name), Some("output"), Some(state @ ("on" | "off"))) => {
+            console::configure_sink(name, |s| s.output = state == "on")
+        }
+        (Some(name), Some("input"), Some(state @ ("on" | "off"))) => {
+            console::configure_sink(name, |s| s.input = state == "on")
+        }
+        (Some(name), Some("log"), Some("off")) => console::configure_sink(name, |s| s.log_level = None),
+        (Some(name), Some("log"), Some(level)) => match LogLevel::from_name(level) {
+            Some(level) => console::configure_sink(name, |s| s.log_level = Some(level)),
+            None => Err(USAGE),
+        },
+        _ => Err(USAGE),
+    };
+    if let Err(x) = result {
+        println!("console: {}", x);
+    }
+}
+
+fn list() {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+
+    println!("name     output input log");
+    for sink in console::sinks() {
+        println!(
+            "{:<8} {:<6} {:<5} {}",
+            sink.name,
+            on_off(sink.output),
+            on_off(sink.input),
+            sink.log_level.map_or("off", LogLevel::name)
+        );
+    }
+}
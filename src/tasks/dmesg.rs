@@ -0,0 +1,49 @@
+use crate::console::{self, LogLevel};
+use crate::{log, println};
+
+pub const USAGE: &str = "usage: dmesg [-c] [-l error|warn|info|debug] [-n error|warn|info|debug]";
+
+/// Dumps the kernel log, `-l` only down to a level, `-c` clears it afterwards. `-n` sets the
+/// level of every console instead.
+pub fn dmesg() {
+    let args = super::parsed_args();
+    let level = |name| args.str(name).map(|l| LogLevel::from_name(l).ok_or(USAGE)).transpose();
+
+    let result = match (level("level"), level("console level")) {
+        (Ok(_), Ok(Some(console_level))) => set_console_level(console_level),
+        (Ok(level), Ok(None)) => {
+            dump(level.unwrap_or(LogLevel::Debug));
+            if args.flag("-c") {
+                log::clear();
+            }
+            Ok(())
+        }
+        (Err(x), _) | (_, Err(x)) => Err(x),
+    };
+    if let Err(x) = result {
+        println!("dmesg: {}", x);
+    }
+}
+
+fn dump(max_level: LogLevel) {
+    for record in log::records().filter(|r| r.level <= max_level) {
+        let pid = record.pid.unwrap_or(0);
+        println!(
+            "[{:>4}.{:06}] {:<5} core {} pid {:<3} {}",
+            record.timestamp.as_secs(),
+            record.timestamp.subsec_micros(),
+            record.level.name(),
+            record.core,
+            pid,
+            record.text()
+        );
+    }
+}
+
+/// Applies to the consoles that take the log at all, `console <name> log` turns one on.
+fn set_console_level(level: LogLevel) -> Result<(), &'static str> {
+    for sink in console::sinks().filter(|s| s.log_level.is_some()) {
+        console::configure_sink(sink.name, |s| s.log_level = Some(level))?;
+    }
+    Ok(())
+}
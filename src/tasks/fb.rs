@@ -0,0 +1,53 @@
+use alloc::vec::Vec;
+
+use crate::bsp::{self, device_driver::Color};
+use crate::println;
+
+pub const USAGE: &str = "usage: fb [test]";
+
+/// Shows the framebuffer size, `fb test` draws a test pattern over the right half of the screen.
+pub fn fb() {
+    let args = super::parsed_args();
+    let fb = &bsp::FRAMEBUFFER;
+    let (width, height) = fb.size();
+
+    match args.str("action") {
+        None if width == 0 => println!("fb: no framebuffer"),
+        None => println!("{}x{}", width, height),
+        Some("test") => {
+            let (x, w) = (width / 2, width / 2);
+
+            // Colour bars across the top half.
+            let bars = [
+                Color::WHITE,
+                Color::new(255, 255, 0),
+                Color::new(0, 255, 255),
+                Color::new(0, 255, 0),
+                Color::new(255, 0, 255),
+                Color::new(255, 0, 0),
+                Color::new(0, 0, 255),
+                Color::BLACK,
+            ];
+            let bar_width = w / bars.len();
+            for (i, &color) in bars.iter().enumerate() {
+                fb.fill_rect(x + i * bar_width, 0, bar_width, height / 2, color);
+            }
+
+            // A grey ramp blitted below them, then a dotted grid over it.
+            let ramp: Vec<Color> = (0..w).map(|i| (i * 255 / w) as u8).map(|v| Color::new(v, v, v)).collect();
+            for y in height / 2..height * 3 / 4 {
+                fb.blit(x, y, w, &ramp);
+            }
+            for y in (height / 2..height * 3 / 4).step_by(16) {
+                for px in (x..width).step_by(4) {
+                    fb.set_pixel(px, y, Color::new(255, 0, 0));
+                }
+            }
+
+            // The bottom quarter is a copy of the bars, inverted.
+            fb.copy_rect(x, 0, w, height / 4, x, height * 3 / 4);
+            fb.invert_rect(x, height * 3 / 4, w, height / 4);
+        }
+        Some(_) => println!("{}", USAGE),
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{print, println, ramfs, stdio};
+
+/// Lists the files in the ramfs.
+pub fn ls() {
+    for (name, size) in ramfs::list() {
+        println!("{:>8}  {}", size, name);
+    }
+}
+
+/// Prints a file, or copies stdin to stdout.
+pub fn cat() {
+    let args = super::parsed_args();
+    let Some(name) = args.str("file") else {
+        while let Some(line) = stdio::read_line() {
+            println!("{}", line);
+        }
+        return;
+    };
+
+    match ramfs::read(name) {
+        Some(data) => print!("{}", core::str::from_utf8(&data).unwrap_or("<binary file>\n")),
+        None => {
+            println!("cat: {}: no such file", name);
+            super::set_exit_code(1);
+        }
+    }
+}
+
+pub fn rm() {
+    let args = super::parsed_args();
+    let name = args.str("file").unwrap_or("");
+    if !ramfs::remove(name) {
+        println!("rm: {}: no such file", name);
+        super::set_exit_code(1);
+    }
+}
+
+/// Prints its arguments.
+pub fn echo() {
+    println!("{}", super::parsed_args().rest().join(" "));
+}
+
+/// Prints the lines of stdin that contain the pattern, or with `-v` the ones that don't. Fails
+/// if no line was printed.
+pub fn grep() {
+    let args = super::parsed_args();
+    let pattern = args.str("pattern").unwrap_or("");
+    let invert = args.flag("-v");
+
+    let mut found = false;
+    while let Some(line) = stdio::read_line() {
+        if line.contains(pattern) != invert {
+            println!("{}", line);
+            found = true;
+        }
+    }
+    if !found {
+        super::set_exit_code(1);
+    }
+}
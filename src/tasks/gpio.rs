@@ -0,0 +1,76 @@
+use crate::bsp::{
+    self,
+    device_driver::{Function, Pull, Trigger, NUM_PINS},
+};
+use crate::{info, println};
+
+pub const USAGE: &str = "usage: gpio [<pin> [in|out|alt0-alt5|high|low|pull up|down|off|irq <trigger>|off|watch|wait]]";
+
+/// What `gpio <pin>` can be followed by: a function, or something to do with the pin.
+pub const ACTIONS: &[&str] = &[
+    "in", "out", "alt0", "alt1", "alt2", "alt3", "alt4", "alt5", "high", "low", "pull", "irq", "watch", "wait",
+];
+
+/// Shows pin functions and levels, or changes one pin.
+pub fn gpio() {
+    let args = super::parsed_args();
+
+    let Some(pin) = args.int("pin") else {
+        for pin in 0..NUM_PINS {
+            print_pin(pin);
+        }
+        return;
+    };
+    let Ok(pin) = usize::try_from(pin) else {
+        println!("{}", USAGE);
+        return;
+    };
+
+    let result = match (args.str("action"), args.str("value")) {
+        (None, _) => Ok(()),
+        (Some("high"), None) => bsp::GPIO.set_level(pin, true),
+        (Some("low"), None) => bsp::GPIO.set_level(pin, false),
+        (Some("pull"), Some(pull)) => match pull {
+            "up" => bsp::GPIO.set_pull(pin, Pull::Up),
+            "down" => bsp::GPIO.set_pull(pin, Pull::Down),
+            "off" => bsp::GPIO.set_pull(pin, Pull::Off),
+            _ => Err(USAGE),
+        },
+        (Some("irq"), Some("off")) => bsp::GPIO
+            .disable_triggers(pin)
+            .and_then(|()| bsp::GPIO.set_callback(pin, None)),
+        (Some("irq"), Some(trigger)) => match Trigger::from_name(trigger) {
+            Some(trigger) => bsp::GPIO.enable_trigger(pin, trigger),
+            None => Err("trigger must be rising, falling, high, low, arising or afalling"),
+        },
+        (Some("watch"), None) => bsp::GPIO.set_callback(pin, Some(log_event)),
+        (Some("wait"), None) => bsp::GPIO.wait_for_event(pin),
+        (Some(function), None) => match Function::from_name(function) {
+            Some(function) => bsp::GPIO.set_function(pin, function),
+            None => Err(USAGE),
+        },
+        _ => Err(USAGE),
+    };
+
+    match result {
+        Ok(()) => print_pin(pin),
+        Err(x) => println!("gpio: {}", x),
+    }
+}
+
+fn print_pin(pin: usize) {
+    match (bsp::GPIO.function(pin), bsp::GPIO.level(pin), bsp::GPIO.triggers(pin)) {
+        (Ok(function), Ok(level), Ok(triggers)) => {
+            crate::print!("{:>2}: {:<4} {:<4}", pin, function, if level { "high" } else { "low" });
+            for trigger in triggers {
+                crate::print!(" {}", trigger.name());
+            }
+            println!();
+        }
+        (Err(x), _, _) | (_, Err(x), _) | (_, _, Err(x)) => println!("gpio: {}", x),
+    }
+}
+
+fn log_event(pin: usize) {
+    info!("gpio {}: event", pin);
+}
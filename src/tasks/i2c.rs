@@ -0,0 +1,118 @@
+use alloc::vec::Vec;
+
+use crate::bsp::{
+    self,
+    device_driver::{ClockId, I2c, I2cAddress, I2cError},
+};
+use crate::{print, println};
+
+pub const USAGE: &str = "usage: i2c [0|1 [config <hz> [stretch ms]|read <addr> <len>|write <addr> <hex bytes>|\
+                     xfer <addr> <len> <hex bytes>]]\n       addresses above 0x7f are 10-bit";
+
+/// Shows the I2C masters' settings, configures one or runs a transaction on it.
+pub fn i2c() {
+    let args = super::parsed_args();
+
+    let Some(bus) = args.int("bus") else {
+        for (i, bus) in buses().iter().enumerate() {
+            println!("i2c{}: {}", i, bus.settings());
+        }
+        return;
+    };
+    let Some(bus) = usize::try_from(bus).ok().and_then(|i| buses().get(i).copied()) else {
+        println!("{}", USAGE);
+        return;
+    };
+
+    let result = match args.rest().as_slice() {
+        ["config", config @ ..] => configure(bus, config),
+        ["read", addr, len] => transaction(bus, addr, len, &[]),
+        ["write", addr, bytes @ ..] => transaction(bus, addr, "0", bytes),
+        ["xfer", addr, len, bytes @ ..] => transaction(bus, addr, len, bytes),
+        _ => Err(USAGE),
+    };
+    if let Err(x) = result {
+        println!("i2c: {}", x);
+    }
+}
+
+/// Scans a bus for devices like Linux' i2cdetect: a bare address write, except for the ranges
+/// where that could latch data into EEPROMs, which get a byte read instead.
+pub fn i2cdetect() {
+    let bus = super::parsed_args().int("bus").and_then(|i| buses().get(i as usize).copied());
+    let Some(bus) = bus else {
+        println!("i2cdetect: no such bus");
+        return;
+    };
+
+    println!("     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f");
+    for row in (0..0x80u8).step_by(16) {
+        print!("{:02x}:", row);
+        for addr in row..row + 16 {
+            // Reserved addresses aren't probed.
+            if !(0x03..=0x77).contains(&addr) {
+                print!("   ");
+                continue;
+            }
+
+            let device = I2cAddress::SevenBit(addr);
+            let result = if (0x30..=0x37).contains(&addr) || (0x50..=0x5F).contains(&addr) {
+                bus.read(device, &mut [0])
+            } else {
+                bus.write(device, &[])
+            };
+            match result {
+                Ok(()) => print!(" {:02x}", addr),
+                Err(I2cError::Nack) => print!(" --"),
+                // Bus trouble rather than an absent device, e.g. a stuck clock.
+                Err(_) => print!(" XX"),
+            }
+        }
+        println!();
+    }
+}
+
+fn buses() -> [&'static I2c; 2] {
+    [&bsp::I2C0, &bsp::I2C1]
+}
+
+fn configure(bus: &I2c, args: &[&str]) -> Result<(), &'static str> {
+    let mut settings = bus.settings();
+    let mut args = args.iter();
+
+    settings.speed_hz = args.next().and_then(|s| s.parse().ok()).ok_or(USAGE)?;
+    if let Some(ms) = args.next() {
+        settings.stretch_timeout_ms = ms.parse().map_err(|_| USAGE)?;
+    }
+
+    bus.configure(&settings, bsp::MAILBOX.clock_rate(ClockId::Core)?)?;
+    println!("{}", bus.settings());
+    Ok(())
+}
+
+fn parse_address(s: &str) -> Result<I2cAddress, &'static str> {
+    let addr = u16::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|_| "address must be hex")?;
+    Ok(match u8::try_from(addr) {
+        Ok(addr) if addr <= 0x7F => I2cAddress::SevenBit(addr),
+        _ => I2cAddress::TenBit(addr),
+    })
+}
+
+/// Writes `bytes` and then reads `len` bytes back in one combined transaction.
+fn transaction(bus: &I2c, addr: &str, len: &str, bytes: &[&str]) -> Result<(), &'static str> {
+    let addr = parse_address(addr)?;
+    let len = len.parse().map_err(|_| USAGE)?;
+    let tx = bytes
+        .iter()
+        .map(|s| u8::from_str_radix(s, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "bytes must be hex, e.g. 0f 00")?;
+    let mut rx = alloc::vec![0; len];
+
+    bus.write_read(addr, &tx, &mut rx)?;
+    for b in rx {
+        print!("{:02x} ", b);
+    }
+    println!();
+    Ok(())
+}
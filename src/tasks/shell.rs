@@ -1,34 +1,285 @@
-use alloc::string::String;
-use core::str;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
 
-use crate::{console::console, println};
+use crate::scheduler::EXIT_INTERRUPTED;
+use crate::stdio::{self, Input, Output, Stdio};
+use crate::{console::console, println, ramfs};
 
-fn parse_command(command: &str) {
-    println!();
-    match command {
-        "help" => {
-            super::CMD_LIST.print_cmds();
-        },
-        "" => {},
-        cmd_with_args => super::CMD_LIST.run_cmd(cmd_with_args),
+mod editor;
+mod jobs;
+mod script;
+
+use editor::LineEditor;
+use jobs::Jobs;
+use script::{Block, Op, Pipeline, Word};
+
+/// Commands the shell handles itself rather than starting a task for.
+const BUILTINS: [&str; 9] = ["help", "jobs", "fg", "bg", "source", "env", "unset", "true", "false"];
+
+/// Run when the shell starts, if the initramfs has it.
+const BOOT_SCRIPT: &str = "boot.sh";
+
+struct Shell {
+    jobs: Jobs,
+    vars: BTreeMap<String, String>,
+    /// Exit status of the last command, `$?`.
+    status: i32,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Self {
+            jobs: Jobs::new(),
+            vars: BTreeMap::new(),
+            status: 0,
+        }
+    }
+
+    fn split_words(&self, text: &str) -> Result<Vec<Word>, String> {
+        script::split_words(text, self.status, |name| self.vars.get(name).map(String::as_str))
+    }
+
+    fn run_parsed(&mut self, parsed: Result<Block, script::Error>) -> i32 {
+        match parsed {
+            Ok(block) => return self.run_block(&block),
+            Err(script::Error::Incomplete) => println!("syntax error: unexpected end of script"),
+            Err(script::Error::Syntax(e)) => println!("syntax error: {}", e),
+        }
+        self.status = 2;
+        self.status
+    }
+
+    /// Runs the commands in order, stopping early if one is interrupted with Ctrl-C.
+    fn run_block(&mut self, block: &Block) -> i32 {
+        for and_or in block {
+            self.run(&and_or.first);
+            for (op, command) in &and_or.rest {
+                if self.status == EXIT_INTERRUPTED {
+                    break;
+                }
+                if (*op == Op::And) == (self.status == 0) {
+                    self.run(command);
+                }
+            }
+            if self.status == EXIT_INTERRUPTED {
+                break;
+            }
+        }
+        self.status
+    }
+
+    fn run(&mut self, command: &script::Command) {
+        match command {
+            script::Command::Simple(text) => self.status = self.run_simple(text),
+            script::Command::If(branches, otherwise) => {
+                for (condition, body) in branches {
+                    if self.run_block(condition) == 0 {
+                        self.run_block(body);
+                        return;
+                    }
+                    if self.status == EXIT_INTERRUPTED {
+                        return;
+                    }
+                }
+                match otherwise {
+                    Some(body) => {
+                        self.run_block(body);
+                    }
+                    None => self.status = 0,
+                }
+            }
+            script::Command::For(var, words, body) => {
+                let words = self.split_words(words).and_then(|words| {
+                    words
+                        .into_iter()
+                        .map(|word| match word {
+                            Word::Text(text) => Ok(text),
+                            _ => Err("expected words after `in`".into()),
+                        })
+                        .collect::<Result<Vec<_>, String>>()
+                });
+                let words = match words {
+                    Ok(words) => words,
+                    Err(e) => {
+                        println!("syntax error: {}", e);
+                        self.status = 2;
+                        return;
+                    }
+                };
+                self.status = 0;
+                for word in words {
+                    self.vars.insert(var.clone(), word);
+                    if self.run_block(body) == EXIT_INTERRUPTED {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn run_simple(&mut self, text: &str) -> i32 {
+        let pipeline = self.split_words(text).and_then(Pipeline::parse);
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                println!("syntax error: {}", e);
+                return 2;
+            }
+        };
+
+        let simple = pipeline.commands.len() == 1 && pipeline.redirect.is_none() && !pipeline.background;
+        let Some(words) = pipeline.commands.first() else {
+            return 0;
+        };
+        // `NAME=value` sets a variable.
+        if let Some((name, value)) = words[0].split_once('=') {
+            if simple && words.len() == 1 && script::is_name(name) {
+                self.vars.insert(name.into(), value.into());
+                return 0;
+            }
+        }
+
+        // Builtins run inside the shell rather than as a task, so can't be piped or redirected.
+        let builtin = pipeline.commands.iter().map(|words| words[0].as_str()).find(|w| BUILTINS.contains(w));
+        match builtin {
+            Some(name) if !simple => {
+                println!("syntax error: `{}` can't be used in a pipeline, with `>` or with `&`", name);
+                return 2;
+            }
+            Some(_) => {}
+            None => return self.run_pipeline(pipeline, text.trim().trim_end_matches('&').trim_end()),
+        }
+
+        let words: Vec<&str> = pipeline.commands[0].iter().map(String::as_str).collect();
+        match words[..] {
+            ["help"] => super::CMD_LIST.print_cmds(),
+            ["help", name, ..] => super::CMD_LIST.print_help(name),
+            ["jobs", ..] => self.jobs.list(),
+            ["fg", ..] => return self.jobs.fg(words.get(1).copied()),
+            ["bg", ..] => return self.jobs.bg(words.get(1).copied()),
+            ["source", name, ..] => return self.source(name),
+            ["source"] => {
+                println!("usage: source <file>");
+                return 2;
+            }
+            ["env", ..] => {
+                for (name, value) in &self.vars {
+                    println!("{}={}", name, value);
+                }
+            }
+            ["unset", ..] => {
+                self.vars.remove(words.get(1).copied().unwrap_or(""));
+            }
+            ["false", ..] => return 1,
+            // `true`.
+            _ => {}
+        }
+        0
+    }
+
+    /// `source <file>`: runs a script from the ramfs.
+    fn source(&mut self, name: &str) -> i32 {
+        let Some(data) = ramfs::read(name) else {
+            println!("source: {}: no such file", name);
+            return 1;
+        };
+        match core::str::from_utf8(&data) {
+            Ok(text) => self.run_parsed(script::parse(text)),
+            Err(_) => {
+                println!("source: {}: not a text file", name);
+                1
+            }
+        }
+    }
+
+    /// Runs a task per command of the pipeline, with each one's stdout piped into the next one's
+    /// stdin. `command` is the line as typed, for `jobs`.
+    fn run_pipeline(&mut self, pipeline: Pipeline, command: &str) -> i32 {
+        let prepared = pipeline.commands.iter().map(|words| super::CMD_LIST.prepare(words));
+        let prepared = match prepared.collect::<Result<Vec<_>, _>>() {
+            Ok(prepared) => prepared,
+            Err(status) => return status,
+        };
+
+        if let Some((file, append)) = &pipeline.redirect {
+            let created = if *append { ramfs::append(file, &[]) } else { ramfs::create(file) };
+            if let Err(e) = created {
+                println!("{}: {}", file, e);
+                return 1;
+            }
+        }
+
+        // The shell owns the console and forwards what is typed to the foreground job.
+        let (reader, writer) = stdio::pipe();
+        let mut inputs = Vec::from([reader]);
+        let mut outputs = Vec::new();
+        for _ in 1..prepared.len() {
+            let (reader, writer) = stdio::pipe();
+            inputs.push(reader);
+            outputs.push(Output::Pipe(writer));
+        }
+        outputs.push(match pipeline.redirect {
+            Some((file, _)) => Output::File(file),
+            None => Output::Console,
+        });
+
+        let pids: Vec<usize> = prepared
+            .into_iter()
+            .zip(inputs.into_iter().zip(outputs))
+            .map(|((cmd, args), (input, output))| {
+                super::spawn(&cmd, args, Stdio { input: Input::Pipe(input), output })
+            })
+            .collect();
+
+        if pipeline.background {
+            let last = pids[pids.len() - 1];
+            let id = self.jobs.add(pids, command, None);
+            println!("[{}] {}", id, last);
+            0
+        } else {
+            let id = self.jobs.add(pids, command, Some(writer));
+            self.jobs.wait(id)
+        }
     }
 }
 
+/// Command names starting with `prefix`.
+fn complete(prefix: &str) -> Vec<&'static str> {
+    let mut names = super::CMD_LIST.names();
+    names.extend(BUILTINS);
+    names.retain(|name| name.starts_with(prefix));
+    names.sort_unstable();
+    names
+}
+
 pub fn shell() {
-    crate::print!("shell\n> ");
+    // Drop whatever was typed while booting.
+    console().clear_rx();
+    crate::print!("shell\n");
 
-    let mut buffer: String = String::with_capacity(65536);
+    let mut shell = Shell::new();
+    if ramfs::read(BOOT_SCRIPT).is_some() {
+        shell.source(BOOT_SCRIPT);
+    }
 
+    let mut editor = LineEditor::new();
+    // Lines of an `if` or `for` still being typed.
+    let mut pending = String::new();
     loop {
-        let c = console().read_char();
-        console().write_char(c);
-
-        if c == '\n' || c == '\r' {
-            parse_command(buffer.as_str());
-            buffer.clear();
-            crate::print!("> ");
-        } else {
-            buffer.push(c);
+        let prompt = if pending.is_empty() { "> " } else { ">> " };
+        match editor.read_line(prompt, complete) {
+            Some(line) => {
+                pending.push_str(&line);
+                pending.push('\n');
+                match script::parse(&pending) {
+                    Err(script::Error::Incomplete) => {}
+                    parsed => {
+                        shell.run_parsed(parsed);
+                        pending.clear();
+                    }
+                }
+            }
+            None => pending.clear(),
         }
+        shell.jobs.reap(true);
     }
 }
@@ -0,0 +1,239 @@
+//! Line editor for the shell: cursor movement, history and tab completion over the handful of
+//! ANSI sequences terminals send for the arrow, home, end and delete keys.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{console::console, print, println};
+
+const MAX_HISTORY: usize = 32;
+
+const CTRL_A: char = '\x01';
+const CTRL_C: char = '\x03';
+const CTRL_E: char = '\x05';
+const CTRL_L: char = '\x0c';
+const CTRL_U: char = '\x15';
+const ESC: char = '\x1b';
+const DEL: char = '\x7f';
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Cancel,
+    Clear,
+    KillLine,
+    /// Anything else, including sequences we don't know.
+    Ignored,
+}
+
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// The history entry shown, `None` while editing a new line.
+    history_pos: Option<usize>,
+    /// The new line, kept while browsing the history.
+    draft: Vec<char>,
+    /// Terminals end lines with CR, LF or both, the LF of a CRLF is swallowed.
+    after_cr: bool,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            history_pos: None,
+            draft: Vec::new(),
+            after_cr: false,
+        }
+    }
+
+    /// Reads a line, `None` if it was cancelled with Ctrl-C. `complete` lists the words a prefix
+    /// can be completed to.
+    pub fn read_line(&mut self, prompt: &str, complete: impl Fn(&str) -> Vec<&'static str>) -> Option<String> {
+        self.line.clear();
+        self.cursor = 0;
+        self.history_pos = None;
+        print!("{}", prompt);
+
+        loop {
+            match self.read_key() {
+                // Typing at the end of the line only needs the echo, not a redraw.
+                Key::Char(c) if self.cursor == self.line.len() => {
+                    self.line.push(c);
+                    self.cursor += 1;
+                    console().write_char(c);
+                    continue;
+                }
+                Key::Char(c) => {
+                    self.line.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+                Key::Enter => {
+                    println!();
+                    let line: String = self.line.iter().collect();
+                    self.add_history(&line);
+                    return Some(line);
+                }
+                Key::Backspace if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+                Key::Delete if self.cursor < self.line.len() => {
+                    self.line.remove(self.cursor);
+                }
+                Key::Tab => self.complete(&complete),
+                Key::Left => self.cursor = self.cursor.saturating_sub(1),
+                Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+                Key::Up => self.browse_history(true),
+                Key::Down => self.browse_history(false),
+                Key::Home => self.cursor = 0,
+                Key::End => self.cursor = self.line.len(),
+                Key::Cancel => {
+                    println!("^C");
+                    return None;
+                }
+                Key::Clear => print!("\x1b[2J\x1b[H"),
+                Key::KillLine => {
+                    self.line.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                _ => continue,
+            }
+            self.refresh(prompt);
+        }
+    }
+
+    fn read_key(&mut self) -> Key {
+        let c = console().read_char();
+        let after_cr = core::mem::replace(&mut self.after_cr, c == '\r');
+
+        match c {
+            '\r' => Key::Enter,
+            '\n' if after_cr => Key::Ignored,
+            '\n' => Key::Enter,
+            '\x08' | DEL => Key::Backspace,
+            '\t' => Key::Tab,
+            CTRL_A => Key::Home,
+            CTRL_C => Key::Cancel,
+            CTRL_E => Key::End,
+            CTRL_L => Key::Clear,
+            CTRL_U => Key::KillLine,
+            ESC => self.read_escape(),
+            c if c.is_control() => Key::Ignored,
+            c => Key::Char(c),
+        }
+    }
+
+    /// The rest of `ESC [ ...` or `ESC O ...`, as sent by the cursor and editing keys.
+    fn read_escape(&self) -> Key {
+        if !matches!(console().read_char(), '[' | 'O') {
+            return Key::Ignored;
+        }
+
+        let mut param: u32 = 0;
+        loop {
+            match console().read_char() {
+                c @ '0'..='9' => param = param.saturating_mul(10).saturating_add(c as u32 - '0' as u32),
+                'A' => return Key::Up,
+                'B' => return Key::Down,
+                'C' => return Key::Right,
+                'D' => return Key::Left,
+                'H' => return Key::Home,
+                'F' => return Key::End,
+                '~' => {
+                    return match param {
+                        1 | 7 => Key::Home,
+                        3 => Key::Delete,
+                        4 | 8 => Key::End,
+                        _ => Key::Ignored,
+                    }
+                }
+                ';' => {}
+                _ => return Key::Ignored,
+            }
+        }
+    }
+
+    /// Redraws the line and puts the cursor back where it belongs.
+    fn refresh(&self, prompt: &str) {
+        let line: String = self.line.iter().collect();
+        print!("\r{}{}\x1b[K", prompt, line);
+        let back = self.line.len() - self.cursor;
+        if back > 0 {
+            print!("\x1b[{}D", back);
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        if self.history.len() == MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(line.into());
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        let pos = match (self.history_pos, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.draft = core::mem::take(&mut self.line);
+                Some(self.history.len() - 1)
+            }
+            (Some(pos), true) => Some(pos.saturating_sub(1)),
+            (Some(pos), false) if pos + 1 < self.history.len() => Some(pos + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+
+        self.line = match pos {
+            Some(pos) => self.history[pos].chars().collect(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.history_pos = pos;
+        self.cursor = self.line.len();
+    }
+
+    /// Completes the command name under the cursor, or lists the candidates if that is ambiguous.
+    fn complete(&mut self, complete: &impl Fn(&str) -> Vec<&'static str>) {
+        if self.line[..self.cursor].contains(&' ') {
+            return;
+        }
+        let prefix: String = self.line[..self.cursor].iter().collect();
+        let candidates = complete(&prefix);
+        let Some(first) = candidates.first() else {
+            return;
+        };
+
+        // The longest prefix all candidates share.
+        let common = candidates.iter().fold(first.chars().count(), |len, c| {
+            first.chars().zip(c.chars()).take_while(|(a, b)| a == b).count().min(len)
+        });
+        let mut insert: Vec<char> = first.chars().take(common).skip(self.cursor).collect();
+        if candidates.len() == 1 {
+            insert.push(' ');
+        } else if insert.is_empty() {
+            println!();
+            for candidate in &candidates {
+                print!("{}  ", candidate);
+            }
+            println!();
+        }
+
+        for c in insert {
+            self.line.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+}
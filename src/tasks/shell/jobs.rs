@@ -0,0 +1,166 @@
+//! Job control: the shell waits for foreground jobs, background ones run alongside it. A job
+//! is the tasks of a pipeline. Ctrl-C asks the foreground job to finish, Ctrl-Z stops it, and
+//! anything else typed goes to its stdin, with Ctrl-D ending it.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::console;
+use crate::println;
+use crate::scheduler::{EXIT_WAITERS, PTABLE};
+use crate::stdio::PipeWriter;
+
+const CTRL_C: char = '\x03';
+const CTRL_D: char = '\x04';
+const CTRL_Z: char = '\x1a';
+
+/// Status of a job stopped with Ctrl-Z, as in `sh`.
+const EXIT_STOPPED: i32 = 148;
+
+struct Job {
+    id: usize,
+    pids: Vec<usize>,
+    command: String,
+    stopped: bool,
+    /// The first task's stdin, while the job is in the foreground.
+    input: Option<PipeWriter>,
+}
+
+impl Job {
+    fn is_alive(&self) -> bool {
+        self.pids.iter().any(|&pid| PTABLE.is_alive(pid))
+    }
+}
+
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    pub const fn new() -> Self {
+        Self { jobs: Vec::new() }
+    }
+
+    /// Adds the tasks started from `command` and returns the job number.
+    pub fn add(&mut self, pids: Vec<usize>, command: &str, input: Option<PipeWriter>) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job {
+            id,
+            pids,
+            command: command.into(),
+            stopped: false,
+            input,
+        });
+        id
+    }
+
+    /// Waits for a job in the foreground, until it exits or is stopped with Ctrl-Z. Returns
+    /// the exit code of its last task.
+    pub fn wait(&mut self, id: usize) -> i32 {
+        let Some(pids) = self.find(id).map(|job| job.pids.clone()) else {
+            return 0;
+        };
+        let alive = || pids.iter().any(|&pid| PTABLE.is_alive(pid));
+
+        while let Some(c) = console::read_char_until(&EXIT_WAITERS, || !alive()) {
+            let Some(job) = self.find(id) else {
+                return 0;
+            };
+            match c {
+                CTRL_C => {
+                    println!("^C");
+                    for &pid in &job.pids {
+                        PTABLE.interrupt(pid);
+                    }
+                    job.input = None;
+                }
+                CTRL_Z => {
+                    println!("^Z");
+                    for &pid in &job.pids {
+                        PTABLE.stop(pid);
+                    }
+                    job.stopped = true;
+                    println!("[{}]+ Stopped  {}", job.id, job.command);
+                    return EXIT_STOPPED;
+                }
+                CTRL_D => job.input = None,
+                c => {
+                    let c = if c == '\r' { '\n' } else { c };
+                    console::console().write_char(c);
+                    if let Some(input) = &job.input {
+                        let mut buf = [0; 4];
+                        input.write(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                }
+            }
+        }
+        self.jobs.retain(|job| job.id != id);
+
+        let codes: Vec<i32> = pids.iter().map(|&pid| PTABLE.take_exit_code(pid).unwrap_or(0)).collect();
+        codes.last().copied().unwrap_or(0)
+    }
+
+    /// `fg [job]`: resumes a job, the most recent one by default, and waits for it.
+    pub fn fg(&mut self, id: Option<&str>) -> i32 {
+        match self.resume(id) {
+            Some(id) => self.wait(id),
+            None => 1,
+        }
+    }
+
+    /// `bg [job]`: lets a stopped job carry on in the background.
+    pub fn bg(&mut self, id: Option<&str>) -> i32 {
+        match self.resume(id) {
+            Some(_) => 0,
+            None => 1,
+        }
+    }
+
+    /// `jobs`: lists the jobs, and forgets the ones that have finished.
+    pub fn list(&mut self) {
+        self.reap(true);
+        for job in &self.jobs {
+            let state = if job.stopped { "Stopped" } else { "Running" };
+            println!("[{}]  {:<8} {}", job.id, state, job.command);
+        }
+    }
+
+    /// Forgets jobs that have finished, telling the user if `verbose`.
+    pub fn reap(&mut self, verbose: bool) {
+        self.jobs.retain(|job| {
+            if job.is_alive() {
+                return true;
+            }
+            for &pid in &job.pids {
+                PTABLE.take_exit_code(pid);
+            }
+            if verbose {
+                println!("[{}]  Done     {}", job.id, job.command);
+            }
+            false
+        });
+    }
+
+    fn find(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    fn resume(&mut self, id: Option<&str>) -> Option<usize> {
+        self.reap(false);
+        let job = match id.map(|id| id.trim_start_matches('%').parse()) {
+            None => self.jobs.last_mut(),
+            Some(Ok(id)) => self.find(id),
+            Some(Err(_)) => None,
+        };
+        let Some(job) = job else {
+            println!("no such job");
+            return None;
+        };
+
+        job.stopped = false;
+        for &pid in &job.pids {
+            PTABLE.resume(pid);
+        }
+        println!("[{}]  {}", job.id, job.command);
+        Some(job.id)
+    }
+}
@@ -0,0 +1,429 @@
+//! The shell language: commands chained with `;`, `&&` and `||` or on separate lines,
+//! `if cond; then ...; elif cond; then ...; else ...; fi`, `for x in words; do ...; done`,
+//! `NAME=value` and `$NAME`, `${NAME}` and `$?` expansion, and `'...'`, `"..."` and `\`
+//! quoting. `#` starts a comment.
+//!
+//! Commands are kept as text and split into words and expanded just before they run, so `$?`
+//! and loop variables see the values of the moment.
+
+use alloc::{format, string::String, vec::Vec};
+use core::iter::Peekable;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Keyword {
+    If,
+    Then,
+    Elif,
+    Else,
+    Fi,
+    Do,
+    Done,
+}
+
+impl Keyword {
+    fn from_word(word: &str) -> Option<Self> {
+        Some(match word {
+            "if" => Self::If,
+            "then" => Self::Then,
+            "elif" => Self::Elif,
+            "else" => Self::Else,
+            "fi" => Self::Fi,
+            "do" => Self::Do,
+            "done" => Self::Done,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::If => "if",
+            Self::Then => "then",
+            Self::Elif => "elif",
+            Self::Else => "else",
+            Self::Fi => "fi",
+            Self::Do => "do",
+            Self::Done => "done",
+        }
+    }
+}
+
+enum Token {
+    Keyword(Keyword),
+    /// `for <name> in <words>`.
+    For(String, String),
+    /// A command line as typed, pipes and redirection included.
+    Command(String),
+    /// `;` or a line break.
+    Separator,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    And,
+    Or,
+}
+
+pub enum Command {
+    Simple(String),
+    /// Conditions and their bodies, and the `else` body.
+    If(Vec<(Block, Block)>, Option<Block>),
+    /// Variable, words and body.
+    For(String, String, Block),
+}
+
+/// Commands joined by `&&` and `||`, each one run depending on the status of the one before.
+pub struct AndOr {
+    pub first: Command,
+    pub rest: Vec<(Op, Command)>,
+}
+
+pub type Block = Vec<AndOr>;
+
+pub enum Error {
+    /// The input stops inside an `if` or `for`, or after `&&`, more lines may complete it.
+    Incomplete,
+    Syntax(String),
+}
+
+/// Splits a segment between separators into keywords and the command that follows them.
+fn push_segment(tokens: &mut Vec<Token>, segment: &str) -> Result<(), Error> {
+    let mut rest = segment.trim();
+    while !rest.is_empty() {
+        let (word, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if word == "for" {
+            // The words are split later, and may be quoted.
+            let tail = tail.trim_start();
+            let (var, tail) = tail.split_once(char::is_whitespace).unwrap_or((tail, ""));
+            let words = tail.trim_start().strip_prefix("in");
+            let words = words.filter(|w| w.is_empty() || w.starts_with(char::is_whitespace));
+            let Some(words) = words.filter(|_| is_name(var)) else {
+                return Err(Error::Syntax("expected `for <name> in <words>`".into()));
+            };
+            tokens.push(Token::For(var.into(), words.trim().into()));
+            return Ok(());
+        }
+        match Keyword::from_word(word) {
+            Some(keyword) => tokens.push(Token::Keyword(keyword)),
+            None => {
+                tokens.push(Token::Command(rest.into()));
+                return Ok(());
+            }
+        }
+        rest = tail.trim_start();
+    }
+    Ok(())
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut segment = String::new();
+    let mut chars = text.chars().peekable();
+    // Quoted text is left for `split_words`, separators and all.
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            segment.push(c);
+            if c == q {
+                quote = None;
+            } else if c == '\\' && q == '"' {
+                segment.extend(chars.next());
+            }
+            continue;
+        }
+        let separator = match c {
+            '\'' | '"' => {
+                quote = Some(c);
+                segment.push(c);
+                continue;
+            }
+            '\\' => {
+                segment.push(c);
+                segment.extend(chars.next());
+                continue;
+            }
+            '\n' | ';' => Token::Separator,
+            '&' if chars.peek() == Some(&'&') => Token::And,
+            '|' if chars.peek() == Some(&'|') => Token::Or,
+            '#' if segment.is_empty() || segment.ends_with(char::is_whitespace) => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            c => {
+                segment.push(c);
+                continue;
+            }
+        };
+        if matches!(separator, Token::And | Token::Or) {
+            chars.next();
+        }
+        push_segment(&mut tokens, &segment)?;
+        segment.clear();
+        tokens.push(separator);
+    }
+    if quote.is_some() {
+        // The quote may go on over the next lines.
+        return Err(Error::Incomplete);
+    }
+    push_segment(&mut tokens, &segment)?;
+    Ok(tokens)
+}
+
+struct Parser<I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+}
+
+impl<I: Iterator<Item = Token>> Parser<I> {
+    fn skip_separators(&mut self) {
+        while self.tokens.next_if(|t| matches!(t, Token::Separator)).is_some() {}
+    }
+
+    /// Commands up to one of the keywords in `end`, which is left for the caller.
+    fn block(&mut self, end: &[Keyword]) -> Result<Block, Error> {
+        let mut block = Vec::new();
+        loop {
+            self.skip_separators();
+            match self.tokens.peek() {
+                None if end.is_empty() => return Ok(block),
+                None => return Err(Error::Incomplete),
+                Some(Token::Keyword(k)) if end.contains(k) => return Ok(block),
+                Some(_) => block.push(self.and_or()?),
+            }
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, Error> {
+        let first = self.command()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.tokens.peek() {
+                Some(Token::And) => Op::And,
+                Some(Token::Or) => Op::Or,
+                _ => return Ok(AndOr { first, rest }),
+            };
+            self.tokens.next();
+            // The next command may be on the next line.
+            self.skip_separators();
+            rest.push((op, self.command()?));
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, Error> {
+        match self.tokens.next() {
+            None => Err(Error::Incomplete),
+            Some(Token::Command(text)) => Ok(Command::Simple(text)),
+            Some(Token::Keyword(Keyword::If)) => self.if_rest(),
+            Some(Token::For(var, words)) => {
+                self.skip_separators();
+                self.expect(Keyword::Do)?;
+                let body = self.block(&[Keyword::Done])?;
+                self.expect(Keyword::Done)?;
+                Ok(Command::For(var, words, body))
+            }
+            Some(Token::Keyword(k)) => Err(Error::Syntax(format!("unexpected `{}`", k.name()))),
+            Some(Token::And) => Err(Error::Syntax("unexpected `&&`".into())),
+            Some(Token::Or) => Err(Error::Syntax("unexpected `||`".into())),
+            Some(Token::Separator) => Err(Error::Syntax("unexpected `;`".into())),
+        }
+    }
+
+    /// Whatever follows `if`, up to and including `fi`.
+    fn if_rest(&mut self) -> Result<Command, Error> {
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.block(&[Keyword::Then])?;
+            self.expect(Keyword::Then)?;
+            let body = self.block(&[Keyword::Elif, Keyword::Else, Keyword::Fi])?;
+            branches.push((condition, body));
+
+            match self.tokens.next() {
+                Some(Token::Keyword(Keyword::Elif)) => continue,
+                Some(Token::Keyword(Keyword::Else)) => {
+                    let otherwise = self.block(&[Keyword::Fi])?;
+                    self.expect(Keyword::Fi)?;
+                    return Ok(Command::If(branches, Some(otherwise)));
+                }
+                _ => return Ok(Command::If(branches, None)),
+            }
+        }
+    }
+
+    fn expect(&mut self, keyword: Keyword) -> Result<(), Error> {
+        match self.tokens.next() {
+            Some(Token::Keyword(k)) if k == keyword => Ok(()),
+            None => Err(Error::Incomplete),
+            Some(_) => Err(Error::Syntax(format!("expected `{}`", keyword.name()))),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Block, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?.into_iter().peekable(),
+    };
+    parser.block(&[])
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Whether `name` can be a variable name.
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(is_name_char)
+}
+
+/// The value of the variable at the start of `after`, the text following a `$`, and what comes
+/// after its name, or `None` if no variable name follows the `$`. Unset variables are empty.
+fn variable<'a, 'b>(
+    after: &'b str,
+    status: i32,
+    lookup: &impl Fn(&str) -> Option<&'a str>,
+) -> Option<(String, &'b str)> {
+    if let Some(tail) = after.strip_prefix('?') {
+        return Some((format!("{}", status), tail));
+    }
+    let (name, tail) = match after.strip_prefix('{').and_then(|a| a.split_once('}')) {
+        Some(braced) => braced,
+        None => after.split_at(after.find(|c| !is_name_char(c)).unwrap_or(after.len())),
+    };
+    is_name(name).then(|| (lookup(name).unwrap_or("").into(), tail))
+}
+
+/// A piece of a command line.
+pub enum Word {
+    Text(String),
+    /// `|`.
+    Pipe,
+    /// `>`, or `>>` to append.
+    Redirect { append: bool },
+    /// `&`.
+    Background,
+}
+
+/// Splits a command line into words and the operators between them, removing quotes and
+/// expanding `$NAME`, `${NAME}` and `$?` as it goes. Values are never split again, so a `|`
+/// or `>` in one stays part of its word. `'...'` keeps its text as it is, `"..."` still
+/// expands variables, and `\` quotes the character after it.
+pub fn split_words<'a>(
+    text: &str,
+    status: i32,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    // The word being read, if one has started: `''` is an empty word, not none.
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let operator = match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                continue;
+            }
+            (Some('\''), c) => {
+                word.get_or_insert_with(String::new).push(c);
+                continue;
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+                continue;
+            }
+            (_, '\\') if quote.is_none() || rest.starts_with(['"', '\\', '$']) => {
+                let word = word.get_or_insert_with(String::new);
+                if let Some(next) = rest.chars().next() {
+                    word.push(next);
+                    rest = &rest[next.len_utf8()..];
+                }
+                continue;
+            }
+            (_, '$') => {
+                match variable(rest, status, &lookup) {
+                    // An unquoted empty value alone isn't a word.
+                    Some((value, tail)) if value.is_empty() && quote.is_none() => rest = tail,
+                    Some((value, tail)) => {
+                        word.get_or_insert_with(String::new).push_str(&value);
+                        rest = tail;
+                    }
+                    None => word.get_or_insert_with(String::new).push('$'),
+                }
+                continue;
+            }
+            (None, '|') => Word::Pipe,
+            (None, '>') => match rest.strip_prefix('>') {
+                Some(tail) => {
+                    rest = tail;
+                    Word::Redirect { append: true }
+                }
+                None => Word::Redirect { append: false },
+            },
+            (None, '&') => Word::Background,
+            (None, c) if c.is_whitespace() => {
+                words.extend(word.take().map(Word::Text));
+                continue;
+            }
+            (_, c) => {
+                word.get_or_insert_with(String::new).push(c);
+                continue;
+            }
+        };
+        words.extend(word.take().map(Word::Text));
+        words.push(operator);
+    }
+
+    if quote.is_some() {
+        return Err("unterminated quote".into());
+    }
+    words.extend(word.take().map(Word::Text));
+    Ok(words)
+}
+
+/// `cmd args | cmd args ... [> file | >> file] [&]`.
+#[derive(Default)]
+pub struct Pipeline {
+    /// Each command's name and arguments, none for an empty line.
+    pub commands: Vec<Vec<String>>,
+    /// The file the last command writes to, and whether it is appended to.
+    pub redirect: Option<(String, bool)>,
+    pub background: bool,
+}
+
+impl Pipeline {
+    pub fn parse(words: Vec<Word>) -> Result<Self, String> {
+        let mut pipeline = Self::default();
+        if words.is_empty() {
+            return Ok(pipeline);
+        }
+
+        let mut command = Vec::new();
+        let mut words = words.into_iter();
+        while let Some(word) = words.next() {
+            if pipeline.background {
+                return Err("`&` must end the command".into());
+            }
+            match word {
+                Word::Text(_) | Word::Pipe | Word::Redirect { .. } if pipeline.redirect.is_some() => {
+                    return Err("`>` and its file must end the command".into());
+                }
+                Word::Text(text) => command.push(text),
+                Word::Pipe if command.is_empty() => return Err("missing command before `|`".into()),
+                Word::Pipe => pipeline.commands.push(core::mem::take(&mut command)),
+                Word::Redirect { append } => match words.next() {
+                    Some(Word::Text(file)) => pipeline.redirect = Some((file, append)),
+                    _ => return Err("missing file name after `>`".into()),
+                },
+                Word::Background => pipeline.background = true,
+            }
+        }
+        if command.is_empty() {
+            return Err("missing command".into());
+        }
+        pipeline.commands.push(command);
+        Ok(pipeline)
+    }
+}
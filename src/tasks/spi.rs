@@ -0,0 +1,174 @@
+use alloc::vec::Vec;
+
+use crate::bsp::{
+    self,
+    device_driver::{AuxSpi, ClockId, Spi0TransferMode, SpiBus, SpiMode, SpiSettings},
+};
+use crate::{print, println};
+
+pub const USAGE: &str = "usage: spi [spi0|spi1|spi2 [config <hz> [mode] [cs]|xfer <hex bytes>|loopback]]\n       \
+                     spi spi0 [mode polled|irq|dma|cspol <cs> high|low]\n       \
+                     spi spi1|spi2 [on|off|words <bits> <hex words>]";
+
+/// Bytes sent by the loopback test, enough to overflow the FIFOs a few times.
+const LOOPBACK_LEN: usize = 300;
+
+/// Shows the SPI masters' settings, configures one or runs a transfer on it.
+pub fn spi() {
+    let args = super::parsed_args();
+
+    let Some(name) = args.str("bus") else {
+        print_spi0();
+        for (name, bus) in aux_buses() {
+            let state = if bus.is_enabled() { "on" } else { "off" };
+            println!("{}: {:<3} {}", name, state, bus.settings());
+        }
+        return;
+    };
+    let rest = args.rest();
+
+    let result = match aux_buses().into_iter().find(|(n, _)| *n == name) {
+        Some((_, bus)) => aux_spi(bus, &rest),
+        None => spi0(&rest),
+    };
+    if let Err(x) = result {
+        println!("spi: {}", x);
+    }
+}
+
+fn aux_buses() -> [(&'static str, &'static AuxSpi); 2] {
+    [("spi1", &bsp::SPI1), ("spi2", &bsp::SPI2)]
+}
+
+fn print_spi0() {
+    let spi0 = &bsp::SPI0;
+    print!("spi0: {:<6} {} cspol", spi0.transfer_mode().name(), spi0.settings());
+    for cs in 0..3 {
+        print!(" {}", if spi0.cs_active_high(cs) { "high" } else { "low" });
+    }
+    println!();
+}
+
+fn spi0(args: &[&str]) -> Result<(), &'static str> {
+    let spi0 = &bsp::SPI0;
+
+    match args {
+        ["config", config @ ..] => {
+            let settings = parse_settings(spi0.settings(), config)?;
+            spi0.configure(&settings, bsp::MAILBOX.clock_rate(ClockId::Core)?)?;
+            println!("{}", spi0.settings());
+            Ok(())
+        }
+        ["mode", mode] => {
+            let mode = Spi0TransferMode::from_name(mode).ok_or(USAGE)?;
+            spi0.set_transfer_mode(mode)
+        }
+        ["cspol", cs, polarity @ ("high" | "low")] => {
+            let cs = cs.parse().map_err(|_| USAGE)?;
+            spi0.set_cs_active_high(cs, *polarity == "high")
+        }
+        ["xfer", bytes @ ..] => transfer(spi0, bytes),
+        ["loopback"] => {
+            // Every transfer mode, coming back to the one that was selected.
+            let original = spi0.transfer_mode();
+            let mut failed = false;
+            for mode in [Spi0TransferMode::Polled, Spi0TransferMode::Interrupt, Spi0TransferMode::Dma] {
+                print!("{:<6} ", mode.name());
+                if let Err(x) = spi0.set_transfer_mode(mode).and_then(|_| loopback(spi0)) {
+                    println!("{}", x);
+                    failed = true;
+                }
+            }
+            spi0.set_transfer_mode(original)?;
+            if failed {
+                Err("loopback failed")
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err(USAGE),
+    }
+}
+
+fn aux_spi(bus: &AuxSpi, args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [state @ ("on" | "off")] => {
+            bus.set_enabled(*state == "on");
+            Ok(())
+        }
+        ["config", config @ ..] => {
+            let settings = parse_settings(bus.settings(), config)?;
+            // The auxiliary masters divide down the VPU core clock.
+            bus.configure(&settings, bsp::MAILBOX.clock_rate(ClockId::Core)?)?;
+            println!("{}", bus.settings());
+            Ok(())
+        }
+        ["xfer", bytes @ ..] => transfer(bus, bytes),
+        ["words", bits, words @ ..] => transfer_words(bus, bits, words),
+        ["loopback"] => loopback(bus),
+        _ => Err(USAGE),
+    }
+}
+
+fn parse_settings(mut settings: SpiSettings, args: &[&str]) -> Result<SpiSettings, &'static str> {
+    let mut args = args.iter();
+
+    settings.speed_hz = args.next().and_then(|s| s.parse().ok()).ok_or(USAGE)?;
+    if let Some(mode) = args.next() {
+        settings.mode = mode.parse().ok().and_then(SpiMode::from_number).ok_or("mode must be 0 to 3")?;
+    }
+    if let Some(cs) = args.next() {
+        settings.chip_select = cs.parse().map_err(|_| USAGE)?;
+    }
+    Ok(settings)
+}
+
+fn transfer(bus: &dyn SpiBus, args: &[&str]) -> Result<(), &'static str> {
+    let tx = args
+        .iter()
+        .map(|s| u8::from_str_radix(s, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "bytes must be hex, e.g. 9f 00 00")?;
+    let mut rx = alloc::vec![0; tx.len()];
+
+    bus.transfer(&tx, &mut rx)?;
+    for b in rx {
+        print!("{:02x} ", b);
+    }
+    println!();
+    Ok(())
+}
+
+/// Self-test with MOSI wired to MISO: whatever goes out has to come straight back.
+fn loopback(bus: &dyn SpiBus) -> Result<(), &'static str> {
+    let tx: Vec<u8> = (0..LOOPBACK_LEN).map(|i| (i * 7 + 3) as u8).collect();
+    let mut rx = alloc::vec![0; tx.len()];
+
+    bus.transfer(&tx, &mut rx)?;
+    match tx.iter().zip(&rx).position(|(t, r)| t != r) {
+        None => {
+            println!("ok, {} bytes", tx.len());
+            Ok(())
+        }
+        Some(i) => {
+            print!("byte {}: sent {:02x}, received {:02x}, ", i, tx[i], rx[i]);
+            Err("is MOSI connected to MISO?")
+        }
+    }
+}
+
+fn transfer_words(bus: &AuxSpi, bits: &str, args: &[&str]) -> Result<(), &'static str> {
+    let bits = bits.parse().map_err(|_| USAGE)?;
+    let mut words = args
+        .iter()
+        .map(|s| u32::from_str_radix(s, 16))
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| "words must be hex")?;
+
+    bus.transfer_words(&mut words, bits)?;
+    for word in words {
+        print!("{:x} ", word);
+    }
+    println!();
+    Ok(())
+}
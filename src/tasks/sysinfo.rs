@@ -0,0 +1,98 @@
+use crate::bsp::{
+    self,
+    device_driver::{ClockId, MemoryRegion, PowerDevice},
+};
+use crate::println;
+
+pub const USAGE: &str = "usage: sysinfo [board|memory|clocks|power [<device> on|off]]";
+
+/// Shows what the firmware reports about the board, or just one part of it.
+pub fn sysinfo() {
+    let args = super::parsed_args();
+
+    let result = match (args.str("part"), args.str("device"), args.str("state")) {
+        (None, ..) => board().and_then(|_| memory()).and_then(|_| clocks()).and_then(|_| power()),
+        (Some("board"), None, _) => board(),
+        (Some("memory"), None, _) => memory(),
+        (Some("clocks"), None, _) => clocks(),
+        (Some("power"), None, _) => power(),
+        (Some("power"), Some(device), Some(state)) => set_power(device, state == "on"),
+        _ => Err(USAGE),
+    };
+    if let Err(x) = result {
+        println!("sysinfo: {}", x);
+    }
+}
+
+fn board() -> Result<(), &'static str> {
+    let mailbox = &bsp::MAILBOX;
+
+    println!("board:       {}", mailbox.board_revision()?);
+    println!("model:       {:#x}", mailbox.board_model()?);
+    println!("serial:      {:016x}", mailbox.board_serial()?);
+    println!("firmware:    {:#x}", mailbox.firmware_revision()?);
+
+    let mac = mailbox.mac_address()?;
+    println!(
+        "MAC:         {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+
+    let temp = mailbox.temperature()?;
+    let max = mailbox.max_temperature()?;
+    println!(
+        "temperature: {}.{} C, throttling at {}.{} C",
+        temp / 1000,
+        temp % 1000 / 100,
+        max / 1000,
+        max % 1000 / 100
+    );
+    Ok(())
+}
+
+fn memory() -> Result<(), &'static str> {
+    let print_region = |name: &str, region: MemoryRegion| {
+        println!(
+            "{:<12} {:#010x} - {:#010x} ({} MB)",
+            name,
+            region.base,
+            region.base as u64 + region.size as u64,
+            region.size >> 20
+        );
+    };
+
+    print_region("ARM memory:", bsp::MAILBOX.arm_memory()?);
+    print_region("VC memory:", bsp::MAILBOX.vc_memory()?);
+    Ok(())
+}
+
+fn clocks() -> Result<(), &'static str> {
+    println!("clock        rate       max");
+    for clock in ClockId::ALL {
+        // Not every firmware knows every clock.
+        let Ok(rate) = bsp::MAILBOX.clock_rate(clock) else {
+            continue;
+        };
+        let max = bsp::MAILBOX.max_clock_rate(clock).unwrap_or(rate);
+        println!("  {:<8} {:>6} MHz {:>6} MHz", clock.name(), rate / 1_000_000, max / 1_000_000);
+    }
+    Ok(())
+}
+
+fn power() -> Result<(), &'static str> {
+    println!("power:");
+    for device in PowerDevice::ALL {
+        match bsp::MAILBOX.power_state(device) {
+            Ok(on) => println!("  {:<8} {}", device.name(), if on { "on" } else { "off" }),
+            Err(_) => println!("  {:<8} -", device.name()),
+        }
+    }
+    Ok(())
+}
+
+fn set_power(device: &str, on: bool) -> Result<(), &'static str> {
+    let device = PowerDevice::from_name(device).ok_or("unknown device")?;
+    let on = bsp::MAILBOX.set_power_state(device, on)?;
+    println!("{}: {}", device.name(), if on { "on" } else { "off" });
+    Ok(())
+}
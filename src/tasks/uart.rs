@@ -0,0 +1,59 @@
+use crate::bsp::{
+    self,
+    device_driver::{ClockId, LineSettings},
+};
+use crate::println;
+
+pub const USAGE: &str = "usage: uart [mini|pl011 <baud> [format, e.g. 8N1] [rtscts]]";
+
+/// Shows or changes the line settings of the UARTs.
+pub fn uart() {
+    let args = super::parsed_args();
+
+    let Some(port) = args.str("port") else {
+        println!("mini:  {}", bsp::MINI_UART.line_settings());
+        println!("pl011: {}", bsp::PL011_UART.line_settings());
+        return;
+    };
+
+    let mut settings = match port {
+        "mini" => bsp::MINI_UART.line_settings(),
+        _ => bsp::PL011_UART.line_settings(),
+    };
+
+    match args.int("baud").map(u32::try_from) {
+        Some(Ok(baud)) => settings.baud = baud,
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    }
+    if let Some(format) = args.str("format") {
+        if let Err(x) = settings.parse_format(format) {
+            println!("uart: {}", x);
+            return;
+        }
+    }
+    settings.flow_control = args.str("flow control").is_some();
+
+    match configure(port, &settings) {
+        Ok(()) => println!("{}: {}", port, settings),
+        Err(x) => println!("uart: {}", x),
+    }
+}
+
+fn configure(port: &str, settings: &LineSettings) -> Result<(), &'static str> {
+    if port == "mini" {
+        // The mini UART is clocked by the VPU core clock.
+        let core_clock = bsp::MAILBOX.clock_rate(ClockId::Core)?;
+        bsp::MINI_UART.configure(settings, core_clock)
+    } else {
+        let uart_clock = bsp::MAILBOX.clock_rate(ClockId::Uart)?;
+        bsp::PL011_UART.configure(settings, uart_clock)
+    }
+}
+
+pub fn uartstat() {
+    bsp::MINI_UART.print_stats();
+    bsp::PL011_UART.print_stats();
+}
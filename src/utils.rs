@@ -20,6 +20,90 @@ pub fn get_el() -> u8 {
     ((_el >> 2) & 0b11) as u8
 }
 
+/// Walks the frame record chain and returns up to `N` return addresses, starting `skip` frames
+/// above the caller. Unused entries are zero. Needs the kernel to be built with frame pointers.
+#[cfg(any(feature = "heap_tracking", feature = "heap_debug"))]
+#[inline(never)]
+pub fn return_addresses<const N: usize>(skip: usize) -> [usize; N] {
+    let mut addrs = [0; N];
+    let mut fp: usize;
+    unsafe {
+        core::arch::asm!("mov {}, x29", out(reg) fp)
+    }
+
+    let mut depth = 0;
+    let mut i = 0;
+    while fp != 0 && fp % 16 == 0 && i < N {
+        let (next_fp, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        if depth > skip {
+            addrs[i] = lr;
+            i += 1;
+        }
+        depth += 1;
+
+        // Frames only ever move towards the top of the (64 KiB) stack.
+        if next_fp <= fp || next_fp - fp > 65536 {
+            break;
+        }
+        fp = next_fp;
+    }
+    addrs
+}
+
+/// Fixed-capacity byte FIFO, used for the UART receive and transmit buffers.
+pub struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends `byte`, handing it back if the buffer is full.
+    pub fn push(&mut self, byte: u8) -> Result<(), u8> {
+        if self.is_full() {
+            return Err(byte);
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+}
+
 pub fn _sys_timer_sleep_ms(ms: u64) {
     let start_time = _sys_timer_get_ticks();
     while _sys_timer_get_ticks() < start_time + (ms * 1000) {}